@@ -0,0 +1,44 @@
+// Throughput of the thread pool with lots of tiny jobs, where the cost of handing jobs to
+// the workers dominates. Run with `cargo bench --bench thread_pool`.
+use std::time::{Duration, Instant};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use rust_web_server::thread_pool::thread_pool::ThreadPool;
+
+const JOBS: usize = 200_000;
+const RUNS: usize = 5;
+
+fn main() {
+    for size in [1, 4, 16] {
+        let mut timings: Vec<Duration> = (0..RUNS).map(|_| run(size)).collect();
+        timings.sort();
+        let median = timings[RUNS / 2];
+        println!(
+            "{:>2} workers: {} tiny jobs in {:?} (median of {}), {:.0} jobs/s",
+            size,
+            JOBS,
+            median,
+            RUNS,
+            JOBS as f64 / median.as_secs_f64()
+        );
+    }
+}
+
+// Queue up JOBS jobs that each just bump a counter and wait for them all to finish.
+fn run(size: usize) -> Duration {
+    let pool = ThreadPool::new(size).expect("Failed to create the pool");
+    let counter = Arc::new(AtomicUsize::new(0));
+
+    let start = Instant::now();
+    for _ in 0..JOBS {
+        let counter = Arc::clone(&counter);
+        pool.execute(move || {
+            counter.fetch_add(1, Ordering::Relaxed);
+        });
+    }
+    pool.join();
+    let elapsed = start.elapsed();
+
+    assert_eq!(counter.load(Ordering::SeqCst), JOBS);
+    elapsed
+}
@@ -0,0 +1,767 @@
+use std::env;
+use std::fmt;
+use std::thread;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use crate::http::method::Method;
+use crate::server::connection::{
+    DEFAULT_KEEP_ALIVE_MAX, DEFAULT_KEEP_ALIVE_TIMEOUT, DEFAULT_MAX_BODY_SIZE, DEFAULT_MAX_HEADER_SIZE, DEFAULT_TIMEOUT,
+};
+use crate::server::log::LogFormat;
+use crate::server::static_files::check_document_root;
+
+/// The port to listen on when `PORT` isn't set.
+pub const DEFAULT_PORT: u16 = 7878;
+
+/// The directory static files are served from when `DOCUMENT_ROOT` isn't set.
+pub const DEFAULT_DOCUMENT_ROOT: &str = "public";
+
+/// Everything the server can be configured with, read from the environment at startup.
+///
+/// | Variable | Default |
+/// | --- | --- |
+/// | `HOST` | `127.0.0.1` (or a list, e.g. `127.0.0.1,::1`) |
+/// | `PORT` | `7878` |
+/// | `WORKERS` | one per CPU |
+/// | `DOCUMENT_ROOT` | `public` |
+/// | `VIRTUAL_HOSTS`, e.g. `example.com=/srv/example,blog.example.com=/srv/blog` | `DOCUMENT_ROOT` for every host |
+/// | `READ_TIMEOUT_SECS`, `WRITE_TIMEOUT_SECS` | 30 seconds |
+/// | `MAX_CONNECTIONS` | no limit |
+/// | `KEEP_ALIVE_MAX` requests, `KEEP_ALIVE_TIMEOUT_SECS` | 100 requests, 5 seconds |
+/// | `MAX_BODY_SIZE` | 1 MiB |
+/// | `MAX_HEADER_SIZE` | 8 KiB |
+/// | `DIRECTORY_LISTING` | off |
+/// | `SPA_MODE` | off |
+/// | `FILE_CACHE_SIZE` | off |
+/// | `SERVER_HEADER` | on |
+/// | `TCP_NODELAY` | off |
+/// | `LOG_FORMAT`, `plain` or `json` | `plain` |
+/// | `RATE_LIMIT` requests a second, `RATE_LIMIT_BURST` | no limit, a burst of `RATE_LIMIT` |
+/// | `ALLOWED_METHODS`, e.g. `GET,HEAD` | every method |
+/// | `CORS_ALLOWED_ORIGINS` | none |
+/// | `MIME_TYPES`, e.g. `wasm=application/wasm,md=text/markdown` | the built-in types |
+/// | `RESPONSE_HEADERS`, e.g. `X-Frame-Options: DENY\|X-Content-Type-Options: nosniff` | none |
+/// | `TLS_CERT`, `TLS_KEY` | plain HTTP |
+/// | `ADMIN_USER`, `ADMIN_PASS` | no admin endpoints |
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Config {
+    // Every address in the list is listened on, all on the same port.
+    pub hosts: Vec<IpAddr>,
+    pub port: u16,
+    pub workers: usize,
+    // Checked to exist and canonicalized.
+    pub document_root: PathBuf,
+    // Hosts served from their own document root instead, each checked like the main one.
+    pub virtual_hosts: Vec<(String, PathBuf)>,
+    pub read_timeout: Duration,
+    pub write_timeout: Duration,
+    pub max_connections: usize,
+    // How many requests one connection can make, and how long it can sit idle in between.
+    pub keep_alive_max: usize,
+    pub keep_alive_timeout: Duration,
+    // The largest request body we'll accept, in bytes.
+    pub max_body_size: usize,
+    // The most bytes of request line and headers we'll accept.
+    pub max_header_size: usize,
+    pub directory_listing: bool,
+    // Whether unmatched routes get the index page, for single-page apps.
+    pub spa_mode: bool,
+    // How many static files to keep in memory, if any.
+    pub file_cache_size: Option<usize>,
+    // Whether to say what we are in the Server header.
+    pub server_header: bool,
+    // Whether to turn off Nagle's algorithm on every connection.
+    pub nodelay: bool,
+    pub log_format: LogFormat,
+    // How many requests a second each client can make, and how many in a burst.
+    pub rate_limit: Option<(u32, u32)>,
+    // The only methods we'll answer, if we've been told to lock it down.
+    pub allowed_methods: Option<Vec<Method>>,
+    pub cors_allowed_origins: Option<Vec<String>>,
+    // Content types for file extensions, on top of the built-in ones.
+    pub mime_types: Vec<(String, String)>,
+    // Extra headers to add to every response.
+    pub response_headers: Vec<(String, String)>,
+    // The certificate and key to serve HTTPS with, if any.
+    pub tls: Option<(PathBuf, PathBuf)>,
+    // The credentials for the admin endpoints, which are only there if these are set.
+    pub admin: Option<(String, String)>,
+}
+impl Config {
+    /// Read the configuration from the environment.
+    ///
+    /// Every variable is checked, and if any are invalid the error lists all of them
+    /// rather than just the first.
+    pub fn from_env() -> Result<Config, ConfigError> {
+        Config::from_vars(|name| env::var(name).ok())
+    }
+
+    /// Read the configuration from `var`, which looks up a variable by name the way
+    /// `env::var` would. This is what `from_env` uses, and lets tests supply their own.
+    pub fn from_vars<F>(var: F) -> Result<Config, ConfigError>
+    where
+        F: Fn(&str) -> Option<String>,
+    {
+        let mut problems = Vec::new();
+        let hosts = check(&mut problems, "HOST", parse_hosts(var("HOST").as_deref()));
+        let port = check(&mut problems, "PORT", parse_port(var("PORT").as_deref()));
+        let workers = check(&mut problems, "WORKERS", parse_workers(var("WORKERS").as_deref(), default_workers()));
+        let document_root = var("DOCUMENT_ROOT").unwrap_or(String::from(DEFAULT_DOCUMENT_ROOT));
+        let document_root = check(
+            &mut problems,
+            "DOCUMENT_ROOT",
+            check_document_root(Path::new(&document_root)).map_err(|err| err.to_string()),
+        );
+        let virtual_hosts = check(&mut problems, "VIRTUAL_HOSTS", parse_virtual_hosts(var("VIRTUAL_HOSTS").as_deref()));
+        let read_timeout = check(&mut problems, "READ_TIMEOUT_SECS", parse_timeout(var("READ_TIMEOUT_SECS").as_deref()));
+        let write_timeout = check(&mut problems, "WRITE_TIMEOUT_SECS", parse_timeout(var("WRITE_TIMEOUT_SECS").as_deref()));
+        let max_connections = check(&mut problems, "MAX_CONNECTIONS", parse_max_connections(var("MAX_CONNECTIONS").as_deref()));
+        let keep_alive_max = check(&mut problems, "KEEP_ALIVE_MAX", parse_keep_alive_max(var("KEEP_ALIVE_MAX").as_deref()));
+        let keep_alive_timeout = check(
+            &mut problems,
+            "KEEP_ALIVE_TIMEOUT_SECS",
+            parse_keep_alive_timeout(var("KEEP_ALIVE_TIMEOUT_SECS").as_deref()),
+        );
+        let max_body_size = check(&mut problems, "MAX_BODY_SIZE", parse_max_body_size(var("MAX_BODY_SIZE").as_deref()));
+        let max_header_size = check(&mut problems, "MAX_HEADER_SIZE", parse_max_header_size(var("MAX_HEADER_SIZE").as_deref()));
+        let log_format = check(&mut problems, "LOG_FORMAT", parse_log_format(var("LOG_FORMAT").as_deref()));
+        let rate_limit = check(
+            &mut problems,
+            "RATE_LIMIT",
+            parse_rate_limit(var("RATE_LIMIT").as_deref(), var("RATE_LIMIT_BURST").as_deref()),
+        );
+        let allowed_methods = check(&mut problems, "ALLOWED_METHODS", parse_allowed_methods(var("ALLOWED_METHODS").as_deref()));
+        let file_cache_size = check(&mut problems, "FILE_CACHE_SIZE", parse_file_cache_size(var("FILE_CACHE_SIZE").as_deref()));
+        let mime_types = check(&mut problems, "MIME_TYPES", parse_mime_types(var("MIME_TYPES").as_deref()));
+        let response_headers = check(&mut problems, "RESPONSE_HEADERS", parse_response_headers(var("RESPONSE_HEADERS").as_deref()));
+        let tls = check(&mut problems, "TLS_CERT/TLS_KEY", parse_tls(var("TLS_CERT"), var("TLS_KEY")));
+        let admin = check(&mut problems, "ADMIN_USER/ADMIN_PASS", parse_admin(var("ADMIN_USER"), var("ADMIN_PASS")));
+        let directory_listing = var("DIRECTORY_LISTING").is_some_and(|value| value == "1" || value.eq_ignore_ascii_case("true"));
+        let server_header = !var("SERVER_HEADER")
+            .is_some_and(|value| value == "0" || value.eq_ignore_ascii_case("false") || value.eq_ignore_ascii_case("off"));
+        let spa_mode = var("SPA_MODE").is_some_and(|value| value == "1" || value.eq_ignore_ascii_case("true"));
+        let nodelay = var("TCP_NODELAY").is_some_and(|value| value == "1" || value.eq_ignore_ascii_case("true"));
+        let cors_allowed_origins = var("CORS_ALLOWED_ORIGINS").map(|origins| {
+            origins
+                .split(',')
+                .map(str::trim)
+                .filter(|origin| !origin.is_empty())
+                .map(String::from)
+                .collect()
+        });
+
+        let (
+            Some(hosts),
+            Some(port),
+            Some(workers),
+            Some(document_root),
+            Some(virtual_hosts),
+            Some(read_timeout),
+            Some(write_timeout),
+            Some(max_connections),
+            Some(keep_alive_max),
+            Some(keep_alive_timeout),
+            Some(max_body_size),
+            Some(max_header_size),
+            Some(log_format),
+            Some(rate_limit),
+            Some(allowed_methods),
+            Some(file_cache_size),
+            Some(mime_types),
+            Some(response_headers),
+            Some(tls),
+            Some(admin),
+        ) = (
+            hosts,
+            port,
+            workers,
+            document_root,
+            virtual_hosts,
+            read_timeout,
+            write_timeout,
+            max_connections,
+            keep_alive_max,
+            keep_alive_timeout,
+            max_body_size,
+            max_header_size,
+            log_format,
+            rate_limit,
+            allowed_methods,
+            file_cache_size,
+            mime_types,
+            response_headers,
+            tls,
+            admin,
+        )
+        else {
+            return Err(ConfigError { problems });
+        };
+        Ok(Config {
+            hosts,
+            port,
+            workers,
+            document_root,
+            virtual_hosts,
+            read_timeout,
+            write_timeout,
+            max_connections,
+            keep_alive_max,
+            keep_alive_timeout,
+            max_body_size,
+            max_header_size,
+            directory_listing,
+            spa_mode,
+            file_cache_size,
+            server_header,
+            nodelay,
+            log_format,
+            rate_limit,
+            allowed_methods,
+            cors_allowed_origins,
+            mime_types,
+            response_headers,
+            tls,
+            admin,
+        })
+    }
+
+    /// The addresses to listen on, e.g. `127.0.0.1:7878` and `[::1]:7878`.
+    pub fn addresses(&self) -> Vec<SocketAddr> {
+        self.hosts.iter().map(|host| SocketAddr::new(*host, self.port)).collect()
+    }
+}
+
+// Keep the value if it's valid, otherwise note down what's wrong with the variable.
+fn check<T>(problems: &mut Vec<String>, name: &str, result: Result<T, String>) -> Option<T> {
+    result.map_err(|err| problems.push(format!("{}: {}", name, err))).ok()
+}
+
+// The host is a comma separated list of IP addresses, by default we only listen on localhost.
+fn parse_hosts(value: Option<&str>) -> Result<Vec<IpAddr>, String> {
+    let Some(value) = value else {
+        return Ok(vec![IpAddr::V4(Ipv4Addr::LOCALHOST)]);
+    };
+    let mut hosts = Vec::new();
+    for host in value.split(',').map(str::trim).filter(|host| !host.is_empty()) {
+        let host = host.parse().map_err(|err| format!("{:?} is not an IP address: {}", host, err))?;
+        // Listening on the same address twice would just fail to bind.
+        if !hosts.contains(&host) {
+            hosts.push(host);
+        }
+    }
+    if hosts.is_empty() {
+        return Err(String::from("At least one address is needed"));
+    }
+    Ok(hosts)
+}
+
+fn parse_port(value: Option<&str>) -> Result<u16, String> {
+    match value {
+        Some(value) => value.trim().parse().map_err(|err| format!("{:?} is not a valid port: {}", value, err)),
+        None => Ok(DEFAULT_PORT),
+    }
+}
+
+// One worker per CPU, or a single worker if we can't tell how many CPUs there are.
+fn default_workers() -> usize {
+    thread::available_parallelism().map_or(1, |workers| workers.get())
+}
+
+// Parse the number of workers, using the default when it's unset. There has to be at
+// least one worker or nothing would ever get served.
+fn parse_workers(value: Option<&str>, default: usize) -> Result<usize, String> {
+    let workers = match value {
+        Some(value) => value.trim().parse().map_err(|err| format!("{:?} is not a number: {}", value, err))?,
+        None => default,
+    };
+    if workers == 0 {
+        return Err(String::from("there must be at least one worker"));
+    }
+    Ok(workers)
+}
+
+// A timeout in whole seconds. Sockets can't be given a timeout of zero.
+fn parse_timeout(value: Option<&str>) -> Result<Duration, String> {
+    let timeout = match value {
+        Some(value) => value
+            .trim()
+            .parse()
+            .map(Duration::from_secs)
+            .map_err(|err| format!("{:?} is not a number of seconds: {}", value, err))?,
+        None => DEFAULT_TIMEOUT,
+    };
+    if timeout.is_zero() {
+        return Err(String::from("the timeout must be at least a second"));
+    }
+    Ok(timeout)
+}
+
+// Parse the maximum number of connections to handle at once, with no limit when it's
+// unset. A limit of zero would turn every connection away.
+fn parse_max_connections(value: Option<&str>) -> Result<usize, String> {
+    let max_connections = match value {
+        Some(value) => value.trim().parse().map_err(|err| format!("{:?} is not a number: {}", value, err))?,
+        None => usize::MAX,
+    };
+    if max_connections == 0 {
+        return Err(String::from("at least one connection must be allowed"));
+    }
+    Ok(max_connections)
+}
+
+// How many requests one connection can make before it's closed. A connection that
+// couldn't make any would be no use to anyone.
+fn parse_keep_alive_max(value: Option<&str>) -> Result<usize, String> {
+    let keep_alive_max = match value {
+        Some(value) => value.trim().parse().map_err(|err| format!("{:?} is not a number: {}", value, err))?,
+        None => DEFAULT_KEEP_ALIVE_MAX,
+    };
+    if keep_alive_max == 0 {
+        return Err(String::from("at least one request must be allowed"));
+    }
+    Ok(keep_alive_max)
+}
+
+// How long a connection can sit idle between requests, in whole seconds. Sockets can't
+// be given a timeout of zero.
+fn parse_keep_alive_timeout(value: Option<&str>) -> Result<Duration, String> {
+    let keep_alive_timeout = match value {
+        Some(value) => value
+            .trim()
+            .parse()
+            .map(Duration::from_secs)
+            .map_err(|err| format!("{:?} is not a number of seconds: {}", value, err))?,
+        None => DEFAULT_KEEP_ALIVE_TIMEOUT,
+    };
+    if keep_alive_timeout.is_zero() {
+        return Err(String::from("the keep-alive timeout must be at least a second"));
+    }
+    Ok(keep_alive_timeout)
+}
+
+// The largest body to accept in bytes, anything bigger is turned away with a 413.
+fn parse_max_body_size(value: Option<&str>) -> Result<usize, String> {
+    match value {
+        Some(value) => value.trim().parse().map_err(|err| format!("{:?} is not a number of bytes: {}", value, err)),
+        None => Ok(DEFAULT_MAX_BODY_SIZE),
+    }
+}
+
+// The most header bytes to accept, anything more is turned away with a 431. No request
+// at all would fit in zero.
+fn parse_max_header_size(value: Option<&str>) -> Result<usize, String> {
+    let max_header_size = match value {
+        Some(value) => value.trim().parse().map_err(|err| format!("{:?} is not a number of bytes: {}", value, err))?,
+        None => DEFAULT_MAX_HEADER_SIZE,
+    };
+    if max_header_size == 0 {
+        return Err(String::from("the headers need at least one byte"));
+    }
+    Ok(max_header_size)
+}
+
+// Access logs are plain text unless JSON is asked for.
+fn parse_log_format(value: Option<&str>) -> Result<LogFormat, String> {
+    match value.map(str::trim) {
+        None => Ok(LogFormat::Plain),
+        Some(value) if value.eq_ignore_ascii_case("plain") => Ok(LogFormat::Plain),
+        Some(value) if value.eq_ignore_ascii_case("json") => Ok(LogFormat::Json),
+        Some(value) => Err(format!("{:?} should be \"plain\" or \"json\"", value)),
+    }
+}
+
+// Comma separated `extension=type` pairs, e.g. `wasm=application/wasm`. The extension can
+// be given with or without its dot.
+fn parse_mime_types(value: Option<&str>) -> Result<Vec<(String, String)>, String> {
+    let Some(value) = value else {
+        return Ok(Vec::new());
+    };
+    let mut types = Vec::new();
+    for pair in value.split(',').map(str::trim).filter(|pair| !pair.is_empty()) {
+        let (extension, content_type) = pair
+            .split_once('=')
+            .map(|(extension, content_type)| (extension.trim().trim_start_matches('.'), content_type.trim()))
+            .filter(|(extension, content_type)| !extension.is_empty() && !content_type.is_empty())
+            .ok_or_else(|| format!("{:?} should look like \"wasm=application/wasm\"", pair))?;
+        types.push((String::from(extension), String::from(content_type)));
+    }
+    Ok(types)
+}
+
+// Comma separated `host=root` pairs, e.g. `example.com=/srv/example`. Every root has to
+// exist just like the main document root.
+fn parse_virtual_hosts(value: Option<&str>) -> Result<Vec<(String, PathBuf)>, String> {
+    let Some(value) = value else {
+        return Ok(Vec::new());
+    };
+    let mut virtual_hosts = Vec::new();
+    for pair in value.split(',').map(str::trim).filter(|pair| !pair.is_empty()) {
+        let (host, root) = pair
+            .split_once('=')
+            .map(|(host, root)| (host.trim(), root.trim()))
+            .filter(|(host, root)| !host.is_empty() && !root.is_empty())
+            .ok_or_else(|| format!("{:?} should look like \"example.com=/srv/example\"", pair))?;
+        let root = check_document_root(Path::new(root)).map_err(|err| err.to_string())?;
+        virtual_hosts.push((String::from(host), root));
+    }
+    Ok(virtual_hosts)
+}
+
+// The requests a second each client is allowed, if there's a limit at all, and the burst
+// they can make at once, which is the same as the rate unless it's set too.
+fn parse_rate_limit(rate: Option<&str>, burst: Option<&str>) -> Result<Option<(u32, u32)>, String> {
+    let Some(rate) = rate else {
+        return match burst {
+            Some(_) => Err(String::from("RATE_LIMIT_BURST needs RATE_LIMIT to be set too")),
+            None => Ok(None),
+        };
+    };
+    let positive = |value: &str| match value.trim().parse::<u32>() {
+        Ok(0) => Err(format!("{:?} should be at least 1", value)),
+        Ok(number) => Ok(number),
+        Err(err) => Err(format!("{:?} is not a number: {}", value, err)),
+    };
+    let rate = positive(rate)?;
+    let burst = burst.map_or(Ok(rate), positive)?;
+    Ok(Some((rate, burst)))
+}
+
+// A comma separated list of methods, e.g. `GET,HEAD`. Unset means every method is allowed.
+fn parse_allowed_methods(value: Option<&str>) -> Result<Option<Vec<Method>>, String> {
+    let Some(value) = value else {
+        return Ok(None);
+    };
+    let mut methods = Vec::new();
+    for method in value.split(',').map(str::trim).filter(|method| !method.is_empty()) {
+        let method = method.parse().map_err(|_| format!("{:?} is not a method we know", method))?;
+        if !methods.contains(&method) {
+            methods.push(method);
+        }
+    }
+    if methods.is_empty() {
+        return Err(String::from("At least one method is needed"));
+    }
+    methods.sort();
+    Ok(Some(methods))
+}
+
+// How many files to cache, with no cache when it's unset or zero.
+fn parse_file_cache_size(value: Option<&str>) -> Result<Option<usize>, String> {
+    match value {
+        Some(value) => match value.trim().parse() {
+            Ok(0) => Ok(None),
+            Ok(size) => Ok(Some(size)),
+            Err(err) => Err(format!("{:?} is not a number of files: {}", value, err)),
+        },
+        None => Ok(None),
+    }
+}
+
+// Headers to add to every response, as `Name: value` pairs separated by `|` (since
+// header values often contain commas and semicolons).
+fn parse_response_headers(value: Option<&str>) -> Result<Vec<(String, String)>, String> {
+    let Some(value) = value else {
+        return Ok(Vec::new());
+    };
+    let mut headers = Vec::new();
+    for header in value.split('|').map(str::trim).filter(|header| !header.is_empty()) {
+        let Some((name, value)) = header.split_once(':') else {
+            return Err(format!("{:?} should look like \"Name: value\"", header));
+        };
+        let name = name.trim();
+        if name.is_empty() || !name.bytes().all(|byte| byte.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&byte)) {
+            return Err(format!("{:?} is not a valid header name", name));
+        }
+        headers.push((String::from(name), String::from(value.trim())));
+    }
+    Ok(headers)
+}
+
+// HTTPS needs both a certificate and a key, one without the other is a mistake.
+fn parse_tls(cert: Option<String>, key: Option<String>) -> Result<Option<(PathBuf, PathBuf)>, String> {
+    match (cert, key) {
+        (Some(cert), Some(key)) => Ok(Some((PathBuf::from(cert), PathBuf::from(key)))),
+        (None, None) => Ok(None),
+        _ => Err(String::from("both must be set to serve HTTPS")),
+    }
+}
+
+// The admin endpoints need a user and a password, and an empty one is a mistake.
+fn parse_admin(user: Option<String>, pass: Option<String>) -> Result<Option<(String, String)>, String> {
+    match (user, pass) {
+        (Some(user), Some(pass)) if !user.is_empty() && !pass.is_empty() => Ok(Some((user, pass))),
+        (None, None) => Ok(None),
+        _ => Err(String::from("both must be set (and not empty) to enable the admin endpoints")),
+    }
+}
+
+// Errors:
+// Every problem found with the configuration, so they can all be fixed in one go.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigError {
+    pub problems: Vec<String>,
+}
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Invalid configuration:")?;
+        for problem in &self.problems {
+            write!(f, "\n  {}", problem)?;
+        }
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn from_map(vars: &[(&str, &str)]) -> Result<Config, ConfigError> {
+        let vars: HashMap<String, String> = vars.iter().map(|(name, value)| (String::from(*name), String::from(*value))).collect();
+        Config::from_vars(|name| vars.get(name).cloned())
+    }
+
+    #[test]
+    fn test_fully_specified_config() {
+        let root = tempfile::tempdir().expect("Failed to create temp dir");
+        let config = from_map(&[
+            ("HOST", "0.0.0.0"),
+            ("PORT", "8080"),
+            ("WORKERS", "8"),
+            ("DOCUMENT_ROOT", root.path().to_str().unwrap()),
+            ("READ_TIMEOUT_SECS", "5"),
+            ("WRITE_TIMEOUT_SECS", "10"),
+            ("MAX_CONNECTIONS", "100"),
+            ("KEEP_ALIVE_MAX", "10"),
+            ("KEEP_ALIVE_TIMEOUT_SECS", "2"),
+            ("MAX_BODY_SIZE", "4096"),
+            ("MAX_HEADER_SIZE", "16384"),
+            ("DIRECTORY_LISTING", "true"),
+            ("SPA_MODE", "1"),
+            ("FILE_CACHE_SIZE", "64"),
+            ("SERVER_HEADER", "off"),
+            ("TCP_NODELAY", "1"),
+            ("LOG_FORMAT", "json"),
+            ("ALLOWED_METHODS", "HEAD,GET"),
+            ("RATE_LIMIT", "10"),
+            ("RATE_LIMIT_BURST", "20"),
+            ("CORS_ALLOWED_ORIGINS", "https://a.example, https://b.example,"),
+            ("RESPONSE_HEADERS", "X-Frame-Options: DENY"),
+            ("MIME_TYPES", "wasm=application/wasm"),
+            ("TLS_CERT", "cert.pem"),
+            ("TLS_KEY", "key.pem"),
+            ("ADMIN_USER", "admin"),
+            ("ADMIN_PASS", "hunter2"),
+        ])
+        .expect("The config should be valid");
+
+        assert_eq!(config.addresses(), ["0.0.0.0:8080".parse().unwrap()]);
+        assert_eq!(config.workers, 8);
+        assert_eq!(config.document_root, root.path().canonicalize().unwrap());
+        assert_eq!(config.read_timeout, Duration::from_secs(5));
+        assert_eq!(config.write_timeout, Duration::from_secs(10));
+        assert_eq!(config.max_connections, 100);
+        assert_eq!(config.keep_alive_max, 10);
+        assert_eq!(config.keep_alive_timeout, Duration::from_secs(2));
+        assert_eq!(config.max_body_size, 4096);
+        assert_eq!(config.max_header_size, 16384);
+        assert!(config.directory_listing);
+        assert!(config.spa_mode);
+        assert_eq!(config.file_cache_size, Some(64));
+        assert!(!config.server_header);
+        assert!(config.nodelay);
+        assert_eq!(config.log_format, LogFormat::Json);
+        assert_eq!(config.allowed_methods, Some(vec![Method::Get, Method::Head]));
+        assert_eq!(config.rate_limit, Some((10, 20)));
+        assert_eq!(
+            config.cors_allowed_origins,
+            Some(vec![String::from("https://a.example"), String::from("https://b.example")])
+        );
+        assert_eq!(config.response_headers, [(String::from("X-Frame-Options"), String::from("DENY"))]);
+        assert_eq!(config.mime_types, [(String::from("wasm"), String::from("application/wasm"))]);
+        assert_eq!(config.tls, Some((PathBuf::from("cert.pem"), PathBuf::from("key.pem"))));
+        assert_eq!(config.admin, Some((String::from("admin"), String::from("hunter2"))));
+    }
+
+    #[test]
+    fn test_defaults() {
+        let root = tempfile::tempdir().expect("Failed to create temp dir");
+        let config = from_map(&[("DOCUMENT_ROOT", root.path().to_str().unwrap())]).expect("The config should be valid");
+        assert_eq!(config.addresses(), ["127.0.0.1:7878".parse().unwrap()]);
+        assert_eq!(config.workers, default_workers());
+        assert_eq!(config.read_timeout, DEFAULT_TIMEOUT);
+        assert_eq!(config.max_connections, usize::MAX);
+        assert_eq!(config.keep_alive_max, DEFAULT_KEEP_ALIVE_MAX);
+        assert_eq!(config.keep_alive_timeout, DEFAULT_KEEP_ALIVE_TIMEOUT);
+        assert_eq!(config.max_body_size, DEFAULT_MAX_BODY_SIZE);
+        assert_eq!(config.max_header_size, DEFAULT_MAX_HEADER_SIZE);
+        assert!(!config.directory_listing);
+        assert!(!config.spa_mode);
+        assert_eq!(config.file_cache_size, None);
+        assert!(config.server_header);
+        assert!(!config.nodelay);
+        assert_eq!(config.log_format, LogFormat::Plain);
+        assert_eq!(config.allowed_methods, None);
+        assert_eq!(config.rate_limit, None);
+        assert_eq!(config.cors_allowed_origins, None);
+        assert!(config.response_headers.is_empty());
+        assert!(config.mime_types.is_empty());
+        assert!(config.virtual_hosts.is_empty());
+        assert_eq!(config.tls, None);
+        assert_eq!(config.admin, None);
+    }
+
+    #[test]
+    fn test_every_invalid_value_is_reported() {
+        let err = from_map(&[
+            ("HOST", "localhost"),
+            ("PORT", "http"),
+            ("WORKERS", "0"),
+            ("DOCUMENT_ROOT", "/does/not/exist"),
+            ("MAX_BODY_SIZE", "1MB"),
+            ("TLS_CERT", "cert.pem"),
+        ])
+        .unwrap_err();
+        let fields: Vec<&str> = err.problems.iter().map(|problem| problem.split(':').next().unwrap()).collect();
+        assert_eq!(fields, ["HOST", "PORT", "WORKERS", "DOCUMENT_ROOT", "MAX_BODY_SIZE", "TLS_CERT/TLS_KEY"]);
+        assert!(err.to_string().starts_with("Invalid configuration:\n  HOST: "));
+    }
+
+    #[test]
+    fn test_address_brackets_ipv6() {
+        let config = Config { hosts: vec!["::1".parse().unwrap()], port: 8080, ..from_map(&[("DOCUMENT_ROOT", ".")]).unwrap() };
+        assert_eq!(config.addresses()[0].to_string(), "[::1]:8080");
+    }
+
+    #[test]
+    fn test_parse_hosts() {
+        let localhost: [IpAddr; 2] = ["127.0.0.1".parse().unwrap(), "::1".parse().unwrap()];
+        assert_eq!(parse_hosts(None), Ok(vec![localhost[0]]));
+        assert_eq!(parse_hosts(Some("127.0.0.1, ::1")), Ok(localhost.to_vec()));
+        assert_eq!(parse_hosts(Some("127.0.0.1,::1,127.0.0.1,")), Ok(localhost.to_vec()));
+        assert!(parse_hosts(Some(" , ")).is_err());
+        assert!(parse_hosts(Some("127.0.0.1,localhost")).unwrap_err().contains("\"localhost\""));
+    }
+
+    #[test]
+    fn test_parse_response_headers() {
+        let headers = parse_response_headers(Some("X-Frame-Options: DENY | Content-Security-Policy: default-src 'self'; img-src *|"));
+        assert_eq!(
+            headers,
+            Ok(vec![
+                (String::from("X-Frame-Options"), String::from("DENY")),
+                (String::from("Content-Security-Policy"), String::from("default-src 'self'; img-src *")),
+            ])
+        );
+        assert_eq!(parse_response_headers(None), Ok(Vec::new()));
+        assert!(parse_response_headers(Some("nosniff")).is_err());
+        assert!(parse_response_headers(Some("Bad Name: value")).is_err());
+    }
+
+    #[test]
+    fn test_parse_mime_types() {
+        assert_eq!(
+            parse_mime_types(Some("wasm=application/wasm, .md = text/markdown; charset=utf-8")),
+            Ok(vec![
+                (String::from("wasm"), String::from("application/wasm")),
+                (String::from("md"), String::from("text/markdown; charset=utf-8")),
+            ])
+        );
+        assert_eq!(parse_mime_types(None), Ok(Vec::new()));
+        assert!(parse_mime_types(Some("application/wasm")).is_err());
+        assert!(parse_mime_types(Some("wasm=")).is_err());
+    }
+
+    #[test]
+    fn test_parse_virtual_hosts() {
+        let root = tempfile::tempdir().expect("Failed to create temp dir");
+        let value = format!("example.com={}, ", root.path().display());
+        assert_eq!(
+            parse_virtual_hosts(Some(&value)),
+            Ok(vec![(String::from("example.com"), root.path().canonicalize().unwrap())])
+        );
+        assert_eq!(parse_virtual_hosts(None), Ok(Vec::new()));
+        assert!(parse_virtual_hosts(Some("example.com")).is_err());
+        assert!(parse_virtual_hosts(Some("example.com=/does/not/exist")).is_err());
+    }
+
+    #[test]
+    fn test_parse_rate_limit() {
+        assert_eq!(parse_rate_limit(None, None), Ok(None));
+        assert_eq!(parse_rate_limit(Some("5"), None), Ok(Some((5, 5))));
+        assert_eq!(parse_rate_limit(Some("5"), Some("50")), Ok(Some((5, 50))));
+        assert!(parse_rate_limit(Some("0"), None).is_err());
+        assert!(parse_rate_limit(Some("5"), Some("lots")).is_err());
+        assert!(parse_rate_limit(None, Some("50")).is_err());
+    }
+
+    #[test]
+    fn test_parse_admin() {
+        assert_eq!(parse_admin(None, None), Ok(None));
+        assert!(parse_admin(Some(String::from("admin")), None).is_err());
+        assert!(parse_admin(Some(String::from("admin")), Some(String::new())).is_err());
+    }
+
+    #[test]
+    fn test_parse_allowed_methods() {
+        assert_eq!(parse_allowed_methods(None), Ok(None));
+        assert_eq!(parse_allowed_methods(Some("GET, HEAD, GET")), Ok(Some(vec![Method::Get, Method::Head])));
+        assert!(parse_allowed_methods(Some("get")).is_err());
+        assert!(parse_allowed_methods(Some(" , ")).is_err());
+    }
+
+    #[test]
+    fn test_parse_port() {
+        assert_eq!(parse_port(Some("8080")), Ok(8080));
+        assert_eq!(parse_port(None), Ok(DEFAULT_PORT));
+        assert!(parse_port(Some("70000")).is_err());
+        assert!(parse_port(Some("-1")).is_err());
+    }
+
+    #[test]
+    fn test_parse_workers() {
+        assert_eq!(parse_workers(Some("8"), 4), Ok(8));
+        assert_eq!(parse_workers(None, 4), Ok(4));
+    }
+
+    #[test]
+    fn test_parse_workers_rejects_invalid_values() {
+        assert!(parse_workers(Some("0"), 4).is_err());
+        assert!(parse_workers(Some("-1"), 4).is_err());
+        assert!(parse_workers(Some("many"), 4).is_err());
+    }
+
+    #[test]
+    fn test_default_workers_is_at_least_one() {
+        assert!(default_workers() >= 1);
+    }
+
+    #[test]
+    fn test_parse_max_connections() {
+        assert_eq!(parse_max_connections(Some("100")), Ok(100));
+        assert_eq!(parse_max_connections(None), Ok(usize::MAX));
+        assert!(parse_max_connections(Some("0")).is_err());
+        assert!(parse_max_connections(Some("lots")).is_err());
+    }
+
+    #[test]
+    fn test_parse_keep_alive() {
+        assert_eq!(parse_keep_alive_max(Some("10")), Ok(10));
+        assert_eq!(parse_keep_alive_max(None), Ok(DEFAULT_KEEP_ALIVE_MAX));
+        assert!(parse_keep_alive_max(Some("0")).is_err());
+        assert_eq!(parse_keep_alive_timeout(Some("2")), Ok(Duration::from_secs(2)));
+        assert_eq!(parse_keep_alive_timeout(None), Ok(DEFAULT_KEEP_ALIVE_TIMEOUT));
+        assert!(parse_keep_alive_timeout(Some("a while")).is_err());
+        assert!(parse_keep_alive_timeout(Some("0")).is_err());
+    }
+
+    #[test]
+    fn test_parse_timeout() {
+        assert_eq!(parse_timeout(Some("5")), Ok(Duration::from_secs(5)));
+        assert_eq!(parse_timeout(None), Ok(DEFAULT_TIMEOUT));
+        assert!(parse_timeout(Some("soon")).is_err());
+        assert!(parse_timeout(Some("0")).is_err());
+    }
+}
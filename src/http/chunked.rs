@@ -0,0 +1,305 @@
+use std::fmt;
+use std::io;
+use std::error;
+use std::io::prelude::*;
+
+/// How many bytes `copy_chunked` reads from its source for each chunk.
+const COPY_CHUNK_SIZE: usize = 64 * 1024;
+
+/// How long a chunk size line can get before we give up on it, it's only a few hex digits
+/// and maybe some extensions.
+const MAX_SIZE_LINE: usize = 1024;
+
+/// Write a body with `Transfer-Encoding: chunked`, for when its length isn't known up front.
+///
+/// Each chunk goes out as its size in hex, a `\r\n`, the bytes and another `\r\n`,
+/// followed at the end by the zero-length chunk that marks the end of the body. The
+/// response head (with `Transfer-Encoding: chunked` and no `Content-Length`) has to be
+/// written first. Empty chunks are skipped, since one would end the body early.
+pub fn write_chunked<W, I>(stream: &mut W, chunks: I) -> io::Result<()>
+where
+    W: Write,
+    I: IntoIterator,
+    I::Item: AsRef<[u8]>,
+{
+    for chunk in chunks {
+        let chunk = chunk.as_ref();
+        if chunk.is_empty() {
+            continue;
+        }
+        write!(stream, "{:x}\r\n", chunk.len())?;
+        stream.write_all(chunk)?;
+        stream.write_all(b"\r\n")?;
+    }
+    stream.write_all(b"0\r\n\r\n")
+}
+
+/// Copy everything `reader` has to offer to `stream` as a chunked body, one chunk per read,
+/// like `write_chunked` but without needing the chunks up front. Returns how many bytes
+/// were written, framing included.
+pub fn copy_chunked<R: Read, W: Write>(reader: &mut R, stream: &mut W) -> io::Result<u64> {
+    let mut chunk = vec![0; COPY_CHUNK_SIZE];
+    let mut bytes_written = 0;
+    loop {
+        let bytes_read = match reader.read(&mut chunk) {
+            Ok(bytes_read) => bytes_read,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err),
+        };
+        if bytes_read == 0 {
+            stream.write_all(b"0\r\n\r\n")?;
+            return Ok(bytes_written + 5);
+        }
+        let size_line = format!("{:x}\r\n", bytes_read);
+        stream.write_all(size_line.as_bytes())?;
+        stream.write_all(&chunk[..bytes_read])?;
+        stream.write_all(b"\r\n")?;
+        bytes_written += (size_line.len() + bytes_read + 2) as u64;
+    }
+}
+
+/// Decode a body sent with `Transfer-Encoding: chunked`.
+///
+/// `data` is everything after the request's headers. Returns the decoded body along with
+/// how many bytes of `data` it took up (anything after that is the next request), or
+/// `None` if the terminating zero-length chunk hasn't arrived yet. Chunk extensions and
+/// trailers are read past and thrown away. A body that decodes to more than `limit`
+/// bytes is an error.
+pub fn decode_chunked(data: &[u8], limit: usize) -> Result<Option<(Vec<u8>, usize)>, ChunkedError> {
+    let mut decoder = ChunkedDecoder::new(limit);
+    let used = decoder.decode(data)?;
+    Ok(decoder.is_done().then(|| (decoder.into_body(), used)))
+}
+
+/// Decodes a chunked body a piece at a time, as it comes in.
+///
+/// Each call to `decode` takes the whole chunks (and trailer lines) at the start of what
+/// it's given and says how many bytes that was, so the caller can drop them and hand over
+/// the rest again once more has arrived. Nothing that's been decoded is looked at twice.
+pub struct ChunkedDecoder {
+    body: Vec<u8>,
+    limit: usize,
+    max_trailer_size: usize,
+    trailer_size: usize,
+    in_trailers: bool,
+    done: bool,
+}
+impl ChunkedDecoder {
+    /// Create a decoder for a body of at most `limit` bytes, with no limit on the trailers.
+    pub fn new(limit: usize) -> ChunkedDecoder {
+        ChunkedDecoder {
+            body: Vec::new(),
+            limit,
+            max_trailer_size: usize::MAX,
+            trailer_size: 0,
+            in_trailers: false,
+            done: false,
+        }
+    }
+
+    /// Give up on a body whose trailers go on for more than `max_trailer_size` bytes.
+    pub fn with_max_trailer_size(mut self, max_trailer_size: usize) -> ChunkedDecoder {
+        self.max_trailer_size = max_trailer_size;
+        self
+    }
+
+    /// Decode as much of `data` as makes up whole chunks or trailer lines, returning how
+    /// many bytes were used. Once the body is done nothing more is used, so whatever's left
+    /// over belongs to the next request.
+    pub fn decode(&mut self, data: &[u8]) -> Result<usize, ChunkedError> {
+        let mut position = 0;
+        while !self.done {
+            let Some(line_end) = find_crlf(data, position) else {
+                let pending = data.len() - position;
+                if self.in_trailers && pending > self.max_trailer_size - self.trailer_size {
+                    return Err(ChunkedError::TooLarge);
+                }
+                if !self.in_trailers && pending > MAX_SIZE_LINE {
+                    return Err(ChunkedError::InvalidSize(String::from_utf8_lossy(&data[position..]).into_owned()));
+                }
+                break;
+            };
+
+            // Skip over any trailers up to the blank line that ends the body.
+            if self.in_trailers {
+                let line_size = line_end + 2 - position;
+                if line_size > self.max_trailer_size - self.trailer_size {
+                    return Err(ChunkedError::TooLarge);
+                }
+                self.trailer_size += line_size;
+                self.done = line_end == position;
+                position = line_end + 2;
+                continue;
+            }
+
+            let size = parse_chunk_size(&data[position..line_end])?;
+            if size == 0 {
+                self.in_trailers = true;
+                position = line_end + 2;
+                continue;
+            }
+            if size > self.limit - self.body.len() {
+                return Err(ChunkedError::TooLarge);
+            }
+            // The size line is left for next time until the whole chunk is here. Compared
+            // against what's left rather than added to `start`, so a huge size can't overflow.
+            let start = line_end + 2;
+            if data.len() - start < size.saturating_add(2) {
+                break;
+            }
+            if &data[start + size..start + size + 2] != b"\r\n" {
+                return Err(ChunkedError::MissingCrlf);
+            }
+            self.body.extend_from_slice(&data[start..start + size]);
+            position = start + size + 2;
+        }
+        Ok(position)
+    }
+
+    /// Whether the zero-length chunk and the blank line after any trailers have been seen.
+    pub fn is_done(&self) -> bool {
+        self.done
+    }
+
+    /// The body decoded so far.
+    pub fn into_body(self) -> Vec<u8> {
+        self.body
+    }
+}
+
+// Find the next `\r\n` at or after `from`.
+fn find_crlf(data: &[u8], from: usize) -> Option<usize> {
+    data[from..].windows(2).position(|window| window == b"\r\n").map(|position| from + position)
+}
+
+// Parse a chunk size line like `1a` or `1a;name=value`, the size is in hex.
+fn parse_chunk_size(line: &[u8]) -> Result<usize, ChunkedError> {
+    let invalid = || ChunkedError::InvalidSize(String::from_utf8_lossy(line).into_owned());
+    let line = std::str::from_utf8(line).map_err(|_| invalid())?;
+    let size = line.split(';').next().unwrap_or("").trim();
+    if size.is_empty() || !size.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+        return Err(invalid());
+    }
+    usize::from_str_radix(size, 16).map_err(|_| invalid())
+}
+
+// Errors:
+// Everything that can go wrong while decoding a chunked body.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ChunkedError {
+    // A chunk size line wasn't a hex number.
+    InvalidSize(String),
+    // A chunk wasn't followed by `\r\n`, so its size was wrong.
+    MissingCrlf,
+    // The body was bigger than we're willing to accept.
+    TooLarge,
+}
+impl fmt::Display for ChunkedError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ChunkedError::InvalidSize(line) => write!(f, "Invalid chunk size: {:?}", line),
+            ChunkedError::MissingCrlf => write!(f, "A chunk was longer than its size said!"),
+            ChunkedError::TooLarge => write!(f, "The chunked body was too large!"),
+        }
+    }
+}
+impl error::Error for ChunkedError {}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_chunked_framing() {
+        let mut output = Vec::new();
+        write_chunked(&mut output, ["hello", " ", "chunked world, this is thirty"]).unwrap();
+        assert_eq!(output, b"5\r\nhello\r\n1\r\n \r\n1d\r\nchunked world, this is thirty\r\n0\r\n\r\n");
+    }
+
+    #[test]
+    fn test_write_chunked_skips_empty_chunks() {
+        let mut output = Vec::new();
+        write_chunked(&mut output, [&b"abc"[..], b"", b"de"]).unwrap();
+        assert_eq!(output, b"3\r\nabc\r\n2\r\nde\r\n0\r\n\r\n");
+
+        let mut output = Vec::new();
+        write_chunked(&mut output, Vec::<Vec<u8>>::new()).unwrap();
+        assert_eq!(output, b"0\r\n\r\n");
+    }
+
+    #[test]
+    fn test_copy_chunked() {
+        let mut output = Vec::new();
+        let written = copy_chunked(&mut io::Cursor::new("hello world"), &mut output).unwrap();
+        assert_eq!(output, b"b\r\nhello world\r\n0\r\n\r\n");
+        assert_eq!(written, output.len() as u64);
+
+        let mut output = Vec::new();
+        copy_chunked(&mut io::empty(), &mut output).unwrap();
+        assert_eq!(output, b"0\r\n\r\n");
+    }
+
+    #[test]
+    fn test_decode_chunked_body() {
+        let data = b"5\r\nhello\r\n6;ext=1\r\n world\r\n0\r\n\r\nGET / HTTP/1.1\r\n\r\n";
+        let (body, used) = decode_chunked(data, usize::MAX).unwrap().expect("The body is complete");
+        assert_eq!(body, b"hello world");
+        assert_eq!(&data[used..], b"GET / HTTP/1.1\r\n\r\n");
+
+        // Trailers are skipped.
+        let data = b"3\r\nabc\r\n0\r\nExpires: never\r\n\r\n";
+        let (body, used) = decode_chunked(data, usize::MAX).unwrap().unwrap();
+        assert_eq!(body, b"abc");
+        assert_eq!(used, data.len());
+    }
+
+    #[test]
+    fn test_decode_incomplete_chunked_body() {
+        assert_eq!(decode_chunked(b"", usize::MAX), Ok(None));
+        assert_eq!(decode_chunked(b"5\r\nhel", usize::MAX), Ok(None));
+        assert_eq!(decode_chunked(b"5\r\nhello\r\n0\r\n", usize::MAX), Ok(None));
+    }
+
+    #[test]
+    fn test_decode_chunked_huge_size() {
+        // Without a limit a size this big is just a chunk that hasn't finished arriving.
+        assert_eq!(decode_chunked(b"ffffffffffffffff\r\nhello\r\n", usize::MAX), Ok(None));
+        assert_eq!(decode_chunked(b"ffffffffffffffff\r\nhello\r\n", 1024), Err(ChunkedError::TooLarge));
+    }
+
+    #[test]
+    fn test_decode_chunked_rejects_bad_sizes() {
+        assert!(matches!(decode_chunked(b"zz\r\nhello\r\n0\r\n\r\n", usize::MAX), Err(ChunkedError::InvalidSize(_))));
+        assert!(matches!(decode_chunked(b"+5\r\nhello\r\n0\r\n\r\n", usize::MAX), Err(ChunkedError::InvalidSize(_))));
+        assert!(matches!(decode_chunked(b"\r\n", usize::MAX), Err(ChunkedError::InvalidSize(_))));
+        assert!(matches!(decode_chunked(b"ffffffffffffffffffff\r\n", usize::MAX), Err(ChunkedError::InvalidSize(_))));
+        assert_eq!(decode_chunked(b"3\r\nhello\r\n0\r\n\r\n", usize::MAX), Err(ChunkedError::MissingCrlf));
+        assert_eq!(decode_chunked(b"5\r\nhello\r\n0\r\n\r\n", 4), Err(ChunkedError::TooLarge));
+    }
+
+    #[test]
+    fn test_decoder_picks_up_where_it_left_off() {
+        let mut decoder = ChunkedDecoder::new(usize::MAX);
+        // Only the first chunk is whole, the second's size line waits for the rest of it.
+        assert_eq!(decoder.decode(b"5\r\nhello\r\n6\r\n wo"), Ok(10));
+        assert_eq!(decoder.decode(b"6\r\n world\r\n0\r\nExpires: never\r\n"), Ok(30));
+        assert!(!decoder.is_done());
+        assert_eq!(decoder.decode(b"\r\nGET / HTTP/1.1\r\n\r\n"), Ok(2));
+        assert!(decoder.is_done());
+        assert_eq!(decoder.into_body(), b"hello world");
+    }
+
+    #[test]
+    fn test_decoder_limits_trailers() {
+        let mut decoder = ChunkedDecoder::new(usize::MAX).with_max_trailer_size(20);
+        assert_eq!(decoder.decode(b"0\r\nExpires: never\r\n\r\n"), Ok(21));
+        assert!(decoder.is_done());
+
+        let mut decoder = ChunkedDecoder::new(usize::MAX).with_max_trailer_size(20);
+        assert_eq!(decoder.decode(b"0\r\nExpires: never\r\nX: y\r\n\r\n"), Err(ChunkedError::TooLarge));
+        // Still too long without the end of the line having arrived.
+        let mut decoder = ChunkedDecoder::new(usize::MAX).with_max_trailer_size(20);
+        assert_eq!(decoder.decode(b"0\r\nX-Padding: aaaaaaaaaaaa"), Err(ChunkedError::TooLarge));
+    }
+}
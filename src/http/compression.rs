@@ -0,0 +1,139 @@
+use std::io::prelude::*;
+use flate2::Compression;
+use flate2::write::GzEncoder;
+use crate::http::request::Request;
+use crate::http::response::Response;
+
+/// Gzip the body if the client's `Accept-Encoding` says it can handle it.
+///
+/// Returns the (possibly compressed) body along with the `Content-Encoding` to send with
+/// it, which is `None` if the body was left alone.
+pub fn maybe_compress(body: Vec<u8>, accept_encoding: &str) -> (Vec<u8>, Option<&'static str>) {
+    if body.is_empty() || !accepts_gzip(accept_encoding) {
+        return (body, None);
+    }
+
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    // Writing into a Vec can't fail.
+    encoder.write_all(&body).expect("Writing to a Vec should never fail");
+    match encoder.finish() {
+        Ok(compressed) => (compressed, Some("gzip")),
+        Err(_) => (body, None),
+    }
+}
+
+/// Compress a response for the request if it's worth it.
+///
+/// Only text-like content is compressed, formats like images and archives are already
+/// compressed and would just get bigger.
+pub fn compress_response(mut response: Response, request: &Request) -> Response {
+    let accept_encoding = match request.header("accept-encoding") {
+        Some(accept_encoding) => accept_encoding,
+        None => return response,
+    };
+    // A partial response is a slice of the uncompressed file, compressing it would break
+    // the Content-Range.
+    let compressible = response.header("content-type").is_some_and(is_compressible);
+    if !compressible || response.status == 206 || response.header("content-encoding").is_some() {
+        return response;
+    }
+
+    let (body, encoding) = maybe_compress(response.body, accept_encoding);
+    response.body = body;
+    match encoding {
+        Some(encoding) => response
+            .with_header("Content-Encoding", encoding)
+            .with_header("Vary", "Accept-Encoding"),
+        None => response,
+    }
+}
+
+// Whether the Accept-Encoding header lists gzip (or `*`) without ruling it out with `q=0`.
+fn accepts_gzip(accept_encoding: &str) -> bool {
+    accept_encoding.split(',').any(|encoding| {
+        let mut parts = encoding.split(';');
+        let name = parts.next().unwrap_or("").trim();
+        let refused = parts.any(|param| {
+            param
+                .trim()
+                .strip_prefix("q=")
+                .and_then(|q| q.trim().parse::<f32>().ok())
+                .is_some_and(|q| q == 0.0)
+        });
+        (name.eq_ignore_ascii_case("gzip") || name == "*") && !refused
+    })
+}
+
+// Text formats compress well, most binary formats are already compressed.
+fn is_compressible(content_type: &str) -> bool {
+    content_type.starts_with("text/")
+        || content_type.starts_with("application/json")
+        || content_type.starts_with("application/javascript")
+        || content_type.starts_with("application/xml")
+        || content_type.starts_with("image/svg+xml")
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::read::GzDecoder;
+
+    const BODY: &str = "<h1>Hello!</h1><p>Hi from Rust</p>";
+
+    fn gunzip(bytes: &[u8]) -> String {
+        let mut decoded = String::new();
+        GzDecoder::new(bytes).read_to_string(&mut decoded).expect("Body should be valid gzip");
+        decoded
+    }
+
+    #[test]
+    fn test_maybe_compress_when_gzip_advertised() {
+        let (body, encoding) = maybe_compress(BODY.as_bytes().to_vec(), "gzip, deflate, br");
+        assert_eq!(encoding, Some("gzip"));
+        assert_eq!(gunzip(&body), BODY);
+    }
+
+    #[test]
+    fn test_maybe_compress_when_gzip_not_advertised() {
+        let (body, encoding) = maybe_compress(BODY.as_bytes().to_vec(), "deflate, br");
+        assert_eq!(encoding, None);
+        assert_eq!(body, BODY.as_bytes());
+
+        let (_, encoding) = maybe_compress(BODY.as_bytes().to_vec(), "gzip;q=0, br");
+        assert_eq!(encoding, None);
+    }
+
+    #[test]
+    fn test_compress_response_sets_content_encoding() {
+        let request = Request {
+            headers: [(String::from("accept-encoding"), String::from("gzip"))].into(),
+            ..Request::default()
+        };
+        let response = Response::new(200)
+            .with_header("Content-Type", "text/html; charset=utf-8")
+            .with_body(BODY);
+        let response = compress_response(response, &request);
+        assert_eq!(response.header("content-encoding"), Some("gzip"));
+        assert_eq!(gunzip(&response.body), BODY);
+    }
+
+    #[test]
+    fn test_compress_response_skips_images() {
+        let request = Request {
+            headers: [(String::from("accept-encoding"), String::from("gzip"))].into(),
+            ..Request::default()
+        };
+        let response = Response::new(200).with_header("Content-Type", "image/png").with_body(vec![0x89, b'P', b'N', b'G']);
+        let response = compress_response(response, &request);
+        assert_eq!(response.header("content-encoding"), None);
+        assert_eq!(response.body, vec![0x89, b'P', b'N', b'G']);
+    }
+
+    #[test]
+    fn test_compress_response_without_accept_encoding() {
+        let response = Response::text(200, BODY);
+        let response = compress_response(response, &Request::default());
+        assert_eq!(response.header("content-encoding"), None);
+    }
+}
@@ -0,0 +1,80 @@
+use std::fmt;
+use std::str::FromStr;
+use crate::http::request::ParseError;
+
+/// The HTTP request methods the server knows about.
+///
+/// Methods sort in the order they're declared here, which is the order they're listed
+/// in headers like `Allow`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Method {
+    Get,
+    Post,
+    Put,
+    Delete,
+    Head,
+    Options,
+}
+impl Method {
+    /// The method as it appears on the request line, e.g. `GET`.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Method::Get => "GET",
+            Method::Post => "POST",
+            Method::Put => "PUT",
+            Method::Delete => "DELETE",
+            Method::Head => "HEAD",
+            Method::Options => "OPTIONS",
+        }
+    }
+}
+
+impl FromStr for Method {
+    type Err = ParseError;
+
+    /// Parse a method off the request line. Methods are case-sensitive, so `get` is
+    /// not the same as `GET`.
+    fn from_str(method: &str) -> Result<Method, ParseError> {
+        match method {
+            "GET" => Ok(Method::Get),
+            "POST" => Ok(Method::Post),
+            "PUT" => Ok(Method::Put),
+            "DELETE" => Ok(Method::Delete),
+            "HEAD" => Ok(Method::Head),
+            "OPTIONS" => Ok(Method::Options),
+            _ => Err(ParseError::UnknownMethod(String::from(method))),
+        }
+    }
+}
+
+impl fmt::Display for Method {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_known_methods() {
+        assert_eq!("GET".parse(), Ok(Method::Get));
+        assert_eq!("POST".parse(), Ok(Method::Post));
+        assert_eq!("OPTIONS".parse(), Ok(Method::Options));
+    }
+
+    #[test]
+    fn test_parse_unknown_method() {
+        assert_eq!("get".parse::<Method>(), Err(ParseError::UnknownMethod(String::from("get"))));
+        assert!("BREW".parse::<Method>().is_err());
+    }
+
+    #[test]
+    fn test_method_round_trips_through_as_str() {
+        for method in [Method::Get, Method::Post, Method::Put, Method::Delete, Method::Head, Method::Options] {
+            assert_eq!(method.as_str().parse(), Ok(method));
+        }
+    }
+}
@@ -0,0 +1,120 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+/// The type used for files we don't recognise, telling the browser to treat them as
+/// opaque bytes.
+pub const DEFAULT_CONTENT_TYPE: &str = "application/octet-stream";
+
+/// Plain text, used for the small built-in responses that don't come from a file.
+pub const TEXT_PLAIN: &str = "text/plain; charset=utf-8";
+
+/// JSON, for API responses. JSON is always UTF-8 so it doesn't need a charset.
+pub const APPLICATION_JSON: &str = "application/json";
+
+/// Pick the `Content-Type` for a file based on its extension (ignoring case).
+///
+/// Unknown or missing extensions get `application/octet-stream`.
+pub fn content_type_for(path: &Path) -> &'static str {
+    let extension = match path.extension().and_then(|extension| extension.to_str()) {
+        Some(extension) => extension.to_ascii_lowercase(),
+        None => return DEFAULT_CONTENT_TYPE,
+    };
+
+    match extension.as_str() {
+        "html" | "htm" => "text/html; charset=utf-8",
+        "css" => "text/css; charset=utf-8",
+        "js" | "mjs" => "text/javascript; charset=utf-8",
+        "json" => APPLICATION_JSON,
+        "txt" => TEXT_PLAIN,
+        "xml" => "application/xml",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "svg" => "image/svg+xml",
+        "ico" => "image/x-icon",
+        "webp" => "image/webp",
+        "woff" => "font/woff",
+        "woff2" => "font/woff2",
+        "pdf" => "application/pdf",
+        "zip" => "application/zip",
+        "gz" => "application/gzip",
+        "mp4" => "video/mp4",
+        "mp3" => "audio/mpeg",
+        _ => DEFAULT_CONTENT_TYPE,
+    }
+}
+
+/// The `Content-Type` for each file extension, starting from the built-in ones in
+/// `content_type_for` with any additions or overrides on top.
+///
+/// Extensions are matched ignoring case, and are given without the dot, e.g.
+/// `registry.insert("wasm", "application/wasm")`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MimeRegistry {
+    overrides: HashMap<String, String>,
+}
+impl MimeRegistry {
+    /// Create a registry with just the built-in types.
+    pub fn new() -> MimeRegistry {
+        MimeRegistry::default()
+    }
+
+    /// Serve files ending in `.extension` as `content_type`, replacing the built-in type
+    /// if there is one.
+    pub fn insert(&mut self, extension: &str, content_type: &str) {
+        let extension = extension.trim_start_matches('.').to_ascii_lowercase();
+        self.overrides.insert(extension, String::from(content_type));
+    }
+
+    /// Pick the `Content-Type` for a file based on its extension, preferring anything
+    /// inserted over the built-in types.
+    pub fn content_type_for(&self, path: &Path) -> &str {
+        let overridden = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .and_then(|extension| self.overrides.get(&extension.to_ascii_lowercase()));
+        match overridden {
+            Some(content_type) => content_type,
+            None => content_type_for(path),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_content_type_for_known_extensions() {
+        assert_eq!(content_type_for(Path::new("index.html")), "text/html; charset=utf-8");
+        assert_eq!(content_type_for(Path::new("css/style.css")), "text/css; charset=utf-8");
+        assert_eq!(content_type_for(Path::new("app.js")), "text/javascript; charset=utf-8");
+        assert_eq!(content_type_for(Path::new("logo.png")), "image/png");
+        assert_eq!(content_type_for(Path::new("data.json")), "application/json");
+        assert_eq!(content_type_for(Path::new("icon.svg")), "image/svg+xml");
+    }
+
+    #[test]
+    fn test_content_type_ignores_case() {
+        assert_eq!(content_type_for(Path::new("PHOTO.JPG")), "image/jpeg");
+    }
+
+    #[test]
+    fn test_content_type_unknown_extension() {
+        assert_eq!(content_type_for(Path::new("archive.xyz")), DEFAULT_CONTENT_TYPE);
+        assert_eq!(content_type_for(Path::new("Makefile")), DEFAULT_CONTENT_TYPE);
+    }
+
+    #[test]
+    fn test_registry_overrides() {
+        let mut registry = MimeRegistry::new();
+        assert_eq!(registry.content_type_for(Path::new("app.wasm")), DEFAULT_CONTENT_TYPE);
+        registry.insert("wasm", "application/wasm");
+        registry.insert(".JS", "application/javascript");
+        assert_eq!(registry.content_type_for(Path::new("app.wasm")), "application/wasm");
+        assert_eq!(registry.content_type_for(Path::new("APP.js")), "application/javascript");
+        // Everything else still gets the built-in type.
+        assert_eq!(registry.content_type_for(Path::new("index.html")), "text/html; charset=utf-8");
+    }
+}
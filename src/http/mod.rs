@@ -0,0 +1,9 @@
+pub mod chunked;
+pub mod compression;
+pub mod date;
+pub mod method;
+pub mod mime;
+pub mod range;
+pub mod request;
+pub mod response;
+pub mod url;
@@ -0,0 +1,94 @@
+/// Parse a `Range` header like `bytes=0-1023` against a file of `file_len` bytes.
+///
+/// Handles the three single range forms:
+///
+/// * `bytes=start-end` for an explicit range (an `end` past the end of the file is cut short),
+/// * `bytes=start-` for everything from `start` on,
+/// * `bytes=-suffix` for the last `suffix` bytes.
+///
+/// Returns the first and last byte to send (both inclusive), or `None` if the header is
+/// malformed or the range doesn't overlap the file at all. Lists of several ranges
+/// aren't supported and also give `None`.
+pub fn parse_range(header: &str, file_len: u64) -> Option<(u64, u64)> {
+    let spec = header.trim().strip_prefix("bytes=")?;
+    let (start, end) = spec.split_once('-')?;
+    let (start, end) = (start.trim(), end.trim());
+
+    let (start, end) = match (start, end) {
+        // The last `suffix` bytes, or the whole file if it's shorter than that.
+        ("", suffix) => {
+            let suffix: u64 = suffix.parse().ok()?;
+            if suffix == 0 {
+                return None;
+            }
+            (file_len.saturating_sub(suffix), file_len.checked_sub(1)?)
+        }
+        (start, "") => (start.parse().ok()?, file_len.checked_sub(1)?),
+        (start, end) => {
+            let end: u64 = end.parse().ok()?;
+            (start.parse().ok()?, end.min(file_len.checked_sub(1)?))
+        }
+    };
+
+    if start > end || start >= file_len {
+        return None;
+    }
+    Some((start, end))
+}
+
+/// Whether a `Range` header is one we should try to honor at all.
+///
+/// Only byte ranges are defined, and we only serve a single range at a time. Anything
+/// else is ignored and the whole file is sent instead.
+pub fn is_supported_range(header: &str) -> bool {
+    header.trim().starts_with("bytes=") && !header.contains(',')
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_explicit_range() {
+        assert_eq!(parse_range("bytes=0-1023", 4000), Some((0, 1023)));
+        assert_eq!(parse_range("bytes=10-10", 4000), Some((10, 10)));
+        // The end is clamped to the end of the file.
+        assert_eq!(parse_range("bytes=3990-5000", 4000), Some((3990, 3999)));
+    }
+
+    #[test]
+    fn test_parse_open_ended_range() {
+        assert_eq!(parse_range("bytes=100-", 4000), Some((100, 3999)));
+    }
+
+    #[test]
+    fn test_parse_suffix_range() {
+        assert_eq!(parse_range("bytes=-500", 4000), Some((3500, 3999)));
+        // Asking for more than the whole file gets the whole file.
+        assert_eq!(parse_range("bytes=-5000", 4000), Some((0, 3999)));
+    }
+
+    #[test]
+    fn test_parse_unsatisfiable_range() {
+        assert_eq!(parse_range("bytes=4000-", 4000), None);
+        assert_eq!(parse_range("bytes=5000-6000", 4000), None);
+        assert_eq!(parse_range("bytes=20-10", 4000), None);
+        assert_eq!(parse_range("bytes=-0", 4000), None);
+        assert_eq!(parse_range("bytes=0-", 0), None);
+    }
+
+    #[test]
+    fn test_parse_malformed_range() {
+        assert_eq!(parse_range("bytes=abc", 4000), None);
+        assert_eq!(parse_range("items=0-10", 4000), None);
+        assert_eq!(parse_range("bytes=-", 4000), None);
+    }
+
+    #[test]
+    fn test_supported_range() {
+        assert!(is_supported_range("bytes=0-10"));
+        assert!(!is_supported_range("bytes=0-10, 20-30"));
+        assert!(!is_supported_range("items=0-10"));
+    }
+}
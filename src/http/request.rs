@@ -0,0 +1,354 @@
+use std::fmt;
+use std::str;
+use std::net::SocketAddr;
+use std::collections::HashMap;
+use crate::http::url::parse_query;
+
+/// The blank line separating the headers from the body, as it should be sent.
+pub const HEADER_TERMINATOR: &[u8] = b"\r\n\r\n";
+
+/// The HTTP versions we understand, as they appear on the request and status lines.
+pub const HTTP_1_0: &str = "HTTP/1.0";
+pub const HTTP_1_1: &str = "HTTP/1.1";
+
+/// A parsed HTTP request.
+///
+/// Holds the pieces of the request line, e.g. `GET /search?q=rust HTTP/1.1`, the
+/// headers and the body. The query string is split off the path and decoded into
+/// `query`. Header names are lowercased so lookups are case-insensitive. `params` is
+/// filled in by the router with anything captured by a route like `/users/:id`, and
+/// `connection` by the server with where the request came in from.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    pub query: HashMap<String, String>,
+    pub version: String,
+    pub headers: HashMap<String, String>,
+    pub body: Vec<u8>,
+    pub params: HashMap<String, String>,
+    pub connection: ConnectionContext,
+}
+impl Request {
+    /// Look up a query string parameter.
+    pub fn query_param(&self, key: &str) -> Option<&str> {
+        self.query.get(key).map(String::as_str)
+    }
+
+    /// Look up a path parameter captured by the route, e.g. `id` for `/users/:id`.
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.params.get(name).map(String::as_str)
+    }
+
+    /// Look up a header by name, ignoring case.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(&name.to_ascii_lowercase()).map(String::as_str)
+    }
+
+    /// The body as a string, or `None` if it isn't valid UTF-8.
+    pub fn body_str(&self) -> Option<&str> {
+        str::from_utf8(&self.body).ok()
+    }
+}
+
+/// The connection a request arrived on: the client's address and ours. Either is `None`
+/// if the connection doesn't have one, like an in-memory stream in a test.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ConnectionContext {
+    pub peer_addr: Option<SocketAddr>,
+    pub local_addr: Option<SocketAddr>,
+}
+
+/// Parse the request line, headers and body out of the raw bytes read off the stream.
+///
+/// The body is everything after the blank line following the headers. Lines may end in
+/// `\r\n` or a bare `\n`, and header lines without a colon are skipped.
+pub fn parse_request(buffer: &[u8]) -> Result<Request, ParseError> {
+    let mut request = parse_request_line(buffer)?;
+
+    // The headers are everything between the request line and the blank line.
+    let (head, body) = match header_end(buffer) {
+        Some(end) => buffer.split_at(end),
+        None => (buffer, &[][..]),
+    };
+    request.body = body.to_vec();
+    let head = str::from_utf8(head).map_err(|_| ParseError::InvalidUtf8)?;
+    let lines: Vec<&str> = head.lines().skip(1).collect();
+    request.headers = parse_headers(&lines);
+
+    Ok(request)
+}
+
+/// Find where the headers end (just past the blank line), if we've seen the end of them.
+///
+/// Lines should end in `\r\n`, but some clients only send a bare `\n`, so either is
+/// accepted (even mixed together) and the blank line can be `\r\n` or `\n` too.
+pub fn header_end(buffer: &[u8]) -> Option<usize> {
+    buffer.iter().enumerate().filter(|(_, &byte)| byte == b'\n').find_map(|(position, _)| {
+        let rest = &buffer[position + 1..];
+        if rest.starts_with(b"\n") {
+            Some(position + 2)
+        } else if rest.starts_with(b"\r\n") {
+            Some(position + 3)
+        } else {
+            None
+        }
+    })
+}
+
+/// Parse header lines like `Content-Type: text/html` into a map.
+///
+/// Each line is split on its first `:` so values may contain colons themselves (like a
+/// URL). Names are lowercased and both sides are trimmed. A header sent more than once
+/// has its values joined with `, `, and obsolete folded lines (starting with a space or
+/// tab) are appended to the previous header's value. Lines without a colon are skipped.
+pub fn parse_headers(lines: &[&str]) -> HashMap<String, String> {
+    let mut headers: HashMap<String, String> = HashMap::new();
+    // The header a folded line would continue.
+    let mut last: Option<String> = None;
+
+    for line in lines {
+        if line.starts_with([' ', '\t']) {
+            if let Some(value) = last.as_ref().and_then(|name| headers.get_mut(name)) {
+                value.push(' ');
+                value.push_str(line.trim());
+            }
+            continue;
+        }
+
+        let Some((name, value)) = line.split_once(':') else {
+            last = None;
+            continue;
+        };
+        let name = name.trim().to_ascii_lowercase();
+        let value = value.trim();
+
+        headers
+            .entry(name.clone())
+            .and_modify(|existing| {
+                existing.push_str(", ");
+                existing.push_str(value);
+            })
+            .or_insert_with(|| String::from(value));
+        last = Some(name);
+    }
+
+    headers
+}
+
+/// Parse the request line (the first line of the request) out of the raw bytes
+/// read off the stream.
+///
+/// The line is split on whitespace and must contain exactly three tokens: the
+/// method, the path and the HTTP version (which must look like `HTTP/1.1`). Only
+/// `HTTP/1.0` and `HTTP/1.1` are supported, any other version is an error.
+/// Trailing whitespace (including the `\r` of the `\r\n` line ending) is ignored.
+pub fn parse_request_line(buffer: &[u8]) -> Result<Request, ParseError> {
+    // Only the first line matters here, everything after it is headers and body.
+    let line = match buffer.iter().position(|&byte| byte == b'\n') {
+        Some(end) => &buffer[..end],
+        None => buffer,
+    };
+
+    let line = str::from_utf8(line).map_err(|_| ParseError::InvalidUtf8)?;
+    let line = line.trim_end();
+    if line.is_empty() {
+        return Err(ParseError::Empty);
+    }
+
+    let tokens: Vec<&str> = line.split_whitespace().collect();
+    match tokens[..] {
+        [method, target, version] if version.starts_with("HTTP/") => {
+            if version != HTTP_1_0 && version != HTTP_1_1 {
+                return Err(ParseError::UnsupportedVersion(String::from(version)));
+            }
+            let (path, query) = target.split_once('?').unwrap_or((target, ""));
+            Ok(Request {
+                method: String::from(method),
+                path: String::from(path),
+                query: parse_query(query),
+                version: String::from(version),
+                ..Request::default()
+            })
+        }
+        _ => Err(ParseError::Malformed(String::from(line))),
+    }
+}
+
+// Errors:
+// Everything that can go wrong while turning raw bytes into a Request.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseError {
+    // Nothing (or only whitespace) was sent.
+    Empty,
+    // The request line was not valid UTF-8.
+    InvalidUtf8,
+    // The request line did not have exactly three tokens.
+    Malformed(String),
+    // The method is not one we know about.
+    UnknownMethod(String),
+    // The request was for a version of HTTP other than 1.0 or 1.1.
+    UnsupportedVersion(String),
+}
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseError::Empty => write!(f, "The request was empty!"),
+            ParseError::InvalidUtf8 => write!(f, "The request line was not valid UTF-8!"),
+            ParseError::Malformed(line) => write!(f, "Malformed request line: {:?}", line),
+            ParseError::UnknownMethod(method) => write!(f, "Unknown request method: {:?}", method),
+            ParseError::UnsupportedVersion(version) => write!(f, "Unsupported HTTP version: {:?}", version),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_well_formed_get() {
+        let request = parse_request_line(b"GET /index.html HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .expect("A well formed request line should parse");
+        assert_eq!(request.method, "GET");
+        assert_eq!(request.path, "/index.html");
+        assert_eq!(request.version, "HTTP/1.1");
+    }
+
+    #[test]
+    fn test_parse_tolerates_trailing_whitespace() {
+        let request = parse_request_line(b"GET / HTTP/1.1   \r\n").expect("Trailing whitespace should be ignored");
+        assert_eq!(request.version, "HTTP/1.1");
+    }
+
+    #[test]
+    fn test_parse_two_tokens_is_malformed() {
+        assert_eq!(
+            parse_request_line(b"GET /\r\n"),
+            Err(ParseError::Malformed(String::from("GET /")))
+        );
+    }
+
+    #[test]
+    fn test_parse_http_versions() {
+        let request = parse_request_line(b"GET / HTTP/1.0\r\n").expect("HTTP/1.0 should be accepted");
+        assert_eq!(request.version, HTTP_1_0);
+        assert_eq!(
+            parse_request_line(b"GET / HTTP/2.0\r\n"),
+            Err(ParseError::UnsupportedVersion(String::from("HTTP/2.0")))
+        );
+        assert!(matches!(parse_request_line(b"GET / HTTP/1.2\r\n"), Err(ParseError::UnsupportedVersion(_))));
+    }
+
+    #[test]
+    fn test_parse_missing_http_version() {
+        assert!(parse_request_line(b"GET / index.html\r\n").is_err());
+    }
+
+    #[test]
+    fn test_parse_empty_buffer() {
+        assert_eq!(parse_request_line(b""), Err(ParseError::Empty));
+    }
+
+    #[test]
+    fn test_parse_mixed_case_headers() {
+        let request = parse_request(b"GET / HTTP/1.1\r\nHost: localhost\r\nuser-AGENT: curl\r\n\r\n")
+            .expect("Request should parse");
+        assert_eq!(request.header("host"), Some("localhost"));
+        assert_eq!(request.header("User-Agent"), Some("curl"));
+        assert_eq!(request.headers.get("user-agent").map(String::as_str), Some("curl"));
+    }
+
+    #[test]
+    fn test_parse_header_value_with_colons() {
+        let headers = parse_headers(&["Referer: http://localhost:7878/index.html"]);
+        assert_eq!(headers["referer"], "http://localhost:7878/index.html");
+    }
+
+    #[test]
+    fn test_parse_duplicate_headers_are_joined() {
+        let headers = parse_headers(&["Accept: text/html", "accept: application/json"]);
+        assert_eq!(headers["accept"], "text/html, application/json");
+    }
+
+    #[test]
+    fn test_parse_folded_header() {
+        let headers = parse_headers(&["X-Long: first", "  second", "\tthird", "Host: localhost"]);
+        assert_eq!(headers["x-long"], "first second third");
+        assert_eq!(headers["host"], "localhost");
+    }
+
+    #[test]
+    fn test_parse_skips_lines_without_colon() {
+        let headers = parse_headers(&["not a header", "  folded onto nothing", "Host: localhost"]);
+        assert_eq!(headers.len(), 1);
+    }
+
+    #[test]
+    fn test_parse_bare_lf_headers() {
+        let request = parse_request(b"POST / HTTP/1.1\nHost: localhost\r\nContent-Length: 5\n\nhello")
+            .expect("Request should parse");
+        assert_eq!(request.header("host"), Some("localhost"));
+        assert_eq!(request.header("content-length"), Some("5"));
+        assert_eq!(request.body, b"hello");
+    }
+
+    #[test]
+    fn test_header_end() {
+        assert_eq!(header_end(b"GET / HTTP/1.1\r\nHost: a\r\n\r\nbody"), Some(27));
+        assert_eq!(header_end(b"GET / HTTP/1.1\nHost: a\n\nbody"), Some(24));
+        assert_eq!(header_end(b"GET / HTTP/1.1\nHost: a\n\r\nbody"), Some(25));
+        assert_eq!(header_end(b"GET / HTTP/1.1\r\nHost: a\r\n\r"), None);
+        assert_eq!(header_end(b"GET / HTTP/1.1\r\nHost: a\r\n"), None);
+    }
+
+    #[test]
+    fn test_parse_skips_header_without_colon() {
+        let request = parse_request(b"GET / HTTP/1.1\nnot a header\nHost: localhost\n\n").expect("Request should parse");
+        assert_eq!(request.headers.len(), 1);
+        assert_eq!(request.header("host"), Some("localhost"));
+    }
+
+    #[test]
+    fn test_parse_ignores_body() {
+        let request = parse_request(b"POST / HTTP/1.1\r\nContent-Length: 9\r\n\r\nkey:value")
+            .expect("Request should parse");
+        assert_eq!(request.headers.len(), 1);
+    }
+
+    #[test]
+    fn test_parse_query_string_off_path() {
+        let request = parse_request_line(b"GET /search?q=rust%20lang&page=2&x= HTTP/1.1\r\n")
+            .expect("Request should parse");
+        assert_eq!(request.path, "/search");
+        assert_eq!(request.query_param("q"), Some("rust lang"));
+        assert_eq!(request.query_param("page"), Some("2"));
+        assert_eq!(request.query_param("x"), Some(""));
+        assert_eq!(request.query_param("missing"), None);
+    }
+
+    #[test]
+    fn test_parse_path_without_query() {
+        let request = parse_request_line(b"GET /index.html HTTP/1.1\r\n").expect("Request should parse");
+        assert_eq!(request.path, "/index.html");
+        assert!(request.query.is_empty());
+    }
+
+    #[test]
+    fn test_parse_body() {
+        let request = parse_request(b"POST /form HTTP/1.1\r\nContent-Length: 11\r\n\r\nhello world")
+            .expect("Request should parse");
+        assert_eq!(request.body, b"hello world");
+        assert_eq!(request.body_str(), Some("hello world"));
+
+        let request = parse_request(b"GET / HTTP/1.1\r\n\r\n").expect("Request should parse");
+        assert!(request.body.is_empty());
+    }
+
+    #[test]
+    fn test_body_str_invalid_utf8() {
+        let request = parse_request(b"POST / HTTP/1.1\r\nContent-Length: 2\r\n\r\n\xff\xfe").expect("Request should parse");
+        assert_eq!(request.body_str(), None);
+    }
+}
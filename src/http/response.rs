@@ -0,0 +1,427 @@
+use std::fmt;
+use std::io;
+use std::io::prelude::*;
+use std::sync::{Arc, Mutex};
+use std::collections::HashMap;
+use crate::http::chunked::copy_chunked;
+use crate::http::mime::{APPLICATION_JSON, TEXT_PLAIN};
+use crate::http::request::{HTTP_1_0, HTTP_1_1};
+
+/// An HTTP response waiting to be written to the stream.
+///
+/// `Content-Length` is worked out from the body when the response is serialized, so it
+/// doesn't need to be set by hand. Responses are sent as `HTTP/1.1` unless the version
+/// is changed to match the request's with `with_version`. A response built with
+/// `from_reader` has its body read as it's written instead, and `body` is left empty.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Response {
+    pub version: String,
+    pub status: u16,
+    pub headers: HashMap<String, String>,
+    pub body: Vec<u8>,
+    body_reader: Option<BodyReader>,
+}
+impl Response {
+    /// Create a response with the given status code, no headers and an empty body.
+    pub fn new(status: u16) -> Response {
+        Response {
+            version: String::from(HTTP_1_1),
+            status,
+            headers: HashMap::new(),
+            body: Vec::new(),
+            body_reader: None,
+        }
+    }
+
+    /// Create a response whose body is read from `reader` while it's being written to the
+    /// stream, rather than held in memory first. For generated or proxied content, whose
+    /// length isn't known up front, so it's sent with `Transfer-Encoding: chunked`.
+    ///
+    /// The body can only be sent once, clones of the response share the same reader. If
+    /// reading fails partway through the headers have already gone, so the error is
+    /// returned from `write_to` and the connection should be dropped.
+    pub fn from_reader(status: u16, reader: impl Read + Send + 'static) -> Response {
+        let mut response = Response::new(status).with_header("Transfer-Encoding", "chunked");
+        response.body_reader = Some(BodyReader(Arc::new(Mutex::new(Some(Box::new(reader))))));
+        response
+    }
+
+    /// Create a plain text response, handy for short messages and errors.
+    pub fn text(status: u16, body: &str) -> Response {
+        Response::new(status)
+            .with_header("Content-Type", TEXT_PLAIN)
+            .with_body(body)
+    }
+
+    /// Create a JSON response from an already serialized body, for API endpoints.
+    pub fn json(status: u16, body: &str) -> Response {
+        Response::new(status)
+            .with_header("Content-Type", APPLICATION_JSON)
+            .with_body(body)
+    }
+
+    /// Set a header, replacing any earlier value for the same name.
+    pub fn with_header(mut self, name: &str, value: &str) -> Response {
+        self.headers.insert(String::from(name), String::from(value));
+        self
+    }
+
+    /// Add each of `headers` that the response doesn't already have, so headers set for
+    /// this particular response win over the defaults. Names are compared ignoring case.
+    pub fn with_default_headers(mut self, headers: &[(String, String)]) -> Response {
+        for (name, value) in headers {
+            if self.header(name).is_none() {
+                self.headers.insert(name.clone(), value.clone());
+            }
+        }
+        self
+    }
+
+    /// Set the HTTP version to send on the status line, e.g. `HTTP/1.0`.
+    pub fn with_version(mut self, version: &str) -> Response {
+        self.version = String::from(version);
+        self
+    }
+
+    /// Set the body.
+    pub fn with_body(mut self, body: impl Into<Vec<u8>>) -> Response {
+        self.body = body.into();
+        self
+    }
+
+    /// Look up a header by name, ignoring case.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Serialize the response into the bytes to write to the stream: the status line,
+    /// the headers (including `Content-Length`), a blank line and then the body.
+    ///
+    /// Headers are written sorted by name so the output is predictable.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = self.to_head_bytes();
+        bytes.extend_from_slice(&self.body);
+        bytes
+    }
+
+    /// Write the whole response to the stream, returning how many bytes that came to.
+    ///
+    /// A body from `from_reader` is streamed in chunks, except to an `HTTP/1.0` client
+    /// (which wouldn't understand them) where it's read in full and sent with a
+    /// `Content-Length` instead.
+    pub fn write_to<W: Write>(&self, stream: &mut W) -> io::Result<usize> {
+        if let Some(mut reader) = self.body_reader.as_ref().and_then(BodyReader::take) {
+            if self.version == HTTP_1_0 {
+                let mut response = self.clone();
+                response.headers.retain(|name, _| !name.eq_ignore_ascii_case("transfer-encoding"));
+                reader.read_to_end(&mut response.body)?;
+                response.body_reader = None;
+                return response.write_to(stream);
+            }
+            let head = self.to_head_bytes();
+            stream.write_all(&head)?;
+            return Ok(head.len() + copy_chunked(&mut reader, stream)? as usize);
+        }
+
+        let bytes = self.to_bytes();
+        stream.write_all(&bytes)?;
+        Ok(bytes.len())
+    }
+
+    /// Like `write_to`, but without the body, for a `HEAD` request.
+    pub fn write_head_to<W: Write>(&self, stream: &mut W) -> io::Result<usize> {
+        let bytes = self.to_head_bytes();
+        stream.write_all(&bytes)?;
+        Ok(bytes.len())
+    }
+
+    /// Serialize everything but the body, as sent in reply to a `HEAD` request.
+    ///
+    /// `Content-Length` still describes the body that would have been sent. `204 No
+    /// Content` and `304 Not Modified` responses leave it off, since they never describe
+    /// a body of their own, as do responses with a `Transfer-Encoding` (whose body is
+    /// framed by something like `write_chunked` instead).
+    pub fn to_head_bytes(&self) -> Vec<u8> {
+        self.to_head_bytes_with_length(self.body.len() as u64)
+    }
+
+    /// Serialize everything but the body, giving `Content-Length` as `content_length`
+    /// rather than the length of `body`. For bodies that are written out separately,
+    /// like a file streamed from disk.
+    pub fn to_head_bytes_with_length(&self, content_length: u64) -> Vec<u8> {
+        let mut headers: Vec<(&String, &String)> = self
+            .headers
+            .iter()
+            .filter(|(name, _)| !name.eq_ignore_ascii_case("content-length"))
+            .collect();
+        headers.sort();
+
+        let mut head = format!("{} {} {}\r\n", self.version, self.status, reason_phrase(self.status));
+        if !matches!(self.status, 204 | 304) && self.header("transfer-encoding").is_none() {
+            head.push_str(&format!("Content-Length: {}\r\n", content_length));
+        }
+        for (name, value) in headers {
+            head.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        head.push_str("\r\n");
+
+        head.into_bytes()
+    }
+}
+
+/// The source of a body from `Response::from_reader`, taken out the first time the
+/// response is written.
+#[derive(Clone)]
+struct BodyReader(Arc<Mutex<Option<Box<dyn Read + Send>>>>);
+impl BodyReader {
+    fn take(&self) -> Option<Box<dyn Read + Send>> {
+        self.0.lock().unwrap_or_else(|err| err.into_inner()).take()
+    }
+}
+impl fmt::Debug for BodyReader {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "BodyReader")
+    }
+}
+// Two readers are only the same if they're the same reader.
+impl PartialEq for BodyReader {
+    fn eq(&self, other: &BodyReader) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+impl Eq for BodyReader {}
+
+/// A minimal HTML error page for any status, e.g. `error_response(404)` for a page
+/// titled "404 Not Found".
+///
+/// This is what gets sent whenever something goes wrong and there's no custom page for
+/// it (or the custom page can't be read).
+pub fn error_response(status: u16) -> Response {
+    let reason = reason_phrase(status);
+    let body = format!(
+        "<!DOCTYPE html>\n<html lang=\"en\">\n  <head>\n    <meta charset=\"utf-8\">\n    <title>{status} {reason}</title>\n  </head>\n  <body>\n    <h1>{status} {reason}</h1>\n  </body>\n</html>\n"
+    );
+    Response::new(status)
+        .with_header("Content-Type", "text/html; charset=utf-8")
+        .with_body(body)
+}
+
+/// A `400 Bad Request` response, for input that isn't valid HTTP at all.
+pub fn bad_request_response() -> Response {
+    error_response(400)
+}
+
+/// A bare `500 Internal Server Error` response, for when something went wrong on our end
+/// (and so we can't rely on being able to read an error page either).
+pub fn internal_server_error() -> Response {
+    error_response(500)
+}
+
+/// The canonical reason phrase for a status code, as sent after it on the status line,
+/// e.g. `Not Found` for 404. Codes we don't know get a generic phrase for their class.
+pub fn reason_phrase(status: u16) -> &'static str {
+    match status {
+        100 => "Continue",
+        200 => "OK",
+        201 => "Created",
+        202 => "Accepted",
+        204 => "No Content",
+        206 => "Partial Content",
+        301 => "Moved Permanently",
+        302 => "Found",
+        304 => "Not Modified",
+        307 => "Temporary Redirect",
+        308 => "Permanent Redirect",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        408 => "Request Timeout",
+        411 => "Length Required",
+        413 => "Payload Too Large",
+        414 => "URI Too Long",
+        416 => "Range Not Satisfiable",
+        417 => "Expectation Failed",
+        429 => "Too Many Requests",
+        431 => "Request Header Fields Too Large",
+        500 => "Internal Server Error",
+        501 => "Not Implemented",
+        502 => "Bad Gateway",
+        503 => "Service Unavailable",
+        504 => "Gateway Timeout",
+        505 => "HTTP Version Not Supported",
+        _ => match status / 100 {
+            1 => "Informational",
+            2 => "Success",
+            3 => "Redirection",
+            4 => "Client Error",
+            5 => "Server Error",
+            _ => "Unknown",
+        },
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::chunked::decode_chunked;
+
+    #[test]
+    fn test_ok_response_to_bytes() {
+        let response = Response::new(200)
+            .with_header("Content-Type", "text/html")
+            .with_body("<h1>Hello!</h1>");
+        assert_eq!(
+            String::from_utf8(response.to_bytes()).unwrap(),
+            "HTTP/1.1 200 OK\r\nContent-Length: 15\r\nContent-Type: text/html\r\n\r\n<h1>Hello!</h1>"
+        );
+    }
+
+    #[test]
+    fn test_write_to_counts_bytes() {
+        let response = Response::new(200)
+            .with_header("Content-Type", "text/html")
+            .with_body("<h1>Hello!</h1>");
+        let mut written = Vec::new();
+        assert_eq!(response.write_to(&mut written).unwrap(), response.to_bytes().len());
+        assert_eq!(written, response.to_bytes());
+
+        let mut written = Vec::new();
+        assert_eq!(response.write_head_to(&mut written).unwrap(), response.to_head_bytes().len());
+        assert_eq!(written, response.to_head_bytes());
+    }
+
+    #[test]
+    fn test_with_default_headers() {
+        let defaults = vec![
+            (String::from("X-Frame-Options"), String::from("DENY")),
+            (String::from("content-type"), String::from("application/octet-stream")),
+        ];
+        let response = Response::text(200, "hi").with_default_headers(&defaults);
+        assert_eq!(response.header("x-frame-options"), Some("DENY"));
+        // The response's own Content-Type is kept.
+        assert_eq!(response.header("Content-Type"), Some(TEXT_PLAIN));
+        assert_eq!(response.headers.len(), 2);
+    }
+
+    #[test]
+    fn test_response_version() {
+        let response = Response::new(200).with_version("HTTP/1.0");
+        assert_eq!(response.to_bytes(), b"HTTP/1.0 200 OK\r\nContent-Length: 0\r\n\r\n");
+    }
+
+    #[test]
+    fn test_content_length_is_computed_from_body() {
+        // A stale Content-Length set by hand is ignored in favour of the real one.
+        let response = Response::new(404).with_header("content-length", "1").with_body("not found");
+        let bytes = String::from_utf8(response.to_bytes()).unwrap();
+        assert!(bytes.starts_with("HTTP/1.1 404 Not Found\r\nContent-Length: 9\r\n\r\n"));
+        assert_eq!(bytes.matches("ength").count(), 1);
+    }
+
+    #[test]
+    fn test_empty_response_to_bytes() {
+        assert_eq!(Response::new(200).to_bytes(), b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n");
+    }
+
+    #[test]
+    fn test_head_bytes_omit_body() {
+        let response = Response::text(200, "hello");
+        assert_eq!(
+            response.to_head_bytes(),
+            b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\nContent-Type: text/plain; charset=utf-8\r\n\r\n"
+        );
+    }
+
+    #[test]
+    fn test_json_response() {
+        let response = Response::json(201, r#"{"id":42}"#);
+        assert_eq!(response.header("Content-Type"), Some("application/json"));
+        assert_eq!(
+            response.to_bytes(),
+            b"HTTP/1.1 201 Created\r\nContent-Length: 9\r\nContent-Type: application/json\r\n\r\n{\"id\":42}"
+        );
+    }
+
+    #[test]
+    fn test_header_lookup_ignores_case() {
+        let response = Response::text(200, "hello");
+        assert_eq!(response.header("content-type"), Some(TEXT_PLAIN));
+        assert_eq!(response.header("X-Missing"), None);
+    }
+
+    #[test]
+    fn test_not_modified_has_no_content_length() {
+        let response = Response::new(304).with_header("ETag", "\"abc\"");
+        assert_eq!(response.to_bytes(), b"HTTP/1.1 304 Not Modified\r\nETag: \"abc\"\r\n\r\n");
+    }
+
+    #[test]
+    fn test_head_bytes_with_length() {
+        let response = Response::new(200).with_body("ignored");
+        assert_eq!(response.to_head_bytes_with_length(4096), b"HTTP/1.1 200 OK\r\nContent-Length: 4096\r\n\r\n");
+    }
+
+    #[test]
+    fn test_chunked_response_has_no_content_length() {
+        let response = Response::new(200).with_header("Transfer-Encoding", "chunked");
+        assert_eq!(response.to_head_bytes(), b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n");
+    }
+
+    #[test]
+    fn test_from_reader_streams_body() {
+        let source = b"streamed straight from the reader".repeat(5000);
+        let response = Response::from_reader(200, io::Cursor::new(source.clone()));
+        let mut written = Vec::new();
+        assert_eq!(response.write_to(&mut written).unwrap(), written.len());
+
+        let head = b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n";
+        assert!(written.starts_with(head));
+        let (body, _) = decode_chunked(&written[head.len()..], usize::MAX).unwrap().expect("The body should be complete");
+        assert_eq!(body, source);
+    }
+
+    #[test]
+    fn test_from_reader_for_http_1_0() {
+        let response = Response::from_reader(200, io::Cursor::new("hello")).with_version(HTTP_1_0);
+        let mut written = Vec::new();
+        response.write_to(&mut written).unwrap();
+        assert_eq!(written, b"HTTP/1.0 200 OK\r\nContent-Length: 5\r\n\r\nhello");
+    }
+
+    #[test]
+    fn test_reason_phrase() {
+        assert_eq!(reason_phrase(200), "OK");
+        assert_eq!(reason_phrase(404), "Not Found");
+        assert_eq!(reason_phrase(500), "Internal Server Error");
+        assert_eq!(reason_phrase(503), "Service Unavailable");
+        assert_eq!(reason_phrase(418), "Client Error");
+        assert_eq!(reason_phrase(99), "Unknown");
+        assert_eq!(Response::new(405).to_bytes(), b"HTTP/1.1 405 Method Not Allowed\r\nContent-Length: 0\r\n\r\n");
+    }
+
+    #[test]
+    fn test_error_response() {
+        let response = error_response(404);
+        let bytes = String::from_utf8(response.to_bytes()).unwrap();
+        assert!(bytes.starts_with("HTTP/1.1 404 Not Found\r\n"));
+        assert_eq!(response.header("Content-Type"), Some("text/html; charset=utf-8"));
+        assert!(bytes.contains("<h1>404 Not Found</h1>"));
+
+        let response = error_response(416);
+        assert!(response.to_bytes().starts_with(b"HTTP/1.1 416 Range Not Satisfiable\r\n"));
+        assert!(String::from_utf8(response.body).unwrap().contains("Range Not Satisfiable"));
+    }
+
+    #[test]
+    fn test_internal_server_error() {
+        let response = internal_server_error();
+        assert_eq!(response.status, 500);
+        assert!(String::from_utf8(response.body).unwrap().contains("Internal Server Error"));
+    }
+}
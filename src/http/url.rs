@@ -0,0 +1,141 @@
+use std::collections::HashMap;
+
+/// Parse a query string like `q=rust&page=2` (without the leading `?`) into a map.
+///
+/// Keys and values are URL-decoded, including `+` as a space. A key without an `=`
+/// gets an empty value, and if a key is repeated the last value wins.
+pub fn parse_query(query: &str) -> HashMap<String, String> {
+    query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            (decode_query_component(key), decode_query_component(value))
+        })
+        .collect()
+}
+
+/// Parse an `application/x-www-form-urlencoded` request body like `name=ferris&age=7`.
+///
+/// Form bodies use the same encoding as query strings, so they're decoded exactly like
+/// `parse_query` does: `+` is a space, keys without a value get an empty one, and the
+/// last of any repeated keys wins. A trailing newline, which some clients tack on, is
+/// ignored.
+pub fn parse_form_body(body: &str) -> HashMap<String, String> {
+    parse_query(body.trim_end_matches(['\r', '\n']))
+}
+
+// Decode a key or value from a query string. Unlike in a path, `+` means a space here.
+fn decode_query_component(component: &str) -> String {
+    percent_decode(&component.replace('+', " "))
+}
+
+/// Decode the `%XX` escapes in a request path, e.g. `/my%20file.html` to `/my file.html`.
+///
+/// Escapes that aren't followed by two hex digits (like a trailing `%2`) are left as they
+/// are, and `+` is left alone since it only means a space in query strings. Anything that
+/// doesn't decode to valid UTF-8 is replaced with `U+FFFD`.
+pub fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            if let Some(byte) = bytes.get(i + 1..i + 3).and_then(hex_byte) {
+                decoded.push(byte);
+                i += 3;
+                continue;
+            }
+        }
+        decoded.push(bytes[i]);
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+// Turn two hex digits into the byte they represent.
+fn hex_byte(digits: &[u8]) -> Option<u8> {
+    // from_str_radix would take a leading sign, like the `+` in `%+f`.
+    if !digits.iter().all(u8::is_ascii_hexdigit) {
+        return None;
+    }
+    let digits = std::str::from_utf8(digits).ok()?;
+    u8::from_str_radix(digits, 16).ok()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_query_multiple_params() {
+        let query = parse_query("q=rust&page=2");
+        assert_eq!(query.len(), 2);
+        assert_eq!(query["q"], "rust");
+        assert_eq!(query["page"], "2");
+    }
+
+    #[test]
+    fn test_parse_query_empty_values() {
+        let query = parse_query("x=&flag");
+        assert_eq!(query["x"], "");
+        assert_eq!(query["flag"], "");
+    }
+
+    #[test]
+    fn test_parse_query_percent_encoded() {
+        let query = parse_query("q=hello%20world&name=a+b&sym=%26%3D");
+        assert_eq!(query["q"], "hello world");
+        assert_eq!(query["name"], "a b");
+        assert_eq!(query["sym"], "&=");
+    }
+
+    #[test]
+    fn test_parse_query_repeated_key_last_wins() {
+        assert_eq!(parse_query("tag=a&tag=b")["tag"], "b");
+    }
+
+    #[test]
+    fn test_decode_leaves_malformed_escapes() {
+        assert_eq!(percent_decode("100%"), "100%");
+        assert_eq!(percent_decode("%zz"), "%zz");
+    }
+
+    #[test]
+    fn test_parse_form_body() {
+        let form = parse_form_body("name=Ferris+the+Crab&email=ferris%40rust-lang.org&note=&name%21=%E2%9C%93");
+        assert_eq!(form.len(), 4);
+        assert_eq!(form["name"], "Ferris the Crab");
+        assert_eq!(form["email"], "ferris@rust-lang.org");
+        assert_eq!(form["note"], "");
+        assert_eq!(form["name!"], "\u{2713}");
+    }
+
+    #[test]
+    fn test_parse_form_body_repeated_keys_match_query() {
+        let body = "colour=red&colour=blue";
+        assert_eq!(parse_form_body(body), parse_query(body));
+        assert_eq!(parse_form_body(body)["colour"], "blue");
+    }
+
+    #[test]
+    fn test_parse_empty_form_body() {
+        assert!(parse_form_body("").is_empty());
+    }
+
+    #[test]
+    fn test_percent_decode() {
+        assert_eq!(percent_decode("/my%20file.html"), "/my file.html");
+        assert_eq!(percent_decode("/a%2Fb%2fc"), "/a/b/c");
+        assert_eq!(percent_decode("/caf%C3%A9+menu"), "/café+menu");
+    }
+
+    #[test]
+    fn test_percent_decode_leaves_malformed_escapes() {
+        assert_eq!(percent_decode("/file%2"), "/file%2");
+        assert_eq!(percent_decode("/100%"), "/100%");
+        assert_eq!(percent_decode("/%zz"), "/%zz");
+        assert_eq!(percent_decode("/a%+fb"), "/a%+fb");
+    }
+}
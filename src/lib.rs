@@ -0,0 +1,5 @@
+// Library modules shared by the server binary (and its tests).
+pub mod config;
+pub mod http;
+pub mod server;
+pub mod thread_pool;
@@ -1,37 +1,51 @@
-// Import modules
-mod thread_pool;
-
-use std::fs;
-use std::env;
 use std::thread;
 use std::process;
-use std::io::prelude::*;
+use std::sync::Arc;
 use std::time::Duration;
-use std::net::TcpStream;
 use std::net::TcpListener;
-use crate::thread_pool::thread_pool::{ThreadPool};
+use std::sync::atomic::{AtomicBool, Ordering};
+use rust_web_server::config::Config;
+use rust_web_server::http::method::Method;
+use rust_web_server::http::mime::MimeRegistry;
+use rust_web_server::http::request::Request;
+use rust_web_server::http::response::Response;
+use rust_web_server::server::auth::BasicAuth;
+use rust_web_server::server::connection::{serve_page, Server};
+use rust_web_server::server::cors::CorsConfig;
+use rust_web_server::server::listener::{accept_all, reject_connection, shut_down_when_drained, ConnectionLimit};
+use rust_web_server::server::router::Router;
+use rust_web_server::server::static_files::INDEX_PAGE;
+use rust_web_server::server::tls::{accept_tls, load_tls_config};
+use rust_web_server::server::virtual_hosts::VirtualHosts;
+use rust_web_server::thread_pool::thread_pool::{ThreadPool};
 
 fn main() {
 
-    // Grab the port environment variable. If it is unset default to 7878.
-    let port = env::var("PORT").unwrap_or(String::from("7878"));
-
-    // Build up the address using configurable port.
-    let address = format!("127.0.0.1:{}", port);
-
-    // Create the listener by binding to the address and port.
-    let listener = TcpListener::bind(&address).unwrap_or_else(|err| {
-        // If we failed to bind to the port, inform the user.
-        eprintln!("Failed to bind to {}!\nSee Error:\n{}", &address, err);
-        // Exit since failing to create the listener means we can't serve anything.
+    // Read and check all of the configuration up front, see Config for what can be set.
+    let config = Config::from_env().unwrap_or_else(|err| {
+        eprintln!("{}", err);
         process::exit(2);
     });
 
-    // Let the user we successfully bound to the port.
-    println!("Listenting on port {}...", port);
+    // Create a listener for each address by binding to it and the port.
+    let listeners: Vec<TcpListener> = config
+        .addresses()
+        .into_iter()
+        .map(|address| {
+            let listener = TcpListener::bind(address).unwrap_or_else(|err| {
+                // If we failed to bind to the port, inform the user.
+                eprintln!("Failed to bind to {}!\nSee Error:\n{}", &address, err);
+                // Exit since failing to create the listener means we can't serve anything.
+                process::exit(2);
+            });
+            // Let the user we successfully bound to the port.
+            println!("Listening on {}...", address);
+            listener
+        })
+        .collect();
 
     // Setup the thread pool
-    let tp = match ThreadPool::new(5) {
+    let tp = match ThreadPool::new(config.workers) {
         Ok(tp) => tp,
         Err(err) => {
             // If we failed to initialize the threadpool.
@@ -40,52 +54,152 @@ fn main() {
             process::exit(2);
         }
     };
+    println!("Started {} workers.", tp.size());
+
+    // Cap how many connections are handled at once.
+    let limit = ConnectionLimit::new(config.max_connections);
 
-    // Iterate through each connection attempt being recieved on the listener.
-    for stream in listener.incoming() {
-        tp.execute(|| {
-            handle_connection(stream.unwrap());
+    // Register the dynamic routes. The router is shared by every worker.
+    let mut router = Router::new();
+    let index_page = config.document_root.join(INDEX_PAGE);
+    router.route(Method::Get, "/sleep", move |_: &Request| {
+        // Simulate a slow request so we can see the thread pool at work.
+        thread::sleep(Duration::from_secs(5));
+        serve_page(200, &index_page)
+    });
+    // `POST /admin/drain` stops taking new connections and shuts down once the ones in
+    // flight have finished, for deploys that shouldn't drop anyone's request.
+    if let Some((user, pass)) = &config.admin {
+        router.middleware(BasicAuth::new("admin", user, pass).with_prefix("/admin/"));
+        let limit = limit.clone();
+        router.route(Method::Post, "/admin/drain", move |_: &Request| {
+            println!("Draining connections before shutting down.");
+            limit.start_draining();
+            Response::text(202, "draining")
         });
     }
-    
-}
-
-fn handle_connection(mut stream: TcpStream) {
-
-    println!("Handling connection...");
-    // Accept a mutable TcpStream (needs to be mutable since it keeps track (internally) 
-    // of how much of the request we've read.)
+    let mut server = Server::new(&config.document_root, router)
+        .with_timeouts(config.read_timeout, config.write_timeout)
+        .with_max_body_size(config.max_body_size)
+        .with_max_header_size(config.max_header_size)
+        .with_keep_alive(config.keep_alive_max, config.keep_alive_timeout)
+        .with_directory_listing(config.directory_listing)
+        .with_spa_mode(config.spa_mode)
+        .with_pool_stats(tp.stats())
+        .with_default_headers(config.response_headers.clone())
+        .with_server_header(config.server_header)
+        .with_nodelay(config.nodelay)
+        .with_log_format(config.log_format);
+
+    // Slow down clients making too many requests.
+    if let Some((rate, burst)) = config.rate_limit {
+        server = server.with_rate_limit(rate, burst);
+    }
 
-    // Create a buffer big enough for handling simple requests.
-    let mut buffer = [0; 1024];
+    // Lock the server down to just the configured methods.
+    if let Some(methods) = &config.allowed_methods {
+        server = server.with_allowed_methods(methods.clone());
+    }
 
-    // Read the bytes off the stream buffer and store them in the buffer
-    stream.read(&mut buffer).unwrap();
+    // Serve each configured host from its own root, and everyone else from the main one.
+    if !config.virtual_hosts.is_empty() {
+        let mut hosts = VirtualHosts::new(&config.document_root);
+        for (host, root) in &config.virtual_hosts {
+            hosts.insert(host, root);
+        }
+        server = server.with_virtual_hosts(hosts);
+    }
 
-    // Provide a simple output of the buffer contents.
-    // println!("Request: {}", String::from_utf8_lossy(&buffer[..]));
+    // Serve any extra file types we've been told about.
+    if !config.mime_types.is_empty() {
+        let mut mime_types = MimeRegistry::new();
+        for (extension, content_type) in &config.mime_types {
+            mime_types.insert(extension, content_type);
+        }
+        server = server.with_mime_types(mime_types);
+    }
 
+    // Keep hot files in memory if asked to.
+    if let Some(size) = config.file_cache_size {
+        server = server.with_file_cache(size);
+    }
 
-    let (status_line, filename) = if buffer.starts_with(b"GET / HTTP/1.1\r\n") {
-        ("HTTP/1.1 200 OK", "hello.html")
-    } else if buffer.starts_with(b"GET /sleep HTTP/1.1\r\n") {
-        thread::sleep(Duration::from_secs(5));
-        ("HTTP/1.1 200 OK", "hello.html")
-    } else {
-        ("HTTP/1.1 404 NOT FOUND", "404.html")
+    // Cross-origin requests are only allowed from the configured origins.
+    if let Some(origins) = &config.cors_allowed_origins {
+        let origins: Vec<&str> = origins.iter().map(String::as_str).collect();
+        server = server.with_cors(CorsConfig::new(&origins));
+    }
+    let server = Arc::new(server);
+
+    // Serve over HTTPS if we were given a certificate and key, plain HTTP otherwise.
+    let tls_config = match &config.tls {
+        Some((cert, key)) => {
+            let config = load_tls_config(cert, key).unwrap_or_else(|err| {
+                eprintln!("{}", err);
+                process::exit(2);
+            });
+            println!("Serving over HTTPS.");
+            Some(config)
+        }
+        None => None,
     };
 
-    let contents = fs::read_to_string(filename).unwrap();
+    // Flip the shutdown flag on Ctrl-C so the accept loop below stops taking connections.
+    let shutdown = Arc::new(AtomicBool::new(false));
+    {
+        let shutdown = Arc::clone(&shutdown);
+        ctrlc::set_handler(move || shutdown.store(true, Ordering::SeqCst)).unwrap_or_else(|err| {
+            eprintln!("Failed to install the Ctrl-C handler: {}", err);
+            process::exit(2);
+        });
+    }
 
-    let response = format!(
-        "{}\r\nContent-Length: {}\r\n\r\n{}",
-        status_line,
-        contents.len(),
-        contents
-    );
+    // Once we've been asked to drain, stop accepting when the last connection is done.
+    {
+        let limit = limit.clone();
+        let shutdown = Arc::clone(&shutdown);
+        thread::spawn(move || shut_down_when_drained(&limit, &shutdown));
+    }
 
-    stream.write(response.as_bytes()).unwrap();
-    stream.flush().unwrap();
+    // Iterate through each connection attempt being recieved on any of the listeners.
+    // Each connection is handed off to the pool so a slow request doesn't hold up the rest.
+    let result = accept_all(&listeners, &shutdown, |stream| {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(err) => {
+                // A failed accept only affects that one client, keep listening for the rest.
+                eprintln!("Failed to accept connection: {}", err);
+                return;
+            }
+        };
+        // Turn the connection away if we're already handling as many as we're allowed, or
+        // are draining.
+        let Some(guard) = limit.try_acquire() else {
+            if let Err(err) = reject_connection(stream) {
+                eprintln!("Failed to reject connection: {}", err);
+            }
+            return;
+        };
+        let server = Arc::clone(&server);
+        let tls_config = tls_config.clone();
+        tp.execute(move || {
+            match tls_config {
+                Some(config) => match accept_tls(&config, stream) {
+                    Ok(stream) => server.handle_connection(stream),
+                    Err(err) => eprintln!("Failed to start TLS session: {}", err),
+                },
+                None => server.handle_connection(stream),
+            }
+            // Free up the slot for the next connection.
+            drop(guard);
+        });
+    });
+    if let Err(err) = result {
+        eprintln!("Failed to accept connections: {}", err);
+        process::exit(2);
+    }
 
-    println!("Sent response successfully.");
+    println!("Shutting down gracefully");
+    // Let the workers finish any queued requests before they terminate.
+    tp.shutdown();
 }
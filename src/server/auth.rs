@@ -0,0 +1,137 @@
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use crate::http::request::Request;
+use crate::http::response::{error_response, Response};
+use crate::server::middleware::Middleware;
+
+/// Check an `Authorization: Basic <base64>` header against the expected credentials.
+///
+/// The header value is expected to be `Basic ` followed by the base64 encoding of
+/// `user:pass`. A missing or malformed header never matches. The credentials are
+/// compared in constant time so the response time doesn't give away how much of a
+/// guess was right.
+pub fn check_basic_auth(header: Option<&str>, expected_user: &str, expected_pass: &str) -> bool {
+    let Some(header) = header else {
+        return false;
+    };
+    let Some((scheme, encoded)) = header.trim().split_once(' ') else {
+        return false;
+    };
+    if !scheme.eq_ignore_ascii_case("basic") {
+        return false;
+    }
+    let Ok(decoded) = STANDARD.decode(encoded.trim()) else {
+        return false;
+    };
+
+    let expected = format!("{}:{}", expected_user, expected_pass);
+    constant_time_eq(&decoded, expected.as_bytes())
+}
+
+/// Middleware that requires HTTP Basic credentials, answering anything without them
+/// with a `401 Unauthorized` and a `WWW-Authenticate` challenge.
+pub struct BasicAuth {
+    realm: String,
+    user: String,
+    pass: String,
+    prefix: String,
+}
+impl BasicAuth {
+    /// Protect every path with the given credentials. The realm is shown to the user by
+    /// most browsers when they're asked to log in.
+    pub fn new(realm: &str, user: &str, pass: &str) -> BasicAuth {
+        BasicAuth {
+            realm: String::from(realm),
+            user: String::from(user),
+            pass: String::from(pass),
+            prefix: String::from("/"),
+        }
+    }
+
+    /// Only protect paths under `prefix` (e.g. `/private/`), leaving the rest public.
+    pub fn with_prefix(mut self, prefix: &str) -> BasicAuth {
+        self.prefix = String::from(prefix);
+        self
+    }
+}
+
+impl Middleware for BasicAuth {
+    fn handle(&self, request: &Request) -> Option<Response> {
+        if !request.path.starts_with(&self.prefix)
+            || check_basic_auth(request.header("authorization"), &self.user, &self.pass)
+        {
+            return None;
+        }
+
+        let challenge = format!("Basic realm=\"{}\", charset=\"UTF-8\"", self.realm.replace('"', "'"));
+        Some(error_response(401).with_header("WWW-Authenticate", &challenge))
+    }
+}
+
+// Compare two byte strings without bailing out at the first difference.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    let len = a.len().max(b.len());
+    let mut difference = a.len() ^ b.len();
+    for i in 0..len {
+        let x = a.get(i).copied().unwrap_or(0);
+        let y = b.get(i).copied().unwrap_or(0);
+        difference |= usize::from(x ^ y);
+    }
+    difference == 0
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // "ferris:crab" in base64.
+    const FERRIS: &str = "Basic ZmVycmlzOmNyYWI=";
+
+    #[test]
+    fn test_basic_auth_correct_credentials() {
+        assert!(check_basic_auth(Some(FERRIS), "ferris", "crab"));
+        assert!(check_basic_auth(Some("basic ZmVycmlzOmNyYWI="), "ferris", "crab"));
+    }
+
+    #[test]
+    fn test_basic_auth_wrong_password() {
+        assert!(!check_basic_auth(Some(FERRIS), "ferris", "lobster"));
+        assert!(!check_basic_auth(Some(FERRIS), "ferris", "cra"));
+        assert!(!check_basic_auth(Some(FERRIS), "someone", "crab"));
+    }
+
+    #[test]
+    fn test_basic_auth_malformed_header() {
+        assert!(!check_basic_auth(None, "ferris", "crab"));
+        assert!(!check_basic_auth(Some(""), "ferris", "crab"));
+        assert!(!check_basic_auth(Some("Basic"), "ferris", "crab"));
+        assert!(!check_basic_auth(Some("Basic not-base64!"), "ferris", "crab"));
+        assert!(!check_basic_auth(Some("Bearer ZmVycmlzOmNyYWI="), "ferris", "crab"));
+    }
+
+    #[test]
+    fn test_basic_auth_middleware() {
+        let auth = BasicAuth::new("Staff only", "ferris", "crab").with_prefix("/private/");
+        let mut request = Request {
+            path: String::from("/private/notes.txt"),
+            ..Request::default()
+        };
+
+        let response = auth.handle(&request).expect("Should be challenged without credentials");
+        assert_eq!(response.status, 401);
+        assert_eq!(
+            response.header("WWW-Authenticate"),
+            Some("Basic realm=\"Staff only\", charset=\"UTF-8\"")
+        );
+
+        request.headers.insert(String::from("authorization"), String::from(FERRIS));
+        assert_eq!(auth.handle(&request), None);
+
+        let public = Request {
+            path: String::from("/index.html"),
+            ..Request::default()
+        };
+        assert_eq!(auth.handle(&public), None);
+    }
+}
@@ -0,0 +1,174 @@
+use std::fs;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::SystemTime;
+use std::path::{Path, PathBuf};
+use std::collections::HashMap;
+
+/// Keeps the contents of recently served files in memory so hot files don't have to be
+/// read off disk for every request.
+///
+/// Holds up to `capacity` files, dropping the least recently used one to make room for
+/// another. Each entry remembers the file's modification time and size, and is read
+/// again if either has changed since. The cache is shared by every worker, so it sits
+/// behind a lock.
+pub struct FileCache {
+    capacity: usize,
+    entries: Mutex<Entries>,
+}
+impl FileCache {
+    /// Create an empty cache that holds up to `capacity` files. A capacity of zero
+    /// caches nothing.
+    pub fn new(capacity: usize) -> FileCache {
+        FileCache {
+            capacity,
+            entries: Mutex::new(Entries::default()),
+        }
+    }
+
+    /// The contents of the file at `path`, from memory if we have a current copy and
+    /// read from disk (and cached) if we don't. `None` if the file can't be read.
+    pub fn get(&self, path: &Path) -> Option<Arc<Vec<u8>>> {
+        let metadata = fs::metadata(path).ok()?;
+        let modified = metadata.modified().ok()?;
+        let len = metadata.len();
+
+        if let Some(contents) = self.entries.lock().unwrap().find(path, modified, len) {
+            return Some(contents);
+        }
+
+        // Read without holding the lock so other workers aren't stuck waiting on the disk.
+        let contents = Arc::new(fs::read(path).ok()?);
+        if self.capacity > 0 {
+            let mut entries = self.entries.lock().unwrap();
+            entries.insert(path, Arc::clone(&contents), modified, len, self.capacity);
+        }
+        Some(contents)
+    }
+
+    /// How many files are cached right now.
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap().files.len()
+    }
+
+    /// Whether nothing is cached.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+// The cached files, and a counter that goes up with every lookup so we can tell which
+// file was used least recently.
+#[derive(Default)]
+struct Entries {
+    files: HashMap<PathBuf, CachedFile>,
+    clock: u64,
+}
+impl Entries {
+    // The cached contents of `path`, as long as the file hasn't changed since.
+    fn find(&mut self, path: &Path, modified: SystemTime, len: u64) -> Option<Arc<Vec<u8>>> {
+        self.clock += 1;
+        let file = self.files.get_mut(path)?;
+        if file.modified != modified || file.len != len {
+            return None;
+        }
+        file.last_used = self.clock;
+        Some(Arc::clone(&file.contents))
+    }
+
+    // Cache `contents`, replacing any stale copy and making room if we're full.
+    fn insert(&mut self, path: &Path, contents: Arc<Vec<u8>>, modified: SystemTime, len: u64, capacity: usize) {
+        self.clock += 1;
+        if !self.files.contains_key(path) && self.files.len() >= capacity {
+            // A linear scan is fine at the sizes this is meant for.
+            let oldest = self.files.iter().min_by_key(|(_, file)| file.last_used).map(|(path, _)| path.clone());
+            if let Some(oldest) = oldest {
+                self.files.remove(&oldest);
+            }
+        }
+        let file = CachedFile { contents, modified, len, last_used: self.clock };
+        self.files.insert(path.to_path_buf(), file);
+    }
+}
+
+struct CachedFile {
+    contents: Arc<Vec<u8>>,
+    modified: SystemTime,
+    len: u64,
+    last_used: u64,
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+    use std::time::Duration;
+
+    #[test]
+    fn test_cache_hit() {
+        let root = tempfile::tempdir().expect("Failed to create temp dir");
+        let path = root.path().join("index.html");
+        fs::write(&path, "hello").unwrap();
+
+        let cache = FileCache::new(4);
+        let first = cache.get(&path).expect("The file should be readable");
+        assert_eq!(*first, b"hello");
+        // The second lookup hands back the very same copy.
+        let second = cache.get(&path).unwrap();
+        assert!(Arc::ptr_eq(&first, &second));
+        assert_eq!(cache.len(), 1);
+    }
+
+    #[test]
+    fn test_cache_miss() {
+        let root = tempfile::tempdir().expect("Failed to create temp dir");
+        let cache = FileCache::new(4);
+        assert!(cache.get(&root.path().join("missing.html")).is_none());
+        assert!(cache.is_empty());
+
+        // Nothing is kept when there's no room at all.
+        let path = root.path().join("index.html");
+        fs::write(&path, "hello").unwrap();
+        let uncached = FileCache::new(0);
+        assert_eq!(*uncached.get(&path).unwrap(), b"hello");
+        assert!(uncached.is_empty());
+    }
+
+    #[test]
+    fn test_cache_invalidated_when_file_changes() {
+        let root = tempfile::tempdir().expect("Failed to create temp dir");
+        let path = root.path().join("index.html");
+        fs::write(&path, "first").unwrap();
+        let cache = FileCache::new(4);
+        let first = cache.get(&path).unwrap();
+
+        // Same length, so only the new modification time gives it away.
+        fs::write(&path, "again").unwrap();
+        let modified = fs::metadata(&path).unwrap().modified().unwrap() + Duration::from_secs(1);
+        File::options().write(true).open(&path).unwrap().set_modified(modified).unwrap();
+
+        let second = cache.get(&path).unwrap();
+        assert_eq!(*second, b"again");
+        assert!(!Arc::ptr_eq(&first, &second));
+        assert_eq!(cache.len(), 1);
+    }
+
+    #[test]
+    fn test_cache_evicts_least_recently_used() {
+        let root = tempfile::tempdir().expect("Failed to create temp dir");
+        let paths: Vec<PathBuf> = ["a", "b", "c"].iter().map(|name| root.path().join(name)).collect();
+        for path in &paths {
+            fs::write(path, "contents").unwrap();
+        }
+
+        let cache = FileCache::new(2);
+        let a = cache.get(&paths[0]).unwrap();
+        cache.get(&paths[1]).unwrap();
+        // Using a again makes b the oldest, so that's the one c pushes out.
+        cache.get(&paths[0]).unwrap();
+        cache.get(&paths[2]).unwrap();
+        assert_eq!(cache.len(), 2);
+        assert!(Arc::ptr_eq(&a, &cache.get(&paths[0]).unwrap()));
+    }
+}
@@ -0,0 +1,1998 @@
+use std::fs;
+use std::io;
+use std::fs::{File, Metadata};
+use std::io::SeekFrom;
+use std::path::{Path, PathBuf};
+use std::io::prelude::*;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use crate::http::chunked::ChunkedError;
+use crate::http::compression::compress_response;
+use crate::http::date::{format_http_date, parse_http_date};
+use crate::http::method::Method;
+use crate::http::mime::MimeRegistry;
+use crate::http::range::{is_supported_range, parse_range};
+use crate::http::request::{parse_request, ConnectionContext, ParseError, Request, HTTP_1_0};
+use crate::http::response::{bad_request_response, error_response, internal_server_error, Response};
+use crate::server::cache::FileCache;
+use crate::server::cors::{apply_cors, is_preflight, preflight_response, CorsConfig};
+use crate::server::log::{log_request, LogEntry, LogFormat};
+use crate::server::metrics::Metrics;
+use crate::server::rate_limit::RateLimiter;
+use crate::server::reader::{BodyTooLarge, HeadersTooLarge, RequestLineTooLong, RequestReader};
+use crate::server::request_id::{is_valid_request_id, RequestIds, REQUEST_ID_HEADER};
+use crate::server::router::Router;
+use crate::server::transport::Transport;
+use crate::server::virtual_hosts::VirtualHosts;
+use crate::server::static_files::{
+    compute_etag, etag_matches, is_within_root, render_directory_listing, resolve_directory, resolve_path, INDEX_PAGE,
+};
+use crate::thread_pool::thread_pool::PoolStats;
+
+/// The page served when a request tries to escape the document root.
+const FORBIDDEN_PAGE: &str = "403.html";
+
+/// The page served when no file matches the request path.
+const NOT_FOUND_PAGE: &str = "404.html";
+
+/// The built-in health check endpoint, answered without touching the router or the
+/// document root. Load balancers can poll it to see if the server is up.
+pub const HEALTH_CHECK_PATH: &str = "/healthz";
+
+/// The built-in endpoint reporting request and thread pool counters, in the Prometheus
+/// text format.
+pub const METRICS_PATH: &str = "/metrics";
+
+/// The icon browsers ask for on their own. When there isn't one in the document root we
+/// answer with an empty `204 No Content` instead of a 404, and leave it out of the log.
+const FAVICON_PATH: &str = "/favicon.ico";
+
+/// The methods static files can be requested with, as listed in the `Allow` header.
+const STATIC_FILE_METHODS: &str = "GET, HEAD, OPTIONS";
+
+/// Files at least this big are streamed to the client rather than read into memory.
+const STREAMING_THRESHOLD: u64 = 1024 * 1024;
+
+/// How many bytes of a streamed file are copied to the socket at a time.
+const STREAMING_CHUNK_SIZE: usize = 64 * 1024;
+
+/// What we call ourselves in the `Server` header.
+pub const SERVER_NAME: &str = concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION"));
+
+/// How long an idle connection is kept open waiting for the next request, unless
+/// configured otherwise.
+pub const DEFAULT_KEEP_ALIVE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The most requests answered on one connection before it's closed, unless configured
+/// otherwise.
+pub const DEFAULT_KEEP_ALIVE_MAX: usize = 100;
+
+/// How long to wait on a slow client reading or writing before giving up, unless
+/// configured otherwise.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The largest request body accepted, unless configured otherwise.
+pub const DEFAULT_MAX_BODY_SIZE: usize = 1024 * 1024;
+
+/// The most bytes of request line and headers accepted, unless configured otherwise.
+pub const DEFAULT_MAX_HEADER_SIZE: usize = 8 * 1024;
+
+/// The longest request line accepted. Anything longer gets a `414 URI Too Long`.
+pub const MAX_REQUEST_LINE: usize = 8 * 1024;
+
+/// Everything needed to serve connections: where the static files live, the routes,
+/// and how long to wait on clients. Shared by all of the workers.
+pub struct Server {
+    // The document root for each site, picked by the request's Host header.
+    roots: VirtualHosts,
+    router: Router,
+    read_timeout: Duration,
+    write_timeout: Duration,
+    directory_listing: bool,
+    max_body_size: usize,
+    max_header_size: usize,
+    keep_alive_max: usize,
+    keep_alive_timeout: Duration,
+    cors: Option<CorsConfig>,
+    metrics: Metrics,
+    pool_stats: Option<PoolStats>,
+    file_cache: Option<FileCache>,
+    request_ids: RequestIds,
+    default_headers: Vec<(String, String)>,
+    server_header: bool,
+    nodelay: bool,
+    log_format: LogFormat,
+    allowed_methods: Option<Vec<Method>>,
+    rate_limiter: Option<RateLimiter>,
+    spa_mode: bool,
+    mime_types: MimeRegistry,
+}
+impl Server {
+    /// Create a Server serving static files from under `root` and dynamic routes from
+    /// `router`, with the default timeouts.
+    pub fn new(root: impl Into<PathBuf>, router: Router) -> Server {
+        Server {
+            roots: VirtualHosts::new(root),
+            router,
+            read_timeout: DEFAULT_TIMEOUT,
+            write_timeout: DEFAULT_TIMEOUT,
+            directory_listing: false,
+            max_body_size: DEFAULT_MAX_BODY_SIZE,
+            max_header_size: DEFAULT_MAX_HEADER_SIZE,
+            keep_alive_max: DEFAULT_KEEP_ALIVE_MAX,
+            keep_alive_timeout: DEFAULT_KEEP_ALIVE_TIMEOUT,
+            cors: None,
+            metrics: Metrics::new(),
+            pool_stats: None,
+            file_cache: None,
+            request_ids: RequestIds::new(),
+            default_headers: Vec::new(),
+            server_header: true,
+            nodelay: false,
+            log_format: LogFormat::Plain,
+            allowed_methods: None,
+            rate_limiter: None,
+            spa_mode: false,
+            mime_types: MimeRegistry::new(),
+        }
+    }
+
+    /// Set how long to wait for a client to send its request, and to accept our response.
+    pub fn with_timeouts(mut self, read_timeout: Duration, write_timeout: Duration) -> Server {
+        self.read_timeout = read_timeout;
+        self.write_timeout = write_timeout;
+        self
+    }
+
+    /// Set the largest request body, in bytes, that will be accepted. Requests with a
+    /// bigger body get a `413 Payload Too Large`.
+    pub fn with_max_body_size(mut self, max_body_size: usize) -> Server {
+        self.max_body_size = max_body_size;
+        self
+    }
+
+    /// Set how many bytes the request line and headers can add up to. Requests with more
+    /// than that are turned away with `431 Request Header Fields Too Large`.
+    pub fn with_max_header_size(mut self, max_header_size: usize) -> Server {
+        self.max_header_size = max_header_size;
+        self
+    }
+
+    /// Close a kept-alive connection once it has answered `max` requests, or sat idle for
+    /// `timeout` waiting for the next one, so no one connection holds on to a worker for
+    /// too long. The last request a connection gets is answered with `Connection: close`.
+    pub fn with_keep_alive(mut self, max: usize, timeout: Duration) -> Server {
+        self.keep_alive_max = max;
+        self.keep_alive_timeout = timeout;
+        self
+    }
+
+    /// Send CORS headers so browsers allow cross-origin requests from the configured
+    /// origins, and answer their preflight requests. Off by default.
+    pub fn with_cors(mut self, cors: CorsConfig) -> Server {
+        self.cors = Some(cors);
+        self
+    }
+
+    /// Include the thread pool's counters in what `/metrics` reports.
+    pub fn with_pool_stats(mut self, pool_stats: PoolStats) -> Server {
+        self.pool_stats = Some(pool_stats);
+        self
+    }
+
+    /// Keep up to `capacity` static files in memory rather than reading them off disk
+    /// for every request. Files big enough to be streamed are always read from disk.
+    pub fn with_file_cache(mut self, capacity: usize) -> Server {
+        self.file_cache = Some(FileCache::new(capacity));
+        self
+    }
+
+    /// Add `headers` to every response that doesn't set them itself, e.g.
+    /// `X-Content-Type-Options: nosniff` for some security hardening.
+    pub fn with_default_headers(mut self, headers: Vec<(String, String)>) -> Server {
+        self.default_headers = headers;
+        self
+    }
+
+    /// Set `TCP_NODELAY` on every connection, so responses are sent as soon as they're
+    /// written rather than possibly held back waiting for more data. Off by default.
+    pub fn with_nodelay(mut self, nodelay: bool) -> Server {
+        self.nodelay = nodelay;
+        self
+    }
+
+    /// Set how access log lines are written, plain text by default.
+    pub fn with_log_format(mut self, log_format: LogFormat) -> Server {
+        self.log_format = log_format;
+        self
+    }
+
+    /// Whether to send a `Server` header naming this crate and its version. On by default,
+    /// turn it off to give less away about what's running.
+    pub fn with_server_header(mut self, server_header: bool) -> Server {
+        self.server_header = server_header;
+        self
+    }
+
+    /// Only answer requests using one of `methods`, turning everything else away with a
+    /// `405 Method Not Allowed` before it gets anywhere near the router or the document
+    /// root. Every method is allowed by default.
+    pub fn with_allowed_methods(mut self, methods: Vec<Method>) -> Server {
+        self.allowed_methods = Some(methods);
+        self
+    }
+
+    /// Allow each client (by IP address) `rate` requests a second, with bursts of up to
+    /// `burst`. Clients going faster than that get a `429 Too Many Requests`.
+    pub fn with_rate_limit(mut self, rate: u32, burst: u32) -> Server {
+        self.rate_limiter = Some(RateLimiter::new(rate, burst));
+        self
+    }
+
+    /// Serve the root's index page for any path that doesn't match a file or a route, so a
+    /// single-page app's client-side routes work when loaded directly. Paths that look
+    /// like files (they have an extension) still get a 404. Off by default.
+    pub fn with_spa_mode(mut self, spa_mode: bool) -> Server {
+        self.spa_mode = spa_mode;
+        self
+    }
+
+    /// Pick the `Content-Type` of static files from `mime_types`, for extensions the
+    /// built-in types don't cover (or get wrong for a deployment).
+    pub fn with_mime_types(mut self, mime_types: MimeRegistry) -> Server {
+        self.mime_types = mime_types;
+        self
+    }
+
+    /// Serve each host in `hosts` from its own document root, and any other host from
+    /// the default root `hosts` was created with (which replaces the one given to `new`).
+    pub fn with_virtual_hosts(mut self, hosts: VirtualHosts) -> Server {
+        self.roots = hosts;
+        self
+    }
+
+    /// The counters for the requests this server has handled.
+    pub fn metrics(&self) -> &Metrics {
+        &self.metrics
+    }
+
+    /// Set whether directories without an index page get a generated listing of their
+    /// contents instead of a 404. Off by default.
+    pub fn with_directory_listing(mut self, directory_listing: bool) -> Server {
+        self.directory_listing = directory_listing;
+        self
+    }
+
+    /// Handle a single connection.
+    ///
+    /// The stream can be anything that implements `Transport`: a plain `TcpStream`, a TLS
+    /// session wrapped around one, an in-memory buffer, or a `&mut` borrow of any of those
+    /// so the caller can look at it afterwards. Any error that makes it out of `serve` is a
+    /// problem with the socket itself, so there's nobody left to send an error response to
+    /// and the connection is dropped.
+    pub fn handle_connection<S: Transport>(&self, mut stream: S) {
+
+        println!("Handling connection...");
+
+        match self.serve(&mut stream) {
+            Ok(()) => println!("Closed connection."),
+            Err(err) => eprintln!("Dropping connection: {}", err),
+        }
+    }
+
+    /// Read requests off the stream and write back their responses.
+    ///
+    /// Connections are kept alive, answering one request after another, until the client
+    /// asks for the connection to be closed with `Connection: close`, closes it itself,
+    /// sits idle for too long, or has sent as many requests as one connection is allowed.
+    /// A client that takes longer than the read timeout to send a request (or the
+    /// keep-alive timeout between requests) just has its connection closed.
+    ///
+    /// Requests are first offered to the router, anything it doesn't have a route for is
+    /// served as a static file from under the root for the request's host.
+    pub fn serve<S: Transport>(&self, stream: &mut S) -> io::Result<()> {
+        // Don't let a slow client hold on to a worker forever.
+        stream.set_read_timeout(Some(self.read_timeout))?;
+        stream.set_write_timeout(Some(self.write_timeout))?;
+        if self.nodelay {
+            stream.set_nodelay(true)?;
+        }
+
+        // Who we're talking to, for the access log and for handlers to look at.
+        let peer_addr = stream.peer_addr();
+        let connection = ConnectionContext { peer_addr, local_addr: stream.local_addr() };
+
+        let mut reader = RequestReader::with_max_body_size(self.max_body_size)
+            .with_max_header_size(self.max_header_size)
+            .with_max_request_line(MAX_REQUEST_LINE);
+        let mut requests_served = 0;
+        loop {
+            // Read the whole request, however many reads that takes.
+            let buffer = match reader.read_request(stream) {
+                Ok(buffer) => buffer,
+                Err(err) if is_timeout(&err) => {
+                    println!("Connection timed out.");
+                    return Ok(());
+                }
+                // The request couldn't be read, so there's no telling where the next one starts.
+                Err(err) if err.kind() == io::ErrorKind::InvalidData => {
+                    eprintln!("Failed to read request: {}", err);
+                    let request_id = self.request_ids.next_id();
+                    let response = self
+                        .with_common_headers(None, false, unreadable_request_response(&err))
+                        .with_header(REQUEST_ID_HEADER, &request_id);
+                    let bytes_sent = response.write_to(stream)? as u64;
+                    stream.flush()?;
+                    let entry = LogEntry {
+                        remote_addr: peer_addr,
+                        request_id: &request_id,
+                        method: "-",
+                        path: "-",
+                        status: response.status,
+                        duration: Duration::ZERO,
+                        bytes_sent,
+                    };
+                    log_request(&entry, self.log_format);
+                    self.metrics.record(response.status, bytes_sent);
+                    return stream.close();
+                }
+                Err(err) => return Err(err),
+            };
+
+            // The client closed the connection without sending another request.
+            if buffer.is_empty() {
+                return Ok(());
+            }
+
+            // Provide a simple output of the buffer contents.
+            // println!("Request: {}", String::from_utf8_lossy(&buffer));
+
+            let started = Instant::now();
+            requests_served += 1;
+            // The connection has had its share once this one's answered.
+            let may_keep_alive = requests_served < self.keep_alive_max;
+            let mut request = parse_request(&buffer);
+            let request_id = match &mut request {
+                Ok(request) => {
+                    request.connection = connection;
+                    self.assign_request_id(request)
+                }
+                Err(_) => self.request_ids.next_id(),
+            };
+            let (status, bytes_sent, keep_alive) = match &request {
+                Ok(request) if has_unexpected_body(request) => {
+                    // Servers can disagree about whether a body like this is there at all,
+                    // which is how requests get smuggled, so the connection goes too.
+                    let response = self.with_common_headers(Some(request), false, bad_request_response());
+                    (response.status, response.write_to(stream)? as u64, false)
+                }
+                Ok(request) if has_unsupported_expectation(request) => {
+                    let keep_alive = may_keep_alive && wants_keep_alive(request);
+                    let response = self.with_common_headers(Some(request), keep_alive, error_response(417));
+                    (response.status, response.write_to(stream)? as u64, keep_alive)
+                }
+                Ok(request) => {
+                    let keep_alive = may_keep_alive && wants_keep_alive(request);
+                    let (status, bytes_sent) = self.respond(stream, request, keep_alive)?;
+                    (status, bytes_sent, keep_alive)
+                }
+                Err(err) => {
+                    eprintln!("Failed to parse request: {}", err);
+                    let response = match err {
+                        ParseError::UnsupportedVersion(_) => error_response(505),
+                        _ => bad_request_response(),
+                    };
+                    // We can't trust where the next request would start, so give up on the connection.
+                    let response = self
+                        .with_common_headers(None, false, response)
+                        .with_header(REQUEST_ID_HEADER, &request_id);
+                    (response.status, response.write_to(stream)? as u64, false)
+                }
+            };
+            stream.flush()?;
+
+            let (method, path) = match &request {
+                Ok(request) => (request.method.as_str(), request.path.as_str()),
+                Err(_) => ("-", "-"),
+            };
+            if !(status == 204 && path == FAVICON_PATH) {
+                let entry = LogEntry {
+                    remote_addr: peer_addr,
+                    request_id: &request_id,
+                    method,
+                    path,
+                    status,
+                    duration: started.elapsed(),
+                    bytes_sent,
+                };
+                log_request(&entry, self.log_format);
+            }
+            self.metrics.record(status, bytes_sent);
+
+            if !keep_alive {
+                return stream.close();
+            }
+
+            // Idle connections only get so long to send their next request.
+            stream.set_read_timeout(Some(self.keep_alive_timeout.min(self.read_timeout)))?;
+        }
+    }
+
+    // Give the request an ID to tie its log lines together, keeping the one the client
+    // sent if it's usable. The ID is stored in the request's headers so handlers can
+    // see it too, and it's returned for the access log.
+    fn assign_request_id(&self, request: &mut Request) -> String {
+        let id = match request.header(REQUEST_ID_HEADER).filter(|id| is_valid_request_id(id)) {
+            Some(id) => String::from(id),
+            None => self.request_ids.next_id(),
+        };
+        request.headers.insert(REQUEST_ID_HEADER.to_ascii_lowercase(), id.clone());
+        id
+    }
+
+    // Work out the response to a request and write it to the stream, returning the status
+    // that was sent and how many bytes were written. `keep_alive` is whether the connection
+    // will stay open afterwards.
+    fn respond<S: Transport>(&self, stream: &mut S, request: &Request, keep_alive: bool) -> io::Result<(u16, u64)> {
+        if let Some(limiter) = self.rate_limiter.as_ref().filter(|limiter| !is_within_rate_limit(request, limiter)) {
+            let response = error_response(429).with_header("Retry-After", &limiter.retry_after().to_string());
+            let response = self.with_common_headers(Some(request), keep_alive, response);
+            return Ok((response.status, response.write_to(stream)? as u64));
+        }
+        if let Some(allowed) = self.allowed_methods.as_ref().filter(|allowed| !is_allowed(request, allowed)) {
+            let allow: Vec<&str> = allowed.iter().map(Method::as_str).collect();
+            let response = error_response(405).with_header("Allow", &allow.join(", "));
+            let response = self.with_common_headers(Some(request), keep_alive, response);
+            return Ok((response.status, response.write_to(stream)? as u64));
+        }
+
+        // Preflight requests are answered before the router (and any auth middleware) sees
+        // them, browsers never send credentials with them.
+        if let Some(cors) = self.cors.as_ref().filter(|_| is_preflight(request)) {
+            let response = self.with_common_headers(Some(request), keep_alive, preflight_response(cors, request));
+            return Ok((response.status, response.write_to(stream)? as u64));
+        }
+
+        // The built-in endpoints don't depend on the router or the document root being set up right.
+        let dispatched = if is_builtin(request, HEALTH_CHECK_PATH) {
+            Some(Response::text(200, "ok"))
+        } else if is_builtin(request, METRICS_PATH) {
+            Some(Response::text(200, &self.metrics.render(self.pool_stats.as_ref())))
+        } else {
+            self.router.dispatch(request)
+        };
+        let response = match dispatched {
+            Some(response) => response,
+            None => {
+                // Big files are copied straight from disk rather than loaded into memory first.
+                if let Some(path) = self.streamable_file(request) {
+                    let (head, file, len) = open_streaming(&path, &self.mime_types)?;
+                    let head = self.with_common_headers(Some(request), keep_alive, head);
+                    let bytes_sent = write_streaming(stream, &head, file, len)?;
+                    return Ok((head.status, bytes_sent));
+                }
+                self.serve_static(request)
+            }
+        };
+        let response = self.with_common_headers(Some(request), keep_alive, compress_response(response, request));
+
+        // A HEAD request gets everything a GET would, except the body.
+        let bytes_sent = if request.method == "HEAD" {
+            response.write_head_to(stream)?
+        } else {
+            response.write_to(stream)?
+        };
+        Ok((response.status, bytes_sent as u64))
+    }
+
+    // Add the headers every response gets, whichever way it was built. There's no request
+    // to go on when it couldn't be parsed. `Connection` tells the client whether we'll
+    // close the connection after this response, so it isn't left waiting for more. HTTP/1.0
+    // clients get an HTTP/1.0 response back, and the request's ID is echoed back to the
+    // client. The configured default headers fill in anything else that wasn't set, and
+    // then the `Server` header if it's turned on.
+    fn with_common_headers(&self, request: Option<&Request>, keep_alive: bool, response: Response) -> Response {
+        let mut response = response
+            .with_header("Connection", if keep_alive { "keep-alive" } else { "close" })
+            .with_default_headers(&self.default_headers);
+        if self.server_header && response.header("Server").is_none() {
+            response = response.with_header("Server", SERVER_NAME);
+        }
+        if request.is_some_and(|request| request.version == HTTP_1_0) {
+            response = response.with_version(HTTP_1_0);
+        }
+        if let Some(id) = request.and_then(|request| request.header(REQUEST_ID_HEADER)) {
+            response = response.with_header(REQUEST_ID_HEADER, id);
+        }
+        match (&self.cors, request) {
+            (Some(cors), Some(request)) => apply_cors(cors, request, response),
+            _ => response,
+        }
+    }
+
+    // The static file to stream for the request, if it's a plain GET for a file big enough
+    // to be worth streaming. Anything asking for a range or a conditional response goes
+    // through serve_static instead.
+    fn streamable_file(&self, request: &Request) -> Option<PathBuf> {
+        if request.method != "GET"
+            || ["range", "if-none-match", "if-modified-since"].iter().any(|name| request.header(name).is_some())
+        {
+            return None;
+        }
+        let root = self.roots.root_for(request);
+        let file = resolve_path(&request.path, root).filter(|file| is_within_root(file, root))?;
+        let len = fs::metadata(&file).ok()?.len();
+        (len >= STREAMING_THRESHOLD).then_some(file)
+    }
+
+    // Serve the file the request path maps onto under the root. HEAD requests are answered
+    // just like GET, it's up to the caller to leave off the body.
+    fn serve_static(&self, request: &Request) -> Response {
+        let root = self.roots.root_for(request);
+        let file = match request.method.as_str() {
+            "GET" | "HEAD" | "OPTIONS" => resolve_path(&request.path, root),
+            _ => None,
+        };
+
+        // Refuse anything that resolved outside of the root and fall back to the router's
+        // fallback handler (or failing that the 404 page) if the request didn't map onto
+        // a file.
+        match file {
+            Some(file) if is_within_root(&file, root) => match request.method.as_str() {
+                "OPTIONS" => Response::new(204).with_header("Allow", STATIC_FILE_METHODS),
+                _ => serve_file(request, &file, self.file_cache.as_ref(), &self.mime_types),
+            },
+            Some(_) => serve_error_page(403, &root.join(FORBIDDEN_PAGE)),
+            None if request.path == FAVICON_PATH && matches!(request.method.as_str(), "GET" | "HEAD") => {
+                Response::new(204)
+            }
+            None => match self.listable_directory(request) {
+                Some(dir) => Response::new(200)
+                    .with_header("Content-Type", "text/html; charset=utf-8")
+                    .with_body(render_directory_listing(&dir, &request.path)),
+                None => self.router.dispatch_fallback(request).unwrap_or_else(|| match self.spa_index(request) {
+                    Some(index) => serve_file(request, &index, self.file_cache.as_ref(), &self.mime_types),
+                    None => serve_error_page(404, &root.join(NOT_FOUND_PAGE)),
+                }),
+            },
+        }
+    }
+
+    // The index page to serve for the request in SPA mode, if it's a GET or HEAD for what
+    // looks like one of the app's routes rather than a missing asset.
+    fn spa_index(&self, request: &Request) -> Option<PathBuf> {
+        if !self.spa_mode || !matches!(request.method.as_str(), "GET" | "HEAD") || has_extension(&request.path) {
+            return None;
+        }
+        Some(self.roots.root_for(request).join(INDEX_PAGE)).filter(|index| index.is_file())
+    }
+
+    // The directory to list for the request, if listings are turned on and the request
+    // path is a directory under the root.
+    fn listable_directory(&self, request: &Request) -> Option<PathBuf> {
+        if !self.directory_listing || !matches!(request.method.as_str(), "GET" | "HEAD") {
+            return None;
+        }
+        let root = self.roots.root_for(request);
+        resolve_directory(&request.path, root).filter(|dir| is_within_root(dir, root))
+    }
+}
+
+// Whether the client is happy for the connection to stay open after this request.
+// HTTP/1.1 connections stay open unless the client says `close`, HTTP/1.0 ones close
+// unless it asks for `keep-alive`.
+fn wants_keep_alive(request: &Request) -> bool {
+    let connection = request.header("connection").unwrap_or("");
+    let asked_for = |option: &str| connection.split(',').any(|token| token.trim().eq_ignore_ascii_case(option));
+    if request.version == HTTP_1_0 {
+        asked_for("keep-alive")
+    } else {
+        !asked_for("close")
+    }
+}
+
+// Whether the client has a request to spare. Connections without an address (only ever
+// seen in tests) aren't limited.
+fn is_within_rate_limit(request: &Request, limiter: &RateLimiter) -> bool {
+    request.connection.peer_addr.is_none_or(|addr| limiter.check(addr.ip()))
+}
+
+// Whether the last segment of the path has a file extension, like `/assets/app.js`.
+fn has_extension(path: &str) -> bool {
+    let name = path.rsplit('/').next().unwrap_or(path);
+    name.rfind('.').is_some_and(|dot| dot > 0 && dot + 1 < name.len())
+}
+
+// Whether the request's method is one of `allowed`.
+fn is_allowed(request: &Request, allowed: &[Method]) -> bool {
+    request.method.parse().is_ok_and(|method| allowed.contains(&method))
+}
+
+// Whether the request is for the built-in endpoint at `path`.
+fn is_builtin(request: &Request, path: &str) -> bool {
+    request.path == path && matches!(request.method.as_str(), "GET" | "HEAD")
+}
+
+// Whether the request is a GET or HEAD that says it has a body, which they shouldn't.
+fn has_unexpected_body(request: &Request) -> bool {
+    matches!(request.method.as_str(), "GET" | "HEAD")
+        && (request.header("transfer-encoding").is_some()
+            || request.header("content-length").is_some_and(|length| length.trim().parse() != Ok(0)))
+}
+
+// Whether the request has an `Expect` we can't meet. `100-continue` is the only one
+// there is, and the reader has already taken care of it.
+fn has_unsupported_expectation(request: &Request) -> bool {
+    request.header("expect").is_some_and(|expect| !expect.trim().eq_ignore_ascii_case("100-continue"))
+}
+
+// The response to a request the reader gave up on: a request line, headers or a body
+// that were too large, or a body with a length we couldn't make sense of.
+fn unreadable_request_response(err: &io::Error) -> Response {
+    let Some(err) = err.get_ref() else {
+        return bad_request_response();
+    };
+    if err.is::<RequestLineTooLong>() {
+        error_response(414)
+    } else if err.is::<HeadersTooLarge>() {
+        error_response(431)
+    } else if let Some(err) = err.downcast_ref::<BodyTooLarge>() {
+        // A client waiting to be told to send the body is told we won't instead.
+        error_response(if err.expectation { 417 } else { 413 })
+    } else if let Some(ChunkedError::TooLarge) = err.downcast_ref::<ChunkedError>() {
+        error_response(413)
+    } else {
+        bad_request_response()
+    }
+}
+
+// Read timeouts show up as WouldBlock on some platforms and TimedOut on others.
+fn is_timeout(err: &io::Error) -> bool {
+    matches!(err.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut)
+}
+
+// Serve a file that's been found under the root, tagged with an ETag and Last-Modified
+// date so clients can cache it. If the client already has the current version we send
+// back a bodyless 304. The contents come from `cache` if there is one, and the type from
+// `mime`.
+fn serve_file(request: &Request, file: &Path, cache: Option<&FileCache>, mime: &MimeRegistry) -> Response {
+    let metadata = match fs::metadata(file) {
+        Ok(metadata) => metadata,
+        Err(_) => return read_page(200, file, mime),
+    };
+    let etag = compute_etag(&metadata);
+    let modified = metadata.modified().ok();
+
+    if is_not_modified(request, &etag, modified) {
+        return with_validators(Response::new(304), &metadata);
+    }
+
+    // A range is only worth sending if the client's partial copy is of the current file.
+    let range = request.header("range").filter(|range| is_supported_range(range));
+    if let Some(range) = range.filter(|_| if_range_matches(request, &etag, modified)) {
+        return match parse_range(range, metadata.len()) {
+            Some((start, end)) => match read_range(file, start, end) {
+                Ok(body) => with_validators(
+                    Response::new(206)
+                        .with_header("Content-Type", mime.content_type_for(file))
+                        .with_header("Content-Range", &format!("bytes {}-{}/{}", start, end, metadata.len()))
+                        .with_body(body),
+                    &metadata,
+                ),
+                Err(err) => {
+                    eprintln!("Failed to read {}: {}", file.display(), err);
+                    internal_server_error()
+                }
+            },
+            None => error_response(416)
+                .with_header("Content-Range", &format!("bytes */{}", metadata.len())),
+        };
+    }
+
+    let response = match cache {
+        Some(cache) => serve_cached_page(cache, file, mime),
+        None => read_page(200, file, mime),
+    };
+    if response.status == 200 {
+        with_validators(response, &metadata).with_header("Accept-Ranges", "bytes")
+    } else {
+        response
+    }
+}
+
+// Read bytes `start..=end` out of a file.
+fn read_range(file: &Path, start: u64, end: u64) -> io::Result<Vec<u8>> {
+    let mut file = File::open(file)?;
+    file.seek(SeekFrom::Start(start))?;
+    let mut body = Vec::new();
+    file.take(end - start + 1).read_to_end(&mut body)?;
+    Ok(body)
+}
+
+// Add the headers clients use to check whether their cached copy of a file is current.
+fn with_validators(mut response: Response, metadata: &Metadata) -> Response {
+    response = response.with_header("ETag", &compute_etag(metadata));
+    if let Ok(modified) = metadata.modified() {
+        response = response.with_header("Last-Modified", &format_http_date(modified));
+    }
+    response
+}
+
+// Whether the client's cached copy is still current. If-None-Match wins when both are
+// sent, and an If-Modified-Since date we can't parse is ignored.
+fn is_not_modified(request: &Request, etag: &str, modified: Option<SystemTime>) -> bool {
+    if let Some(if_none_match) = request.header("if-none-match") {
+        return etag_matches(if_none_match, etag);
+    }
+
+    match (request.header("if-modified-since").and_then(parse_http_date), modified) {
+        // HTTP dates only go down to the second, so drop the rest of the mtime.
+        (Some(since), Some(modified)) => truncate_to_secs(modified) <= since,
+        _ => false,
+    }
+}
+
+// Whether the request's If-Range, if it sent one, still describes the current file. An
+// ETag has to match exactly (weak ones never do) and a date has to be exactly the file's
+// Last-Modified, anything else means the client's copy is stale.
+fn if_range_matches(request: &Request, etag: &str, modified: Option<SystemTime>) -> bool {
+    let Some(if_range) = request.header("if-range").map(str::trim) else {
+        return true;
+    };
+    if if_range.starts_with('"') || if_range.starts_with("W/") {
+        return if_range == etag;
+    }
+    match (parse_http_date(if_range), modified) {
+        (Some(date), Some(modified)) => truncate_to_secs(modified) == date,
+        _ => false,
+    }
+}
+
+// Round a time down to the whole second.
+fn truncate_to_secs(time: SystemTime) -> SystemTime {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(since) => UNIX_EPOCH + Duration::from_secs(since.as_secs()),
+        Err(_) => time,
+    }
+}
+
+/// Write a `200 OK` response for the file at `path` straight to the stream.
+///
+/// Only the headers are built up front, the file itself is copied to the socket a chunk
+/// at a time so memory use stays the same however big the file is. Returns how many bytes
+/// were written, headers included.
+pub fn serve_file_streaming<W: Write>(stream: &mut W, path: &Path) -> io::Result<u64> {
+    let (head, file, len) = open_streaming(path, &MimeRegistry::new())?;
+    write_streaming(stream, &head, file, len)
+}
+
+// Open a file to be streamed, along with the head of the response to send before it and
+// how long it is.
+fn open_streaming(path: &Path, mime: &MimeRegistry) -> io::Result<(Response, File, u64)> {
+    let file = File::open(path)?;
+    let metadata = file.metadata()?;
+    let head = with_validators(Response::new(200), &metadata)
+        .with_header("Content-Type", mime.content_type_for(path))
+        .with_header("Accept-Ranges", "bytes");
+    Ok((head, file, metadata.len()))
+}
+
+// Write the head of the response and then copy the `len` byte file after it, returning
+// how many bytes that came to.
+fn write_streaming<W: Write>(stream: &mut W, head: &Response, mut file: File, len: u64) -> io::Result<u64> {
+    let head_bytes = head.to_head_bytes_with_length(len);
+    stream.write_all(&head_bytes)?;
+    let mut bytes_sent = head_bytes.len() as u64;
+
+    let mut chunk = vec![0; STREAMING_CHUNK_SIZE];
+    loop {
+        let bytes_read = file.read(&mut chunk)?;
+        if bytes_read == 0 {
+            return Ok(bytes_sent);
+        }
+        stream.write_all(&chunk[..bytes_read])?;
+        bytes_sent += bytes_read as u64;
+    }
+}
+
+// Serve a custom error page from the document root, falling back to the built-in one if
+// it's missing.
+fn serve_error_page(status: u16, page: &Path) -> Response {
+    if page.is_file() {
+        serve_page(status, page)
+    } else {
+        error_response(status)
+    }
+}
+
+// Like serve_page, but with the contents from the cache.
+fn serve_cached_page(cache: &FileCache, file: &Path, mime: &MimeRegistry) -> Response {
+    match cache.get(file) {
+        Some(contents) => Response::new(200)
+            .with_header("Content-Type", mime.content_type_for(file))
+            .with_body(contents.to_vec()),
+        // Let read_page log why the file can't be read.
+        None => read_page(200, file, mime),
+    }
+}
+
+/// Build a response with the contents of `filename` as the body.
+///
+/// If the file can't be read the error is logged and a 500 response is built instead.
+pub fn serve_page(status: u16, filename: &Path) -> Response {
+    read_page(status, filename, &MimeRegistry::new())
+}
+
+// serve_page, with the type picked from `mime`.
+fn read_page(status: u16, filename: &Path, mime: &MimeRegistry) -> Response {
+    match fs::read(filename) {
+        Ok(contents) => Response::new(status)
+            .with_header("Content-Type", mime.content_type_for(filename))
+            .with_body(contents),
+        Err(err) => {
+            eprintln!("Failed to read {}: {}", filename.display(), err);
+            internal_server_error()
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+    use std::io::Cursor;
+    use std::net::{Shutdown, TcpListener, TcpStream};
+    use crate::http::chunked::decode_chunked;
+    use crate::http::request::header_end;
+
+    // Serve a single raw request against the given root and return the raw response.
+    fn roundtrip(root: &Path, request: &[u8]) -> String {
+        roundtrip_with_router(root, Router::new(), request)
+    }
+
+    fn roundtrip_with_router(root: &Path, router: Router, request: &[u8]) -> String {
+        roundtrip_with_server(Server::new(root, router), request)
+    }
+
+    fn roundtrip_with_server(server: Server, request: &[u8]) -> String {
+        String::from_utf8(roundtrip_bytes(server, request)).expect("Response should be valid UTF-8")
+    }
+
+    fn roundtrip_bytes(server: Server, request: &[u8]) -> Vec<u8> {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("Failed to bind test listener");
+        let address = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().expect("Failed to accept test connection");
+            server.handle_connection(stream);
+        });
+
+        let mut client = TcpStream::connect(address).expect("Failed to connect to test listener");
+        client.write_all(request).unwrap();
+        client.shutdown(Shutdown::Write).unwrap();
+        let mut response = Vec::new();
+        client.read_to_end(&mut response).unwrap();
+        server.join().expect("Server thread panicked");
+        response
+    }
+
+    // An in-memory connection: reads come from the request bytes and writes are captured.
+    struct MockStream {
+        input: Cursor<Vec<u8>>,
+        output: Vec<u8>,
+    }
+    impl MockStream {
+        fn new(request: &[u8]) -> MockStream {
+            MockStream { input: Cursor::new(request.to_vec()), output: Vec::new() }
+        }
+    }
+    impl Read for MockStream {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.input.read(buf)
+        }
+    }
+    impl Write for MockStream {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.output.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+    impl Transport for MockStream {}
+
+    // A connection that only takes a few bytes per write, like a slow socket would.
+    struct ThrottledStream {
+        inner: MockStream,
+        max_write: usize,
+    }
+    impl Read for ThrottledStream {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.inner.read(buf)
+        }
+    }
+    impl Write for ThrottledStream {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            let len = buf.len().min(self.max_write);
+            self.inner.write(&buf[..len])
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+    impl Transport for ThrottledStream {}
+
+    fn test_root() -> tempfile::TempDir {
+        let root = tempfile::tempdir().expect("Failed to create temp dir");
+        fs::write(root.path().join(INDEX_PAGE), "hello").unwrap();
+        fs::write(root.path().join(FORBIDDEN_PAGE), "forbidden").unwrap();
+        fs::write(root.path().join(NOT_FOUND_PAGE), "not found").unwrap();
+        fs::write(root.path().join("about.html"), "about us").unwrap();
+        root
+    }
+
+    #[test]
+    fn test_serves_file_from_root() {
+        let root = test_root();
+        let response = roundtrip(root.path(), b"GET /about.html HTTP/1.1\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains("\r\nContent-Type: text/html; charset=utf-8\r\n"));
+        assert!(response.ends_with("\r\n\r\nabout us"));
+    }
+
+    #[test]
+    fn test_missing_file_is_404() {
+        let root = test_root();
+        let response = roundtrip(root.path(), b"GET /missing.html HTTP/1.1\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"));
+        assert!(response.ends_with("\r\n\r\nnot found"));
+    }
+
+    #[test]
+    fn test_escaping_root_is_403() {
+        let dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let root = dir.path().join("public");
+        fs::create_dir(&root).unwrap();
+        fs::write(root.join(FORBIDDEN_PAGE), "forbidden").unwrap();
+        fs::write(dir.path().join("secret.txt"), "secret").unwrap();
+
+        let response = roundtrip(&root, b"GET /../secret.txt HTTP/1.1\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 403 Forbidden\r\n"));
+        assert!(!response.contains("secret"));
+    }
+
+    #[test]
+    fn test_router_takes_priority_over_static_files() {
+        let root = test_root();
+        let mut router = Router::new();
+        router.route(Method::Get, "/about.html", |_: &Request| Response::text(200, "routed"));
+        let response = roundtrip_with_router(root.path(), router, b"GET /about.html HTTP/1.1\r\n\r\n");
+        assert!(response.ends_with("\r\n\r\nrouted"));
+    }
+
+    #[test]
+    fn test_missing_index_page_is_404() {
+        let root = tempfile::tempdir().expect("Failed to create temp dir");
+        let response = roundtrip(root.path(), b"GET / HTTP/1.1\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"));
+    }
+
+    #[test]
+    fn test_missing_404_page_falls_back_to_built_in() {
+        let root = tempfile::tempdir().expect("Failed to create temp dir");
+        fs::write(root.path().join(INDEX_PAGE), "hello").unwrap();
+        let response = roundtrip(root.path(), b"GET /missing.html HTTP/1.1\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"));
+        assert!(response.contains("<h1>404 Not Found</h1>"));
+    }
+
+    #[test]
+    fn test_unreadable_page_is_500() {
+        let root = test_root();
+        let response = serve_page(200, &root.path().join("missing.html"));
+        assert_eq!(response.status, 500);
+        assert!(String::from_utf8(response.body).unwrap().contains("Internal Server Error"));
+    }
+
+    #[test]
+    fn test_pipelined_requests_on_one_connection() {
+        let root = test_root();
+        let response = roundtrip(
+            root.path(),
+            b"GET /about.html HTTP/1.1\r\n\r\nGET /missing.html HTTP/1.1\r\n\r\n",
+        );
+        assert_eq!(response.matches("HTTP/1.1 ").count(), 2);
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains("about usHTTP/1.1 404 Not Found\r\n"));
+    }
+
+    #[test]
+    fn test_connection_close_stops_after_one_response() {
+        let root = test_root();
+        let response = roundtrip(
+            root.path(),
+            b"GET /about.html HTTP/1.1\r\nConnection: close\r\n\r\nGET /about.html HTTP/1.1\r\n\r\n",
+        );
+        assert_eq!(response.matches("HTTP/1.1 ").count(), 1);
+    }
+
+    #[test]
+    fn test_silent_client_is_dropped_after_read_timeout() {
+        let root = test_root();
+        let listener = TcpListener::bind("127.0.0.1:0").expect("Failed to bind test listener");
+        let address = listener.local_addr().unwrap();
+        let server = Server::new(root.path(), Router::new())
+            .with_timeouts(Duration::from_millis(200), Duration::from_millis(200));
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().expect("Failed to accept test connection");
+            let started = Instant::now();
+            let result = server.serve(&mut stream);
+            (result, started.elapsed())
+        });
+
+        // Connect but never send anything, the server should give up on us by itself.
+        let mut client = TcpStream::connect(address).expect("Failed to connect to test listener");
+        let (result, elapsed) = server.join().expect("Server thread panicked");
+        assert!(result.is_ok());
+        assert!(elapsed >= Duration::from_millis(200));
+        assert!(elapsed < Duration::from_secs(5));
+
+        // The connection was closed without a response.
+        let mut response = Vec::new();
+        client.read_to_end(&mut response).unwrap();
+        assert!(response.is_empty());
+    }
+
+    #[test]
+    fn test_garbage_is_400() {
+        let root = test_root();
+        let response = roundtrip(root.path(), b"\x16\x03\x01\x02\x00\x01\x00\x01\xfc\x03\x03\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 400 Bad Request\r\n"));
+
+        let response = roundtrip(root.path(), b"hello there\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 400 Bad Request\r\n"));
+    }
+
+    #[test]
+    fn test_bare_lf_request() {
+        let root = test_root();
+        let response = roundtrip(root.path(), b"GET /about.html HTTP/1.1\nHost: localhost\nnot a header\n\n");
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.ends_with("\r\n\r\nabout us"));
+    }
+
+    #[test]
+    fn test_unknown_path_is_still_404() {
+        let root = test_root();
+        let response = roundtrip(root.path(), b"GET /nope HTTP/1.1\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"));
+    }
+
+    #[test]
+    fn test_head_request_has_no_body() {
+        let root = test_root();
+        let response = roundtrip(root.path(), b"HEAD /about.html HTTP/1.1\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains(&format!("\r\nContent-Length: {}\r\n", "about us".len())));
+        assert!(response.ends_with("\r\n\r\n"));
+    }
+
+    // Pull a header's value out of a raw response.
+    fn header_value<'a>(response: &'a str, name: &str) -> Option<&'a str> {
+        response
+            .lines()
+            .take_while(|line| !line.is_empty())
+            .filter_map(|line| line.split_once(": "))
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value)
+    }
+
+    #[test]
+    fn test_matching_etag_is_304() {
+        let root = test_root();
+        let response = roundtrip(root.path(), b"GET /about.html HTTP/1.1\r\n\r\n");
+        let etag = header_value(&response, "ETag").expect("Static files should have an ETag");
+
+        let request = format!("GET /about.html HTTP/1.1\r\nIf-None-Match: {}\r\n\r\n", etag);
+        let response = roundtrip(root.path(), request.as_bytes());
+        assert!(response.starts_with("HTTP/1.1 304 Not Modified\r\n"));
+        assert_eq!(header_value(&response, "ETag"), Some(etag));
+        assert!(response.ends_with("\r\n\r\n"));
+    }
+
+    #[test]
+    fn test_stale_etag_is_200() {
+        let root = test_root();
+        let response = roundtrip(root.path(), b"GET /about.html HTTP/1.1\r\nIf-None-Match: \"stale\"\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(header_value(&response, "ETag").is_some());
+        assert!(response.ends_with("\r\n\r\nabout us"));
+    }
+
+    #[test]
+    fn test_unmodified_since_is_304() {
+        let root = test_root();
+        let response = roundtrip(root.path(), b"GET /about.html HTTP/1.1\r\n\r\n");
+        let last_modified = header_value(&response, "Last-Modified").expect("Static files should have a Last-Modified");
+        assert!(parse_http_date(last_modified).is_some());
+
+        let request = format!("GET /about.html HTTP/1.1\r\nIf-Modified-Since: {}\r\n\r\n", last_modified);
+        let response = roundtrip(root.path(), request.as_bytes());
+        assert!(response.starts_with("HTTP/1.1 304 Not Modified\r\n"));
+        assert!(response.ends_with("\r\n\r\n"));
+    }
+
+    #[test]
+    fn test_modified_since_is_200() {
+        let root = test_root();
+        let request = b"GET /about.html HTTP/1.1\r\nIf-Modified-Since: Thu, 01 Jan 1970 00:00:00 GMT\r\n\r\n";
+        assert!(roundtrip(root.path(), request).ends_with("\r\n\r\nabout us"));
+    }
+
+    #[test]
+    fn test_invalid_if_modified_since_is_ignored() {
+        let root = test_root();
+        let request = b"GET /about.html HTTP/1.1\r\nIf-Modified-Since: whenever\r\n\r\n";
+        assert!(roundtrip(root.path(), request).ends_with("\r\n\r\nabout us"));
+
+        // A year too big to count the seconds of is just as meaningless.
+        let request = b"GET /about.html HTTP/1.1\r\nIf-Modified-Since: Sun, 06 Nov 18446744073709551615 08:49:37 GMT\r\n\r\n";
+        assert!(roundtrip(root.path(), request).ends_with("\r\n\r\nabout us"));
+    }
+
+    #[test]
+    fn test_range_is_206() {
+        let root = test_root();
+        let response = roundtrip(root.path(), b"GET /about.html HTTP/1.1\r\nRange: bytes=0-4\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 206 Partial Content\r\n"));
+        assert_eq!(header_value(&response, "Content-Range"), Some("bytes 0-4/8"));
+        assert_eq!(header_value(&response, "Content-Length"), Some("5"));
+        assert!(response.ends_with("\r\n\r\nabout"));
+
+        let response = roundtrip(root.path(), b"GET /about.html HTTP/1.1\r\nRange: bytes=-2\r\n\r\n");
+        assert!(response.ends_with("\r\n\r\nus"));
+    }
+
+    #[test]
+    fn test_matching_if_range_is_206() {
+        let root = test_root();
+        let response = roundtrip(root.path(), b"GET /about.html HTTP/1.1\r\n\r\n");
+        let etag = header_value(&response, "ETag").unwrap();
+        let last_modified = header_value(&response, "Last-Modified").unwrap();
+
+        for validator in [etag, last_modified] {
+            let request = format!("GET /about.html HTTP/1.1\r\nRange: bytes=0-4\r\nIf-Range: {}\r\n\r\n", validator);
+            let response = roundtrip(root.path(), request.as_bytes());
+            assert!(response.starts_with("HTTP/1.1 206 Partial Content\r\n"), "{} should match", validator);
+            assert!(response.ends_with("\r\n\r\nabout"));
+        }
+    }
+
+    #[test]
+    fn test_stale_if_range_sends_whole_file() {
+        let root = test_root();
+        let response = roundtrip(root.path(), b"GET /about.html HTTP/1.1\r\n\r\n");
+        let weak_etag = format!("W/{}", header_value(&response, "ETag").unwrap());
+
+        for validator in ["\"stale\"", &weak_etag, "Thu, 01 Jan 1970 00:00:00 GMT", "whenever"] {
+            let request = format!("GET /about.html HTTP/1.1\r\nRange: bytes=0-4\r\nIf-Range: {}\r\n\r\n", validator);
+            let response = roundtrip(root.path(), request.as_bytes());
+            assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{} should be stale", validator);
+            assert!(response.ends_with("\r\n\r\nabout us"));
+        }
+    }
+
+    #[test]
+    fn test_unsatisfiable_range_is_416() {
+        let root = test_root();
+        let response = roundtrip(root.path(), b"GET /about.html HTTP/1.1\r\nRange: bytes=100-200\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 416 Range Not Satisfiable\r\n"));
+        assert_eq!(header_value(&response, "Content-Range"), Some("bytes */8"));
+    }
+
+    #[test]
+    fn test_multiple_ranges_send_whole_file() {
+        let root = test_root();
+        let response = roundtrip(root.path(), b"GET /about.html HTTP/1.1\r\nRange: bytes=0-1, 3-4\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert_eq!(header_value(&response, "Accept-Ranges"), Some("bytes"));
+        assert!(response.ends_with("\r\n\r\nabout us"));
+    }
+
+    #[test]
+    fn test_large_file_is_streamed() {
+        let root = test_root();
+        let contents: String = (0..3 * 1024 * 1024).map(|i| char::from(b'a' + (i % 26) as u8)).collect();
+        fs::write(root.path().join("big.txt"), &contents).unwrap();
+
+        let response = roundtrip(root.path(), b"GET /big.txt HTTP/1.1\r\nConnection: close\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert_eq!(header_value(&response, "Content-Length"), Some(contents.len().to_string().as_str()));
+        assert!(header_value(&response, "ETag").is_some());
+        let (_, body) = response.split_once("\r\n\r\n").unwrap();
+        assert!(body == contents, "Streamed body didn't match the file");
+    }
+
+    #[test]
+    fn test_response_streamed_from_reader() {
+        let root = test_root();
+        let source = b"generated on the fly ".repeat(10_000);
+        let mut router = Router::new();
+        {
+            let source = source.clone();
+            router.route(Method::Get, "/generated", move |_: &Request| {
+                Response::from_reader(200, Cursor::new(source.clone()))
+            });
+        }
+
+        // The chunked body is framed well enough for the next request to be read after it.
+        let response = roundtrip_bytes(
+            Server::new(root.path(), router),
+            b"GET /generated HTTP/1.1\r\n\r\nGET /about.html HTTP/1.1\r\nConnection: close\r\n\r\n",
+        );
+        let end = header_end(&response).expect("Response should have headers");
+        let head = String::from_utf8_lossy(&response[..end]);
+        assert!(head.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert_eq!(header_value(&head, "Transfer-Encoding"), Some("chunked"));
+        assert_eq!(header_value(&head, "Content-Length"), None);
+        let (body, used) = decode_chunked(&response[end..], usize::MAX).unwrap().expect("The body should be complete");
+        assert!(body == source, "Streamed body didn't match the reader");
+        assert!(response[end + used..].ends_with(b"\r\n\r\nabout us"));
+    }
+
+    #[test]
+    fn test_binary_file_round_trips() {
+        let root = test_root();
+        let contents = [0x89, b'P', b'N', b'G', 0x0d, 0x0a, 0x1a, 0x0a, 0x00, 0xff, 0xfe, 0xc3, 0x28];
+        fs::write(root.path().join("image.png"), contents).unwrap();
+
+        let response = roundtrip_bytes(Server::new(root.path(), Router::new()), b"GET /image.png HTTP/1.1\r\n\r\n");
+        let end = header_end(&response).expect("Response should have headers");
+        let head = String::from_utf8_lossy(&response[..end]);
+        assert!(head.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert_eq!(header_value(&head, "Content-Type"), Some("image/png"));
+        assert_eq!(header_value(&head, "Content-Length"), Some(contents.len().to_string().as_str()));
+        assert_eq!(&response[end..], contents);
+    }
+
+    #[test]
+    fn test_directory_serves_its_index() {
+        let root = test_root();
+        fs::create_dir(root.path().join("docs")).unwrap();
+        fs::write(root.path().join("docs").join(INDEX_PAGE), "the docs").unwrap();
+        let response = roundtrip(root.path(), b"GET /docs/ HTTP/1.1\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.ends_with("\r\n\r\nthe docs"));
+    }
+
+    #[test]
+    fn test_directory_without_index_is_404() {
+        let root = test_root();
+        fs::create_dir(root.path().join("empty")).unwrap();
+        let response = roundtrip(root.path(), b"GET /empty/ HTTP/1.1\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"));
+    }
+
+    #[test]
+    fn test_directory_listing() {
+        let root = test_root();
+        fs::create_dir(root.path().join("files")).unwrap();
+        fs::write(root.path().join("files/notes.txt"), "notes").unwrap();
+        let server = Server::new(root.path(), Router::new()).with_directory_listing(true);
+        let response = roundtrip_with_server(server, b"GET /files/ HTTP/1.1\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains("<a href=\"/files/notes.txt\">notes.txt</a>"));
+    }
+
+    #[test]
+    fn test_directory_listing_is_off_by_default() {
+        let root = test_root();
+        fs::create_dir(root.path().join("files")).unwrap();
+        let response = roundtrip(root.path(), b"GET /files/ HTTP/1.1\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"));
+    }
+
+    #[test]
+    fn test_post_body_reaches_handler() {
+        let root = test_root();
+        let mut router = Router::new();
+        router.route(Method::Post, "/echo", |request: &Request| {
+            Response::text(200, request.body_str().unwrap_or("not utf-8"))
+        });
+        let response = roundtrip_with_router(
+            root.path(),
+            router,
+            b"POST /echo HTTP/1.1\r\nContent-Length: 13\r\n\r\nname=ferris&x",
+        );
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.ends_with("\r\n\r\nname=ferris&x"));
+    }
+
+    #[test]
+    fn test_get_with_body_is_400() {
+        let root = test_root();
+        // The pipelined request after it is never answered since the connection closes.
+        let response = roundtrip(
+            root.path(),
+            b"GET /about.html HTTP/1.1\r\nContent-Length: 5\r\n\r\nhelloGET /about.html HTTP/1.1\r\n\r\n",
+        );
+        assert!(response.starts_with("HTTP/1.1 400 Bad Request\r\n"));
+        assert_eq!(header_value(&response, "Connection"), Some("close"));
+        assert_eq!(response.matches("HTTP/1.1 ").count(), 1);
+
+        let response = roundtrip(
+            root.path(),
+            b"HEAD /about.html HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhello\r\n0\r\n\r\n",
+        );
+        assert!(response.starts_with("HTTP/1.1 400 Bad Request\r\n"));
+
+        // Saying there's no body is fine.
+        let response = roundtrip(root.path(), b"GET /about.html HTTP/1.1\r\nContent-Length: 0\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+    }
+
+    #[test]
+    fn test_body_over_limit_is_413() {
+        let root = test_root();
+        let mut router = Router::new();
+        router.route(Method::Post, "/echo", |_: &Request| Response::text(200, "should not run"));
+        let server = Server::new(root.path(), router).with_max_body_size(10);
+        let response = roundtrip_with_server(server, b"POST /echo HTTP/1.1\r\nContent-Length: 20\r\n\r\naaaaaaaaaaaaaaaaaaaa");
+        assert!(response.starts_with("HTTP/1.1 413 Payload Too Large\r\n"));
+        assert!(!response.contains("should not run"));
+        assert_eq!(header_value(&response, "Connection"), Some("close"));
+    }
+
+    #[test]
+    fn test_repeated_content_length_over_limit_is_413() {
+        let root = test_root();
+        let requests: [&[u8]; 2] = [
+            b"POST / HTTP/1.1\r\nContent-Length: 20, 20\r\n\r\naaaaaaaaaaGET / HTTP/1.1\r\n\r\n",
+            b"POST / HTTP/1.1\r\nContent-Length: 20\r\nContent-Length: 20\r\n\r\naaaaaaaaaaGET / HTTP/1.1\r\n\r\n",
+        ];
+        for request in requests {
+            let server = Server::new(root.path(), Router::new()).with_max_body_size(10);
+            let response = roundtrip_with_server(server, request);
+            assert!(response.starts_with("HTTP/1.1 413 Payload Too Large\r\n"), "{}", response);
+            assert_eq!(header_value(&response, "Connection"), Some("close"));
+            // The unread body is never taken for another request.
+            assert_eq!(response.matches("HTTP/1.1 ").count(), 1);
+        }
+    }
+
+    #[test]
+    fn test_chunked_body_reaches_handler() {
+        let root = test_root();
+        let mut router = Router::new();
+        router.route(Method::Post, "/echo", |request: &Request| {
+            Response::text(200, request.body_str().unwrap_or("not utf-8"))
+        });
+        let response = roundtrip_with_router(
+            root.path(),
+            router,
+            b"POST /echo HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n4\r\nname\r\n7\r\n=ferris\r\n0\r\n\r\n",
+        );
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.ends_with("\r\n\r\nname=ferris"));
+    }
+
+    #[test]
+    fn test_bad_chunked_body_is_400() {
+        let root = test_root();
+        let response = roundtrip(root.path(), b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\nxyz\r\nabc\r\n0\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 400 Bad Request\r\n"));
+        assert_eq!(header_value(&response, "Connection"), Some("close"));
+
+        let server = Server::new(root.path(), Router::new()).with_max_body_size(2);
+        let response = roundtrip_with_server(server, b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n3\r\nabc\r\n0\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 413 Payload Too Large\r\n"));
+    }
+
+    #[test]
+    fn test_fallback_replaces_404() {
+        let root = test_root();
+        let mut router = Router::new();
+        router.fallback(|_: &Request| Response::text(200, "app shell"));
+        let response = roundtrip_with_router(root.path(), router, b"GET /app/settings HTTP/1.1\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.ends_with("\r\n\r\napp shell"));
+
+        // Files that do exist are still served.
+        let mut router = Router::new();
+        router.fallback(|_: &Request| Response::text(200, "app shell"));
+        let response = roundtrip_with_router(root.path(), router, b"GET /about.html HTTP/1.1\r\n\r\n");
+        assert!(response.ends_with("\r\n\r\nabout us"));
+    }
+
+    #[test]
+    fn test_unparseable_content_length_is_400() {
+        let root = test_root();
+        let mut router = Router::new();
+        router.route(Method::Post, "/echo", |_: &Request| Response::text(200, "should not run"));
+        let response = roundtrip_with_router(root.path(), router, b"POST /echo HTTP/1.1\r\nContent-Length: ten\r\n\r\naaaaaaaaaa");
+        assert!(response.starts_with("HTTP/1.1 400 Bad Request\r\n"));
+        assert!(!response.contains("should not run"));
+    }
+
+    #[test]
+    fn test_ambiguous_body_length_is_400() {
+        let root = test_root();
+        let requests: [&[u8]; 4] = [
+            b"POST /echo HTTP/1.1\r\nContent-Length: 3\r\nContent-Length: 13\r\n\r\nabcGET / HTTP/1.1\r\n\r\n",
+            b"POST /echo HTTP/1.1\r\nContent-Length: 3\r\nTransfer-Encoding: chunked\r\n\r\n3\r\nabc\r\n0\r\n\r\n",
+            b"POST /echo HTTP/1.1\r\nTransfer-Encoding: gzip\r\n\r\nabcGET / HTTP/1.1\r\n\r\n",
+            b"POST /echo HTTP/1.1\r\nTransfer-Encoding: gzip\r\nContent-Length: 3\r\n\r\nabcGET / HTTP/1.1\r\n\r\n",
+        ];
+        for request in requests {
+            let mut router = Router::new();
+            router.route(Method::Post, "/echo", |_: &Request| Response::text(200, "should not run"));
+            let response = roundtrip_with_router(root.path(), router, request);
+            assert!(response.starts_with("HTTP/1.1 400 Bad Request\r\n"), "{}", response);
+            assert!(!response.contains("should not run"));
+            // Nothing else on the connection is trusted.
+            assert_eq!(header_value(&response, "Connection"), Some("close"));
+            assert_eq!(response.matches("HTTP/1.1 ").count(), 1);
+        }
+    }
+
+    #[test]
+    fn test_file_cache_serves_current_contents() {
+        let root = test_root();
+        let server = Server::new(root.path(), Router::new()).with_file_cache(8);
+        let request = parse_request(b"GET /about.html HTTP/1.1\r\n\r\n").unwrap();
+        assert_eq!(server.serve_static(&request).body, b"about us");
+        assert_eq!(server.file_cache.as_ref().unwrap().len(), 1);
+
+        fs::write(root.path().join("about.html"), "about us, again").unwrap();
+        assert_eq!(server.serve_static(&request).body, b"about us, again");
+    }
+
+    #[test]
+    fn test_responses_carry_a_request_id() {
+        let root = test_root();
+        let response = roundtrip(root.path(), b"GET /about.html HTTP/1.1\r\n\r\nGET /nope HTTP/1.1\r\n\r\n");
+        let ids: Vec<&str> = response
+            .split("\r\n")
+            .filter_map(|line| line.strip_prefix("X-Request-Id: "))
+            .collect();
+        assert_eq!(ids.len(), 2);
+        assert_ne!(ids[0], ids[1]);
+
+        // Even a request we can't make sense of gets one.
+        let response = roundtrip(root.path(), b"garbage\r\n\r\n");
+        assert!(header_value(&response, "X-Request-Id").is_some());
+    }
+
+    #[test]
+    fn test_client_request_id_is_kept() {
+        let root = test_root();
+        let mut router = Router::new();
+        router.route(Method::Get, "/id", |request: &Request| Response::text(200, request.header("x-request-id").unwrap_or("")));
+        let response = roundtrip_with_router(root.path(), router, b"GET /id HTTP/1.1\r\nX-Request-Id: trace-42\r\n\r\n");
+        assert_eq!(header_value(&response, "X-Request-Id"), Some("trace-42"));
+        // Handlers see the same ID.
+        assert!(response.ends_with("\r\n\r\ntrace-42"));
+
+        // One that would garble the log is replaced.
+        let response = roundtrip(root.path(), b"GET /about.html HTTP/1.1\r\nX-Request-Id: two words\r\n\r\n");
+        let id = header_value(&response, "X-Request-Id").unwrap();
+        assert!(id != "two words" && is_valid_request_id(id));
+    }
+
+    #[test]
+    fn test_default_headers_are_added() {
+        let root = test_root();
+        let server = || {
+            let mut router = Router::new();
+            router.route(Method::Get, "/framed", |_: &Request| Response::text(200, "ok").with_header("X-Frame-Options", "SAMEORIGIN"));
+            Server::new(root.path(), router).with_default_headers(vec![
+                (String::from("X-Frame-Options"), String::from("DENY")),
+                (String::from("X-Content-Type-Options"), String::from("nosniff")),
+            ])
+        };
+
+        let response = roundtrip_with_server(server(), b"GET /about.html HTTP/1.1\r\n\r\n");
+        assert_eq!(header_value(&response, "X-Frame-Options"), Some("DENY"));
+        assert_eq!(header_value(&response, "X-Content-Type-Options"), Some("nosniff"));
+
+        // A header the handler set itself isn't overridden.
+        let response = roundtrip_with_server(server(), b"GET /framed HTTP/1.1\r\n\r\n");
+        assert_eq!(header_value(&response, "X-Frame-Options"), Some("SAMEORIGIN"));
+        assert_eq!(header_value(&response, "X-Content-Type-Options"), Some("nosniff"));
+    }
+
+    #[test]
+    fn test_server_header() {
+        let root = test_root();
+        let response = roundtrip(root.path(), b"GET /about.html HTTP/1.1\r\n\r\n");
+        assert_eq!(header_value(&response, "Server"), Some(SERVER_NAME));
+        assert!(SERVER_NAME.starts_with("rust-web-server/"));
+
+        let server = Server::new(root.path(), Router::new()).with_server_header(false);
+        let response = roundtrip_with_server(server, b"GET /about.html HTTP/1.1\r\n\r\n");
+        assert_eq!(header_value(&response, "Server"), None);
+    }
+
+    #[test]
+    fn test_percent_encoded_path_is_served() {
+        let root = test_root();
+        fs::write(root.path().join("my file.html"), "spaced out").unwrap();
+        let response = roundtrip(root.path(), b"GET /my%20file.html HTTP/1.1\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.ends_with("\r\n\r\nspaced out"));
+    }
+
+    #[test]
+    fn test_favicon() {
+        let root = test_root();
+        let response = roundtrip(root.path(), b"GET /favicon.ico HTTP/1.1\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 204 No Content\r\n"));
+        assert!(response.ends_with("\r\n\r\n"));
+        assert_eq!(header_value(&response, "Content-Length"), None);
+
+        fs::write(root.path().join("favicon.ico"), [0, 0, 1, 0]).unwrap();
+        let response = roundtrip_bytes(Server::new(root.path(), Router::new()), b"GET /favicon.ico HTTP/1.1\r\n\r\n");
+        assert!(response.starts_with(b"HTTP/1.1 200 OK\r\n"));
+        assert!(response.ends_with(b"\r\n\r\n\x00\x00\x01\x00"));
+    }
+
+    #[test]
+    fn test_options_on_registered_path() {
+        let root = test_root();
+        let mut router = Router::new();
+        router.route(Method::Get, "/api", |_: &Request| Response::text(200, "api"));
+        router.route(Method::Put, "/api", |_: &Request| Response::text(200, "api"));
+        let response = roundtrip_with_router(root.path(), router, b"OPTIONS /api HTTP/1.1\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 204 No Content\r\n"));
+        assert_eq!(header_value(&response, "Allow"), Some("GET, PUT, OPTIONS"));
+        assert_eq!(header_value(&response, "Content-Length"), None);
+    }
+
+    #[test]
+    fn test_options_on_static_file_and_unknown_path() {
+        let root = test_root();
+        let response = roundtrip(root.path(), b"OPTIONS /about.html HTTP/1.1\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 204 No Content\r\n"));
+        assert_eq!(header_value(&response, "Allow"), Some("GET, HEAD, OPTIONS"));
+
+        let response = roundtrip(root.path(), b"OPTIONS /nope HTTP/1.1\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"));
+    }
+
+    #[test]
+    fn test_cors_headers_and_preflight() {
+        let root = test_root();
+        let server = || Server::new(root.path(), Router::new()).with_cors(CorsConfig::new(&["https://example.com"]));
+
+        let response = roundtrip_with_server(server(), b"GET /about.html HTTP/1.1\r\nOrigin: https://example.com\r\n\r\n");
+        assert_eq!(header_value(&response, "Access-Control-Allow-Origin"), Some("https://example.com"));
+
+        let response = roundtrip_with_server(server(), b"GET /about.html HTTP/1.1\r\nOrigin: https://other.com\r\n\r\n");
+        assert_eq!(header_value(&response, "Access-Control-Allow-Origin"), None);
+
+        let response = roundtrip_with_server(
+            server(),
+            b"OPTIONS /api HTTP/1.1\r\nOrigin: https://example.com\r\nAccess-Control-Request-Method: POST\r\n\r\n",
+        );
+        assert!(response.starts_with("HTTP/1.1 204 No Content\r\n"));
+        assert_eq!(header_value(&response, "Access-Control-Allow-Methods"), Some("GET, HEAD, POST, OPTIONS"));
+    }
+
+    #[test]
+    fn test_connection_header_reflects_keep_alive() {
+        let root = test_root();
+        let response = roundtrip(root.path(), b"GET /about.html HTTP/1.1\r\nConnection: close\r\n\r\n");
+        assert_eq!(header_value(&response, "Connection"), Some("close"));
+
+        let response = roundtrip(root.path(), b"GET /about.html HTTP/1.1\r\n\r\n");
+        assert_eq!(header_value(&response, "Connection"), Some("keep-alive"));
+
+        // Bad requests always close the connection.
+        let response = roundtrip(root.path(), b"garbage\r\n\r\n");
+        assert_eq!(header_value(&response, "Connection"), Some("close"));
+    }
+
+    #[test]
+    fn test_keep_alive_max_requests() {
+        let root = test_root();
+        let server = Server::new(root.path(), Router::new()).with_keep_alive(2, DEFAULT_KEEP_ALIVE_TIMEOUT);
+        // The third request is never answered since the connection closes after the second.
+        let response = roundtrip_with_server(server, &b"GET /about.html HTTP/1.1\r\n\r\n".repeat(3));
+        assert_eq!(response.matches("HTTP/1.1 200 OK\r\n").count(), 2);
+        assert_eq!(response.matches("\r\nConnection: keep-alive\r\n").count(), 1);
+        assert_eq!(response.matches("\r\nConnection: close\r\n").count(), 1);
+        assert!(response.ends_with("about us"));
+    }
+
+    #[test]
+    fn test_keep_alive_idle_timeout() {
+        let root = test_root();
+        let listener = TcpListener::bind("127.0.0.1:0").expect("Failed to bind test listener");
+        let address = listener.local_addr().unwrap();
+        let server = Server::new(root.path(), Router::new()).with_keep_alive(DEFAULT_KEEP_ALIVE_MAX, Duration::from_millis(200));
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().expect("Failed to accept test connection");
+            let started = Instant::now();
+            let result = server.serve(&mut stream);
+            (result, started.elapsed())
+        });
+
+        // Send one request and then go quiet, well within the 30 second read timeout.
+        let mut client = TcpStream::connect(address).expect("Failed to connect to test listener");
+        client.write_all(b"GET /about.html HTTP/1.1\r\n\r\n").unwrap();
+        let (result, elapsed) = server.join().expect("Server thread panicked");
+        assert!(result.is_ok());
+        assert!(elapsed >= Duration::from_millis(200));
+        assert!(elapsed < Duration::from_secs(5));
+
+        let mut response = String::new();
+        client.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert_eq!(header_value(&response, "Connection"), Some("keep-alive"));
+    }
+
+    #[test]
+    fn test_http_1_0_closes_by_default() {
+        let root = test_root();
+        // The second request is never answered since the connection closes after the first.
+        let response = roundtrip(root.path(), b"GET /about.html HTTP/1.0\r\n\r\nGET /about.html HTTP/1.0\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.0 200 OK\r\n"));
+        assert_eq!(header_value(&response, "Connection"), Some("close"));
+        assert_eq!(response.matches("HTTP/1.0 ").count(), 1);
+        assert!(response.ends_with("about us"));
+    }
+
+    #[test]
+    fn test_http_1_0_keep_alive() {
+        let root = test_root();
+        let response = roundtrip(
+            root.path(),
+            b"GET /about.html HTTP/1.0\r\nConnection: Keep-Alive\r\n\r\nGET /nope HTTP/1.0\r\n\r\n",
+        );
+        assert!(response.starts_with("HTTP/1.0 200 OK\r\n"));
+        assert_eq!(header_value(&response, "Connection"), Some("keep-alive"));
+        assert!(response.contains("about usHTTP/1.0 404 Not Found\r\n"));
+    }
+
+    #[test]
+    fn test_unsupported_http_version() {
+        let root = test_root();
+        let response = roundtrip(root.path(), b"GET /about.html HTTP/2.0\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 505 HTTP Version Not Supported\r\n"));
+        assert_eq!(header_value(&response, "Connection"), Some("close"));
+    }
+
+    #[test]
+    fn test_health_check() {
+        // Even with nothing at all in the document root.
+        let root = tempfile::tempdir().expect("Failed to create temp dir");
+        let response = roundtrip(root.path(), b"GET /healthz HTTP/1.1\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.ends_with("\r\n\r\nok"));
+
+        let response = roundtrip(Path::new("/does/not/exist"), b"GET /healthz HTTP/1.1\r\n\r\n");
+        assert!(response.ends_with("\r\n\r\nok"));
+    }
+
+    #[test]
+    fn test_metrics_count_requests() {
+        let root = test_root();
+        let response = roundtrip(
+            root.path(),
+            b"GET /about.html HTTP/1.1\r\n\r\nGET /nope HTTP/1.1\r\n\r\nGET /metrics HTTP/1.1\r\nConnection: close\r\n\r\n",
+        );
+        let (_, metrics) = response.rsplit_once("\r\n\r\n").unwrap();
+        assert!(metrics.contains("http_requests_total 2\n"));
+        assert!(metrics.contains("http_responses_total{status=\"200\"} 1\n"));
+        assert!(metrics.contains("http_responses_total{status=\"404\"} 1\n"));
+    }
+
+    #[test]
+    fn test_handlers_see_connection_addresses() {
+        let root = test_root();
+        let mut router = Router::new();
+        router.route(Method::Get, "/whoami", |request: &Request| {
+            let ConnectionContext { peer_addr, local_addr } = request.connection;
+            Response::text(200, &format!("{} {}", peer_addr.unwrap(), local_addr.unwrap()))
+        });
+        let server = Server::new(root.path(), router);
+
+        let listener = TcpListener::bind("127.0.0.1:0").expect("Failed to bind test listener");
+        let address = listener.local_addr().unwrap();
+        let mut client = TcpStream::connect(address).expect("Failed to connect to test listener");
+        client.write_all(b"GET /whoami HTTP/1.1\r\nConnection: close\r\n\r\n").unwrap();
+        let (stream, _) = listener.accept().expect("Failed to accept test connection");
+        server.handle_connection(stream);
+
+        let mut response = String::new();
+        client.read_to_string(&mut response).unwrap();
+        assert!(response.ends_with(&format!("\r\n\r\n{} {}", client.local_addr().unwrap(), address)));
+    }
+
+    #[test]
+    fn test_expect_continue_with_body_already_sent_gets_no_interim_response() {
+        let root = test_root();
+        let mut router = Router::new();
+        router.route(Method::Post, "/upload", |request: &Request| Response::text(200, request.body_str().unwrap()));
+        let mut stream = MockStream::new(
+            b"POST /upload HTTP/1.1\r\nExpect: 100-continue\r\nContent-Length: 5\r\nConnection: close\r\n\r\nhello",
+        );
+        Server::new(root.path(), router).serve(&mut stream).unwrap();
+
+        // The body had already arrived here, so there was nothing to wait for.
+        let response = String::from_utf8(stream.output).unwrap();
+        assert!(!response.contains("100 Continue"));
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.ends_with("\r\n\r\nhello"));
+    }
+
+    #[test]
+    fn test_unmet_expectation_is_417() {
+        let root = test_root();
+        let server = Server::new(root.path(), Router::new()).with_max_body_size(4);
+        let response = roundtrip_with_server(server, b"POST /upload HTTP/1.1\r\nExpect: 100-continue\r\nContent-Length: 5\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 417 Expectation Failed\r\n"));
+
+        let response = roundtrip(root.path(), b"GET /about.html HTTP/1.1\r\nExpect: coffee\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 417 Expectation Failed\r\n"));
+    }
+
+    #[test]
+    fn test_custom_mime_types() {
+        let root = test_root();
+        fs::write(root.path().join("app.wasm"), b"\0asm").unwrap();
+        let server = || {
+            let mut mime_types = MimeRegistry::new();
+            mime_types.insert("wasm", "application/wasm");
+            mime_types.insert("html", "text/html");
+            Server::new(root.path(), Router::new()).with_mime_types(mime_types)
+        };
+
+        let response = roundtrip_with_server(server(), b"GET /app.wasm HTTP/1.1\r\n\r\n");
+        assert_eq!(header_value(&response, "Content-Type"), Some("application/wasm"));
+        let response = roundtrip_with_server(server(), b"GET /about.html HTTP/1.1\r\n\r\n");
+        assert_eq!(header_value(&response, "Content-Type"), Some("text/html"));
+    }
+
+    #[test]
+    fn test_virtual_hosts() {
+        let default_root = test_root();
+        let example = tempfile::tempdir().expect("Failed to create temp dir");
+        fs::write(example.path().join(INDEX_PAGE), "example").unwrap();
+        let blog = tempfile::tempdir().expect("Failed to create temp dir");
+        fs::write(blog.path().join(INDEX_PAGE), "blog").unwrap();
+        let server = || {
+            let mut hosts = VirtualHosts::new(default_root.path());
+            hosts.insert("example.com", example.path());
+            hosts.insert("blog.example.com", blog.path());
+            Server::new(default_root.path(), Router::new()).with_virtual_hosts(hosts)
+        };
+
+        let response = roundtrip_with_server(server(), b"GET / HTTP/1.1\r\nHost: example.com\r\n\r\n");
+        assert!(response.ends_with("\r\n\r\nexample"));
+        let response = roundtrip_with_server(server(), b"GET / HTTP/1.1\r\nHost: blog.example.com:7878\r\n\r\n");
+        assert!(response.ends_with("\r\n\r\nblog"));
+        let response = roundtrip_with_server(server(), b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n");
+        assert!(response.ends_with("\r\n\r\nhello"));
+        // Each host only sees its own files.
+        let response = roundtrip_with_server(server(), b"GET /about.html HTTP/1.1\r\nHost: example.com\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"));
+    }
+
+    #[test]
+    fn test_spa_mode_serves_index_for_routes() {
+        let root = test_root();
+        let server = || Server::new(root.path(), Router::new()).with_spa_mode(true);
+        let response = roundtrip_with_server(server(), b"GET /app/route HTTP/1.1\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.ends_with("\r\n\r\nhello"));
+
+        // Real files are still served as themselves.
+        let response = roundtrip_with_server(server(), b"GET /about.html HTTP/1.1\r\n\r\n");
+        assert!(response.ends_with("\r\n\r\nabout us"));
+
+        // So are requests without SPA mode turned on.
+        let response = roundtrip(root.path(), b"GET /app/route HTTP/1.1\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"));
+    }
+
+    #[test]
+    fn test_spa_mode_missing_asset_is_404() {
+        let root = test_root();
+        let server = Server::new(root.path(), Router::new()).with_spa_mode(true);
+        let response = roundtrip_with_server(server, b"GET /missing.js HTTP/1.1\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"));
+        assert!(response.ends_with("\r\n\r\nnot found"));
+    }
+
+    #[test]
+    fn test_has_extension() {
+        assert!(has_extension("/missing.js"));
+        assert!(has_extension("/assets/app.min.css"));
+        assert!(!has_extension("/app/route"));
+        assert!(!has_extension("/v1.2/users"));
+        assert!(!has_extension("/.well-known"));
+        assert!(!has_extension("/trailing."));
+    }
+
+    #[test]
+    fn test_rate_limited_client_gets_429() {
+        let root = test_root();
+        let server = Server::new(root.path(), Router::new()).with_rate_limit(1, 2);
+        let response = roundtrip_with_server(
+            server,
+            b"GET /about.html HTTP/1.1\r\n\r\nGET /about.html HTTP/1.1\r\n\r\nGET /about.html HTTP/1.1\r\n\r\n",
+        );
+        let statuses: Vec<&str> = response.split("HTTP/1.1 ").skip(1).map(|response| &response[..3]).collect();
+        assert_eq!(statuses, ["200", "200", "429"]);
+        assert_eq!(header_value(response.rsplit("HTTP/1.1 ").next().unwrap(), "Retry-After"), Some("1"));
+    }
+
+    #[test]
+    fn test_disallowed_method_is_405() {
+        let root = test_root();
+        let server = || {
+            let mut router = Router::new();
+            router.route(Method::Post, "/submit", |_: &Request| Response::text(200, "submitted"));
+            Server::new(root.path(), router).with_allowed_methods(vec![Method::Get, Method::Head])
+        };
+
+        for request in [&b"POST /submit HTTP/1.1\r\n\r\n"[..], b"POST /about.html HTTP/1.1\r\n\r\n"] {
+            let response = roundtrip_with_server(server(), request);
+            assert!(response.starts_with("HTTP/1.1 405 Method Not Allowed\r\n"));
+            assert_eq!(header_value(&response, "Allow"), Some("GET, HEAD"));
+        }
+
+        let response = roundtrip_with_server(server(), b"GET /about.html HTTP/1.1\r\n\r\n");
+        assert!(response.ends_with("\r\n\r\nabout us"));
+    }
+
+    #[test]
+    fn test_metrics_count_bytes_sent() {
+        let root = test_root();
+        fs::write(root.path().join("big.bin"), vec![b'x'; STREAMING_THRESHOLD as usize]).unwrap();
+        let mut stream = MockStream::new(
+            b"GET /about.html HTTP/1.1\r\n\r\nHEAD /about.html HTTP/1.1\r\n\r\nGET /big.bin HTTP/1.1\r\nConnection: close\r\n\r\n",
+        );
+        let server = Server::new(root.path(), Router::new());
+        server.serve(&mut stream).unwrap();
+
+        // Every byte of every response is counted, whether it was buffered or streamed.
+        assert_eq!(server.metrics().requests(), 3);
+        assert_eq!(server.metrics().total_bytes_sent(), stream.output.len() as u64);
+    }
+
+    #[test]
+    fn test_handle_connection_with_in_memory_stream() {
+        let root = test_root();
+        let mut stream = MockStream::new(b"GET /about.html HTTP/1.1\r\n\r\nGET /nope HTTP/1.1\r\nConnection: close\r\n\r\n");
+        Server::new(root.path(), Router::new()).handle_connection(&mut stream);
+
+        let response = String::from_utf8(stream.output).unwrap();
+        let (first, second) = response.split_once("about us").expect("The first response should be the file");
+        assert!(first.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(second.starts_with("HTTP/1.1 404 Not Found\r\n"));
+        assert!(second.ends_with("\r\n\r\nnot found"));
+    }
+
+    #[test]
+    fn test_handle_connection_stops_when_input_runs_out() {
+        let root = test_root();
+        let mut stream = MockStream::new(b"GET / HTTP/1.1\r\n\r\n");
+        Server::new(root.path(), Router::new()).handle_connection(&mut stream);
+        assert!(stream.output.starts_with(b"HTTP/1.1 200 OK\r\n"));
+        assert!(stream.output.ends_with(b"\r\n\r\nhello"));
+        assert_eq!(stream.input.position(), 18);
+    }
+
+    #[test]
+    fn test_client_hanging_up_before_reading_the_response() {
+        let root = test_root();
+        // Far more than the socket buffers can hold, so the writes have to fail.
+        fs::write(root.path().join("big.bin"), vec![b'x'; 8 * STREAMING_THRESHOLD as usize]).unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").expect("Failed to bind test listener");
+        let address = listener.local_addr().unwrap();
+
+        let mut client = TcpStream::connect(address).expect("Failed to connect to test listener");
+        client.write_all(b"GET /big.bin HTTP/1.1\r\n\r\nGET /about.html HTTP/1.1\r\n\r\n").unwrap();
+        drop(client);
+
+        let (mut stream, _) = listener.accept().expect("Failed to accept test connection");
+        let server = Server::new(root.path(), Router::new());
+        let err = server.serve(&mut stream).expect_err("Writing to a closed connection should fail");
+        assert!(matches!(err.kind(), io::ErrorKind::BrokenPipe | io::ErrorKind::ConnectionReset), "{:?}", err);
+
+        // Handling the connection just logs the error rather than panicking.
+        let mut client = TcpStream::connect(address).expect("Failed to connect to test listener");
+        client.write_all(b"GET /big.bin HTTP/1.1\r\n\r\n").unwrap();
+        drop(client);
+        let (stream, _) = listener.accept().expect("Failed to accept test connection");
+        server.handle_connection(stream);
+    }
+
+    #[test]
+    fn test_large_responses_survive_partial_writes() {
+        let root = test_root();
+        let body: Vec<u8> = (0..200_000u32).map(|i| (i % 251) as u8).collect();
+        let streamed: Vec<u8> = (0..STREAMING_THRESHOLD as u32 + 1000).map(|i| (i % 241) as u8).collect();
+        fs::write(root.path().join("big.bin"), &streamed).unwrap();
+        let mut router = Router::new();
+        {
+            let body = body.clone();
+            router.route(Method::Get, "/generated", move |_: &Request| Response::new(200).with_body(body.clone()));
+        }
+        let server = Server::new(root.path(), router);
+
+        for (path, expected) in [("/generated", &body), ("/big.bin", &streamed)] {
+            let request = format!("GET {} HTTP/1.1\r\nConnection: close\r\n\r\n", path);
+            let mut stream = ThrottledStream { inner: MockStream::new(request.as_bytes()), max_write: 1000 };
+            server.serve(&mut stream).expect("Serving should succeed");
+
+            let output = &stream.inner.output;
+            let header_end = header_end(output).expect("The response should have headers");
+            assert_eq!(&output[header_end..], &expected[..], "Truncated response for {}", path);
+        }
+    }
+
+    #[test]
+    fn test_client_closing_without_a_request_gets_nothing() {
+        let root = test_root();
+        let listener = TcpListener::bind("127.0.0.1:0").expect("Failed to bind test listener");
+        let address = listener.local_addr().unwrap();
+        let mut client = TcpStream::connect(address).expect("Failed to connect to test listener");
+        client.shutdown(Shutdown::Write).unwrap();
+
+        let (mut stream, _) = listener.accept().expect("Failed to accept test connection");
+        let server = Server::new(root.path(), Router::new());
+        server.serve(&mut stream).expect("An empty connection isn't an error");
+        drop(stream);
+
+        // No response was written and nothing was counted as a request.
+        let mut response = Vec::new();
+        client.read_to_end(&mut response).unwrap();
+        assert!(response.is_empty());
+        assert_eq!(server.metrics().requests(), 0);
+    }
+
+    #[test]
+    fn test_oversized_headers_get_431() {
+        let root = test_root();
+        let request = format!("GET /about.html HTTP/1.1\r\nCookie: {}\r\n\r\n", "a".repeat(DEFAULT_MAX_HEADER_SIZE));
+        let response = roundtrip(root.path(), request.as_bytes());
+        assert!(response.starts_with("HTTP/1.1 431 Request Header Fields Too Large\r\n"), "{}", response);
+        assert_eq!(header_value(&response, "Connection"), Some("close"));
+
+        // Right at the limit is fine.
+        let server = Server::new(root.path(), Router::new()).with_max_header_size(request.len());
+        let response = roundtrip_with_server(server, request.as_bytes());
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+    }
+
+    #[test]
+    fn test_long_request_line_gets_414() {
+        let root = test_root();
+        let request = format!("GET /{} HTTP/1.1\r\n\r\n", "a".repeat(MAX_REQUEST_LINE));
+        // Even when the headers are allowed to be that big.
+        let server = Server::new(root.path(), Router::new()).with_max_header_size(request.len());
+        let response = roundtrip_with_server(server, request.as_bytes());
+        assert!(response.starts_with("HTTP/1.1 414 URI Too Long\r\n"), "{}", response);
+        assert_eq!(header_value(&response, "Connection"), Some("close"));
+    }
+
+    #[test]
+    fn test_nodelay_is_set_on_request() {
+        let root = test_root();
+        let listener = TcpListener::bind("127.0.0.1:0").expect("Failed to bind test listener");
+        let address = listener.local_addr().unwrap();
+
+        for nodelay in [false, true] {
+            let client = TcpStream::connect(address).expect("Failed to connect to test listener");
+            client.shutdown(Shutdown::Write).unwrap();
+            let (mut stream, _) = listener.accept().expect("Failed to accept test connection");
+            let server = Server::new(root.path(), Router::new()).with_nodelay(nodelay);
+            server.serve(&mut stream).expect("Serving should succeed");
+            assert_eq!(stream.nodelay().unwrap(), nodelay);
+        }
+    }
+}
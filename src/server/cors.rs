@@ -0,0 +1,153 @@
+use crate::http::method::Method;
+use crate::http::request::Request;
+use crate::http::response::Response;
+
+/// Which cross-origin requests browsers should allow, and what they may send.
+///
+/// Origins are matched exactly (e.g. `https://example.com`), or `*` can be used to allow
+/// any origin.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CorsConfig {
+    allowed_origins: Vec<String>,
+    allowed_methods: Vec<Method>,
+    allowed_headers: Vec<String>,
+}
+impl CorsConfig {
+    /// Allow requests from the given origins, with the usual methods (`GET`, `HEAD`,
+    /// `POST` and `OPTIONS`) and the `Content-Type` header.
+    pub fn new(allowed_origins: &[&str]) -> CorsConfig {
+        CorsConfig {
+            allowed_origins: allowed_origins.iter().map(|origin| String::from(*origin)).collect(),
+            allowed_methods: vec![Method::Get, Method::Head, Method::Post, Method::Options],
+            allowed_headers: vec![String::from("Content-Type")],
+        }
+    }
+
+    /// Set the methods cross-origin requests may use.
+    pub fn with_methods(mut self, methods: &[Method]) -> CorsConfig {
+        self.allowed_methods = methods.to_vec();
+        self
+    }
+
+    /// Set the request headers cross-origin requests may send.
+    pub fn with_headers(mut self, headers: &[&str]) -> CorsConfig {
+        self.allowed_headers = headers.iter().map(|header| String::from(*header)).collect();
+        self
+    }
+
+    // Whether requests from `origin` are allowed.
+    fn allows(&self, origin: &str) -> bool {
+        self.allowed_origins
+            .iter()
+            .any(|allowed| allowed == "*" || allowed == origin)
+    }
+}
+
+/// Add the CORS headers to a response if the request came from an allowed origin.
+///
+/// Requests without an `Origin` header, or from an origin that isn't allowed, get the
+/// response back untouched, which browsers take as a refusal. Unless every origin is
+/// allowed the origin is echoed back, so `Vary: Origin` is added to keep caches from
+/// handing one origin's response to another.
+pub fn apply_cors(config: &CorsConfig, request: &Request, response: Response) -> Response {
+    let Some(origin) = request.header("origin") else {
+        return response;
+    };
+    if !config.allows(origin) {
+        return response;
+    }
+
+    let methods: Vec<&str> = config.allowed_methods.iter().map(Method::as_str).collect();
+    let response = response
+        .with_header("Access-Control-Allow-Methods", &methods.join(", "))
+        .with_header("Access-Control-Allow-Headers", &config.allowed_headers.join(", "));
+
+    if config.allowed_origins.iter().any(|allowed| allowed == "*") {
+        return response.with_header("Access-Control-Allow-Origin", "*");
+    }
+    let vary = match response.header("vary") {
+        Some(vary) => format!("{}, Origin", vary),
+        None => String::from("Origin"),
+    };
+    response
+        .with_header("Access-Control-Allow-Origin", origin)
+        .with_header("Vary", &vary)
+}
+
+/// Whether the request is a CORS preflight: an `OPTIONS` request a browser sends to ask
+/// whether it may make the real cross-origin request.
+pub fn is_preflight(request: &Request) -> bool {
+    request.method == "OPTIONS"
+        && request.header("origin").is_some()
+        && request.header("access-control-request-method").is_some()
+}
+
+/// The `204 No Content` answer to a preflight request.
+pub fn preflight_response(config: &CorsConfig, request: &Request) -> Response {
+    apply_cors(config, request, Response::new(204))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request_from(method: &str, origin: &str) -> Request {
+        Request {
+            method: String::from(method),
+            path: String::from("/api"),
+            headers: [(String::from("origin"), String::from(origin))].into(),
+            ..Request::default()
+        }
+    }
+
+    #[test]
+    fn test_allowed_origin() {
+        let config = CorsConfig::new(&["https://example.com"]);
+        let response = apply_cors(&config, &request_from("GET", "https://example.com"), Response::text(200, "ok"));
+        assert_eq!(response.header("Access-Control-Allow-Origin"), Some("https://example.com"));
+        assert_eq!(response.header("Access-Control-Allow-Methods"), Some("GET, HEAD, POST, OPTIONS"));
+        assert_eq!(response.header("Access-Control-Allow-Headers"), Some("Content-Type"));
+        assert_eq!(response.header("Vary"), Some("Origin"));
+    }
+
+    #[test]
+    fn test_wildcard_origin() {
+        let config = CorsConfig::new(&["*"]).with_methods(&[Method::Get]);
+        let response = apply_cors(&config, &request_from("GET", "https://anywhere.dev"), Response::new(200));
+        assert_eq!(response.header("Access-Control-Allow-Origin"), Some("*"));
+        assert_eq!(response.header("Access-Control-Allow-Methods"), Some("GET"));
+        assert_eq!(response.header("Vary"), None);
+    }
+
+    #[test]
+    fn test_disallowed_origin() {
+        let config = CorsConfig::new(&["https://example.com"]);
+        let response = Response::text(200, "ok");
+        assert_eq!(apply_cors(&config, &request_from("GET", "https://evil.example"), response.clone()), response);
+        // Nor is anything added to same-origin requests that don't send an Origin.
+        assert_eq!(apply_cors(&config, &Request::default(), response.clone()), response);
+    }
+
+    #[test]
+    fn test_vary_is_merged() {
+        let config = CorsConfig::new(&["https://example.com"]);
+        let response = Response::new(200).with_header("Vary", "Accept-Encoding");
+        let response = apply_cors(&config, &request_from("GET", "https://example.com"), response);
+        assert_eq!(response.header("Vary"), Some("Accept-Encoding, Origin"));
+    }
+
+    #[test]
+    fn test_preflight() {
+        let config = CorsConfig::new(&["https://example.com"]).with_headers(&["Content-Type", "Authorization"]);
+        let mut request = request_from("OPTIONS", "https://example.com");
+        assert!(!is_preflight(&request));
+        request.headers.insert(String::from("access-control-request-method"), String::from("PUT"));
+        assert!(is_preflight(&request));
+
+        let response = preflight_response(&config, &request);
+        assert_eq!(response.status, 204);
+        assert_eq!(response.header("Access-Control-Allow-Origin"), Some("https://example.com"));
+        assert_eq!(response.header("Access-Control-Allow-Headers"), Some("Content-Type, Authorization"));
+    }
+}
@@ -0,0 +1,21 @@
+use crate::http::request::Request;
+use crate::http::response::Response;
+
+/// Answers the requests for a route, see `Router::route`.
+///
+/// Implement this on a struct to have a handler carry its own state between requests,
+/// like a database pool or some templates. Handlers are shared between the worker threads
+/// so they must be `Send + Sync`, anything they change has to be behind a lock or an atomic.
+pub trait Handler: Send + Sync {
+    fn handle(&self, request: &Request) -> Response;
+}
+
+// Plain functions and closures make perfectly good handlers.
+impl<F> Handler for F
+where
+    F: Fn(&Request) -> Response + Send + Sync,
+{
+    fn handle(&self, request: &Request) -> Response {
+        self(request)
+    }
+}
@@ -0,0 +1,446 @@
+use std::io;
+use std::thread;
+use std::sync::Arc;
+use std::io::prelude::*;
+use std::time::Duration;
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use crate::http::response::error_response;
+
+/// How long to wait before checking for a new connection (and the shutdown flag) again
+/// when nothing is waiting to be accepted.
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// How long to wait before trying again after accepting a connection fails, so an error
+/// that keeps happening (like running out of file descriptors) doesn't spin the CPU.
+const ACCEPT_ERROR_BACKOFF: Duration = Duration::from_millis(100);
+
+/// How often to check whether a draining server has finished its last connection.
+const DRAIN_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How long to spend telling a client we're too busy before giving up on it, so a slow
+/// client can't hold up the accept loop.
+const REJECT_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Accept connections on the listener until `shutdown` is set, passing each one to `handle`.
+///
+/// The listener is switched to non-blocking mode so the loop can notice the shutdown
+/// flag even when no connections are coming in. Accepted streams are switched back to
+/// blocking mode before being handed over. Like `TcpListener::incoming`, `handle` is
+/// given the `Result` of each accept attempt. A failed accept doesn't stop the loop, it
+/// carries on with the next connection after a short pause.
+pub fn accept_loop<F>(listener: &TcpListener, shutdown: &AtomicBool, handle: F) -> io::Result<()>
+where
+    F: FnMut(io::Result<TcpStream>),
+{
+    listener.set_nonblocking(true)?;
+
+    let accept = || match listener.accept() {
+        Ok((stream, _)) => stream.set_nonblocking(false).map(|_| Some(stream)),
+        Err(err) if err.kind() == io::ErrorKind::WouldBlock => Ok(None),
+        Err(err) => Err(err),
+    };
+    run_accept_loop(shutdown, accept, handle);
+
+    Ok(())
+}
+
+// The loop behind accept_loop, with the accepting pulled out so tests can make it fail.
+// `accept` gives `None` when there's no connection waiting.
+fn run_accept_loop<T, A, F>(shutdown: &AtomicBool, mut accept: A, mut handle: F)
+where
+    A: FnMut() -> io::Result<Option<T>>,
+    F: FnMut(io::Result<T>),
+{
+    while !shutdown.load(Ordering::SeqCst) {
+        match accept() {
+            Ok(Some(stream)) => handle(Ok(stream)),
+            Ok(None) => thread::sleep(POLL_INTERVAL),
+            Err(err) => {
+                handle(Err(err));
+                thread::sleep(ACCEPT_ERROR_BACKOFF);
+            }
+        }
+    }
+}
+
+/// Accept connections on every one of the listeners until `shutdown` is set, passing
+/// each one to `handle`.
+///
+/// Each listener gets its own thread running `accept_loop`, so `handle` is shared
+/// between them. If accepting fails on one listener, `shutdown` is set so the rest stop
+/// too, and the first error is returned once they have.
+pub fn accept_all<F>(listeners: &[TcpListener], shutdown: &AtomicBool, handle: F) -> io::Result<()>
+where
+    F: Fn(io::Result<TcpStream>) + Sync,
+{
+    thread::scope(|scope| {
+        let handle = &handle;
+        let loops: Vec<_> = listeners
+            .iter()
+            .map(|listener| {
+                scope.spawn(move || {
+                    let result = accept_loop(listener, shutdown, handle);
+                    if result.is_err() {
+                        shutdown.store(true, Ordering::SeqCst);
+                    }
+                    result
+                })
+            })
+            .collect();
+
+        let mut result = Ok(());
+        for accepting in loops {
+            // A panic in `handle` would already have torn down the thread, pass it on.
+            let outcome = accepting.join().unwrap_or_else(|panic| std::panic::resume_unwind(panic));
+            result = result.and(outcome);
+        }
+        result
+    })
+}
+
+/// Caps how many connections can be handled at once.
+///
+/// Each accepted connection takes a slot with `try_acquire`, which is given back when the
+/// returned guard is dropped at the end of handling. Cloned limits share the same count.
+///
+/// The limit can also be put into draining mode ahead of a shutdown, after which no new
+/// connections get a slot but the ones that already have one carry on.
+#[derive(Debug, Clone)]
+pub struct ConnectionLimit {
+    max: usize,
+    active: Arc<AtomicUsize>,
+    draining: Arc<AtomicBool>,
+}
+impl ConnectionLimit {
+    /// Allow up to `max` connections at once.
+    pub fn new(max: usize) -> ConnectionLimit {
+        ConnectionLimit {
+            max,
+            active: Arc::new(AtomicUsize::new(0)),
+            draining: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// Take a slot for a new connection, or `None` if we're already at the limit or
+    /// draining.
+    pub fn try_acquire(&self) -> Option<ConnectionGuard> {
+        if self.is_draining() {
+            return None;
+        }
+        self.active
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |active| (active < self.max).then_some(active + 1))
+            .ok()
+            .map(|_| ConnectionGuard { active: Arc::clone(&self.active) })
+    }
+
+    /// How many connections are being handled right now.
+    pub fn active(&self) -> usize {
+        self.active.load(Ordering::SeqCst)
+    }
+
+    /// Stop handing out slots, so new connections are turned away while the ones
+    /// already being handled finish.
+    pub fn start_draining(&self) {
+        self.draining.store(true, Ordering::SeqCst);
+    }
+
+    /// Whether `start_draining` has been called.
+    pub fn is_draining(&self) -> bool {
+        self.draining.load(Ordering::SeqCst)
+    }
+
+    /// Whether we're draining and the last connection has finished.
+    pub fn is_drained(&self) -> bool {
+        self.is_draining() && self.active() == 0
+    }
+}
+
+/// Wait for `limit` to be drained and then set `shutdown`, so the accept loop stops once
+/// there's nothing left to finish. Returns early if `shutdown` is set some other way.
+pub fn shut_down_when_drained(limit: &ConnectionLimit, shutdown: &AtomicBool) {
+    while !shutdown.load(Ordering::SeqCst) {
+        if limit.is_drained() {
+            println!("Finished draining.");
+            shutdown.store(true, Ordering::SeqCst);
+            return;
+        }
+        thread::sleep(DRAIN_POLL_INTERVAL);
+    }
+}
+
+/// A connection's slot in a `ConnectionLimit`, given back when this is dropped.
+#[derive(Debug)]
+pub struct ConnectionGuard {
+    active: Arc<AtomicUsize>,
+}
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        self.active.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Turn a connection away with `503 Service Unavailable` because we're at the limit or
+/// draining.
+///
+/// This happens on the accepting thread without reading the request, so it only waits
+/// so long for the client before dropping the connection anyway.
+pub fn reject_connection(mut stream: TcpStream) -> io::Result<()> {
+    stream.set_write_timeout(Some(REJECT_TIMEOUT))?;
+    let response = error_response(503).with_header("Connection", "close");
+    stream.write_all(&response.to_bytes())?;
+    stream.flush()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+    use std::time::Instant;
+    use crate::http::method::Method;
+    use crate::http::request::Request;
+    use crate::http::response::Response;
+    use crate::server::connection::Server;
+    use crate::server::router::Router;
+    use crate::thread_pool::thread_pool::ThreadPool;
+
+    #[test]
+    fn test_accept_loop_stops_on_shutdown() {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("Failed to bind test listener");
+        let address = listener.local_addr().unwrap();
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let (tx, rx) = mpsc::channel();
+
+        let server = {
+            let shutdown = Arc::clone(&shutdown);
+            thread::spawn(move || {
+                accept_loop(&listener, &shutdown, |stream| {
+                    // Accepted streams should be blocking again.
+                    let stream = stream.expect("Accept should succeed");
+                    tx.send(stream.peer_addr().unwrap()).unwrap();
+                })
+            })
+        };
+
+        let first = TcpStream::connect(address).unwrap();
+        let second = TcpStream::connect(address).unwrap();
+        assert_eq!(rx.recv().unwrap(), first.local_addr().unwrap());
+        assert_eq!(rx.recv().unwrap(), second.local_addr().unwrap());
+
+        shutdown.store(true, Ordering::SeqCst);
+        server.join().expect("Accept loop panicked").expect("Accept loop failed");
+    }
+
+    #[test]
+    fn test_accept_errors_dont_stop_the_loop() {
+        let shutdown = AtomicBool::new(false);
+        let mut attempts = vec![
+            Ok(Some(1)),
+            Err(io::Error::other("Too many open files")),
+            Ok(None),
+            Err(io::Error::from(io::ErrorKind::ConnectionAborted)),
+            Ok(Some(2)),
+        ]
+        .into_iter();
+        let mut handled = Vec::new();
+
+        run_accept_loop(
+            &shutdown,
+            || {
+                let attempt = attempts.next().unwrap_or(Ok(None));
+                if attempts.len() == 0 {
+                    shutdown.store(true, Ordering::SeqCst);
+                }
+                attempt
+            },
+            |stream| handled.push(stream.map_err(|err| err.to_string())),
+        );
+
+        assert_eq!(
+            handled,
+            [
+                Ok(1),
+                Err(String::from("Too many open files")),
+                Err(io::Error::from(io::ErrorKind::ConnectionAborted).to_string()),
+                Ok(2),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_connections_are_served_in_parallel() {
+        const CONNECTIONS: usize = 4;
+        const DELAY: Duration = Duration::from_millis(300);
+
+        let listener = TcpListener::bind("127.0.0.1:0").expect("Failed to bind test listener");
+        let address = listener.local_addr().unwrap();
+        let shutdown = Arc::new(AtomicBool::new(false));
+
+        let mut router = Router::new();
+        router.route(Method::Get, "/slow", |_: &Request| {
+            thread::sleep(DELAY);
+            Response::text(200, "done")
+        });
+        let server = Arc::new(Server::new(".", router));
+
+        let accepting = {
+            let shutdown = Arc::clone(&shutdown);
+            thread::spawn(move || {
+                let pool = ThreadPool::new(CONNECTIONS).unwrap();
+                accept_loop(&listener, &shutdown, |stream| {
+                    let stream = stream.expect("Accept should succeed");
+                    let server = Arc::clone(&server);
+                    pool.execute(move || server.handle_connection(stream));
+                })
+            })
+        };
+
+        let start = Instant::now();
+        let clients: Vec<_> = (0..CONNECTIONS)
+            .map(|_| {
+                thread::spawn(move || {
+                    let mut client = TcpStream::connect(address).unwrap();
+                    client.write_all(b"GET /slow HTTP/1.1\r\nConnection: close\r\n\r\n").unwrap();
+                    let mut response = String::new();
+                    client.read_to_string(&mut response).unwrap();
+                    response
+                })
+            })
+            .collect();
+        for client in clients {
+            assert!(client.join().unwrap().starts_with("HTTP/1.1 200 OK\r\n"));
+        }
+        let elapsed = start.elapsed();
+
+        // One at a time this would take CONNECTIONS * DELAY.
+        assert!(elapsed < DELAY * 2, "Connections were serialized, took {:?}", elapsed);
+
+        shutdown.store(true, Ordering::SeqCst);
+        accepting.join().expect("Accept loop panicked").expect("Accept loop failed");
+    }
+
+    #[test]
+    fn test_connection_limit() {
+        let limit = ConnectionLimit::new(2);
+        let first = limit.try_acquire().expect("Should be under the limit");
+        let _second = limit.clone().try_acquire().expect("Should be under the limit");
+        assert!(limit.try_acquire().is_none());
+        assert_eq!(limit.active(), 2);
+
+        drop(first);
+        assert_eq!(limit.active(), 1);
+        assert!(limit.try_acquire().is_some());
+    }
+
+    #[test]
+    fn test_connections_over_the_limit_get_503() {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("Failed to bind test listener");
+        let address = listener.local_addr().unwrap();
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let limit = ConnectionLimit::new(1);
+
+        let accepting = {
+            let shutdown = Arc::clone(&shutdown);
+            let limit = limit.clone();
+            thread::spawn(move || {
+                // Hang on to accepted connections so they stay in flight.
+                let mut held = Vec::new();
+                accept_loop(&listener, &shutdown, |stream| {
+                    let stream = stream.expect("Accept should succeed");
+                    match limit.try_acquire() {
+                        Some(guard) => held.push((guard, stream)),
+                        None => reject_connection(stream).expect("Rejecting should succeed"),
+                    }
+                })
+            })
+        };
+
+        let _first = TcpStream::connect(address).unwrap();
+        while limit.active() == 0 {
+            thread::sleep(Duration::from_millis(10));
+        }
+        let mut second = TcpStream::connect(address).unwrap();
+        let mut response = String::new();
+        second.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 503 Service Unavailable\r\n"));
+        assert!(response.contains("\r\nConnection: close\r\n"));
+
+        shutdown.store(true, Ordering::SeqCst);
+        accepting.join().expect("Accept loop panicked").expect("Accept loop failed");
+    }
+
+    #[test]
+    fn test_draining_turns_away_new_connections() {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("Failed to bind test listener");
+        let address = listener.local_addr().unwrap();
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let limit = ConnectionLimit::new(usize::MAX);
+
+        // Work that was taken on before draining started.
+        let queued = limit.try_acquire().expect("Should be under the limit");
+        let pool = ThreadPool::new(1).unwrap();
+        let (done, finished) = mpsc::channel();
+        pool.execute(move || {
+            thread::sleep(Duration::from_millis(200));
+            done.send(()).unwrap();
+            drop(queued);
+        });
+        limit.start_draining();
+        assert!(!limit.is_drained());
+
+        let accepting = {
+            let shutdown = Arc::clone(&shutdown);
+            let limit = limit.clone();
+            thread::spawn(move || {
+                accept_loop(&listener, &shutdown, |stream| {
+                    let stream = stream.expect("Accept should succeed");
+                    assert!(limit.try_acquire().is_none(), "Nothing new should get a slot while draining");
+                    reject_connection(stream).expect("Rejecting should succeed");
+                })
+            })
+        };
+        let mut client = TcpStream::connect(address).unwrap();
+        let mut response = String::new();
+        client.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 503 Service Unavailable\r\n"));
+
+        // The accept loop is only stopped once the queued work has finished.
+        shut_down_when_drained(&limit, &shutdown);
+        assert_eq!(finished.try_recv(), Ok(()));
+        assert!(limit.is_drained());
+        accepting.join().expect("Accept loop panicked").expect("Accept loop failed");
+    }
+
+    #[test]
+    fn test_accept_all_serves_every_listener() {
+        let ipv4 = TcpListener::bind("127.0.0.1:0").expect("Failed to bind test listener");
+        let port = ipv4.local_addr().unwrap().port();
+        let ipv6 = TcpListener::bind(("::1", port)).expect("Failed to bind IPv6 test listener");
+        let shutdown = Arc::new(AtomicBool::new(false));
+
+        let mut router = Router::new();
+        router.route(Method::Get, "/hello", |_: &Request| Response::text(200, "hello"));
+        let server = Arc::new(Server::new(".", router));
+
+        let accepting = {
+            let shutdown = Arc::clone(&shutdown);
+            thread::spawn(move || {
+                accept_all(&[ipv4, ipv6], &shutdown, |stream| {
+                    server.handle_connection(stream.expect("Accept should succeed"));
+                })
+            })
+        };
+
+        for address in ["127.0.0.1", "::1"] {
+            let mut client = TcpStream::connect((address, port)).unwrap();
+            client.write_all(b"GET /hello HTTP/1.1\r\nConnection: close\r\n\r\n").unwrap();
+            let mut response = String::new();
+            client.read_to_string(&mut response).unwrap();
+            assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "Not served on {}: {}", address, response);
+        }
+
+        shutdown.store(true, Ordering::SeqCst);
+        accepting.join().expect("Accept loop panicked").expect("Accept loop failed");
+    }
+}
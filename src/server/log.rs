@@ -0,0 +1,124 @@
+use std::net::SocketAddr;
+use std::time::Duration;
+
+/// How access log lines are written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LogFormat {
+    /// Space separated fields, e.g. `127.0.0.1:51234 65f1c2a0-42 GET /index.html 200 512 3ms`.
+    #[default]
+    Plain,
+    /// One JSON object per line, for feeding to a log aggregator.
+    Json,
+}
+
+/// Everything the access log records about a handled request.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogEntry<'a> {
+    /// The client's address, if the connection has one.
+    pub remote_addr: Option<SocketAddr>,
+    pub request_id: &'a str,
+    /// `-` for a request we couldn't parse.
+    pub method: &'a str,
+    pub path: &'a str,
+    pub status: u16,
+    pub duration: Duration,
+    /// How many bytes of response were written, headers included.
+    pub bytes_sent: u64,
+}
+
+/// Write an access log line for a handled request to stderr.
+pub fn log_request(entry: &LogEntry, format: LogFormat) {
+    eprintln!("{}", format_log(entry, format));
+}
+
+/// Build the access log line written by `log_request`.
+///
+/// In the plain format the fields are the address, the request's ID, the method, the
+/// path, the status, the bytes sent and the duration, with `-` for an address we don't
+/// have. The JSON format has `remote_addr`, `request_id`, `method`, `path`, `status`,
+/// `duration_ms` and `bytes_sent`, with `null` for an address we don't have.
+pub fn format_log(entry: &LogEntry, format: LogFormat) -> String {
+    let millis = entry.duration.as_millis();
+    match format {
+        LogFormat::Plain => {
+            let addr = entry.remote_addr.map_or_else(|| String::from("-"), |addr| addr.to_string());
+            format!(
+                "{} {} {} {} {} {} {}ms",
+                addr, entry.request_id, entry.method, entry.path, entry.status, entry.bytes_sent, millis
+            )
+        }
+        LogFormat::Json => {
+            let addr = entry.remote_addr.map_or_else(|| String::from("null"), |addr| json_string(&addr.to_string()));
+            format!(
+                "{{\"remote_addr\":{},\"request_id\":{},\"method\":{},\"path\":{},\"status\":{},\"duration_ms\":{},\"bytes_sent\":{}}}",
+                addr,
+                json_string(entry.request_id),
+                json_string(entry.method),
+                json_string(entry.path),
+                entry.status,
+                millis,
+                entry.bytes_sent,
+            )
+        }
+    }
+}
+
+// Quote a string for JSON, escaping anything that would end it early or break the line.
+fn json_string(text: &str) -> String {
+    let mut quoted = String::with_capacity(text.len() + 2);
+    quoted.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if c.is_control() => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry() -> LogEntry<'static> {
+        LogEntry {
+            remote_addr: Some("127.0.0.1:51234".parse().unwrap()),
+            request_id: "abc-1",
+            method: "GET",
+            path: "/index.html",
+            status: 200,
+            duration: Duration::from_micros(3400),
+            bytes_sent: 512,
+        }
+    }
+
+    #[test]
+    fn test_format_plain_log() {
+        assert_eq!(format_log(&entry(), LogFormat::Plain), "127.0.0.1:51234 abc-1 GET /index.html 200 512 3ms");
+        let entry = LogEntry { remote_addr: None, status: 404, duration: Duration::ZERO, ..entry() };
+        assert_eq!(format_log(&entry, LogFormat::Plain), "- abc-1 GET /index.html 404 512 0ms");
+    }
+
+    #[test]
+    fn test_format_json_log() {
+        assert_eq!(
+            format_log(&entry(), LogFormat::Json),
+            "{\"remote_addr\":\"127.0.0.1:51234\",\"request_id\":\"abc-1\",\"method\":\"GET\",\"path\":\"/index.html\",\"status\":200,\"duration_ms\":3,\"bytes_sent\":512}"
+        );
+        let entry = LogEntry { remote_addr: None, ..entry() };
+        assert!(format_log(&entry, LogFormat::Json).starts_with("{\"remote_addr\":null,"));
+    }
+
+    #[test]
+    fn test_json_string_escapes() {
+        assert_eq!(json_string("/a\"b\\c"), "\"/a\\\"b\\\\c\"");
+        assert_eq!(json_string("line\nbreak\u{1}"), "\"line\\nbreak\\u0001\"");
+    }
+}
@@ -0,0 +1,92 @@
+use std::sync::Mutex;
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use crate::thread_pool::thread_pool::PoolStats;
+
+/// Counters for the requests a server has handled, shared by all of the workers.
+#[derive(Default)]
+pub struct Metrics {
+    requests: AtomicUsize,
+    // Responses sent, by status code. A BTreeMap so they're listed in order.
+    statuses: Mutex<BTreeMap<u16, usize>>,
+    bytes_sent: AtomicU64,
+}
+impl Metrics {
+    /// Create a set of metrics with everything at zero.
+    pub fn new() -> Metrics {
+        Metrics::default()
+    }
+
+    /// Count a request that was answered with `status` in `bytes_sent` bytes.
+    pub fn record(&self, status: u16, bytes_sent: u64) {
+        self.requests.fetch_add(1, Ordering::SeqCst);
+        self.bytes_sent.fetch_add(bytes_sent, Ordering::SeqCst);
+        *self.statuses.lock().unwrap().entry(status).or_insert(0) += 1;
+    }
+
+    /// The number of requests answered so far.
+    pub fn requests(&self) -> usize {
+        self.requests.load(Ordering::SeqCst)
+    }
+
+    /// The total number of bytes written in responses so far, headers included.
+    pub fn total_bytes_sent(&self) -> u64 {
+        self.bytes_sent.load(Ordering::SeqCst)
+    }
+
+    /// Render the metrics (and the pool's, if we have them) in the Prometheus text format,
+    /// one `name value` line per counter.
+    pub fn render(&self, pool: Option<&PoolStats>) -> String {
+        let mut text = String::new();
+        text.push_str("# TYPE http_requests_total counter\n");
+        text.push_str(&format!("http_requests_total {}\n", self.requests()));
+        text.push_str("# TYPE http_responses_total counter\n");
+        for (status, count) in self.statuses.lock().unwrap().iter() {
+            text.push_str(&format!("http_responses_total{{status=\"{}\"}} {}\n", status, count));
+        }
+        text.push_str("# TYPE http_response_bytes_total counter\n");
+        text.push_str(&format!("http_response_bytes_total {}\n", self.total_bytes_sent()));
+
+        if let Some(pool) = pool {
+            text.push_str("# TYPE pool_active_workers gauge\n");
+            text.push_str(&format!("pool_active_workers {}\n", pool.active_workers()));
+            text.push_str("# TYPE pool_queued_jobs gauge\n");
+            text.push_str(&format!("pool_queued_jobs {}\n", pool.queued_jobs()));
+            text.push_str("# TYPE pool_panics_total counter\n");
+            text.push_str(&format!("pool_panics_total {}\n", pool.panic_count()));
+        }
+
+        text
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::thread_pool::thread_pool::ThreadPool;
+
+    #[test]
+    fn test_render_metrics() {
+        let metrics = Metrics::new();
+        metrics.record(200, 100);
+        metrics.record(404, 50);
+        metrics.record(200, 100);
+
+        let text = metrics.render(None);
+        assert!(text.contains("\nhttp_requests_total 3\n"));
+        assert!(text.contains("\nhttp_responses_total{status=\"200\"} 2\n"));
+        assert!(text.contains("\nhttp_responses_total{status=\"404\"} 1\n"));
+        assert!(text.contains("\nhttp_response_bytes_total 250\n"));
+        assert_eq!(metrics.total_bytes_sent(), 250);
+        assert!(!text.contains("pool_"));
+    }
+
+    #[test]
+    fn test_render_pool_metrics() {
+        let tp = ThreadPool::new(2).unwrap();
+        let text = Metrics::new().render(Some(&tp.stats()));
+        assert!(text.contains("\npool_active_workers 0\n"));
+        assert!(text.contains("\npool_queued_jobs 0\n"));
+    }
+}
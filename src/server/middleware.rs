@@ -0,0 +1,21 @@
+use crate::http::request::Request;
+use crate::http::response::Response;
+
+/// Something that gets a look at every request before it's handled, like an auth check.
+///
+/// Returning `Some(response)` short-circuits the request: that response is sent and
+/// nothing else (including any later middleware) runs. Returning `None` lets the request
+/// carry on. Middleware is shared between the worker threads so it must be `Send + Sync`.
+pub trait Middleware: Send + Sync {
+    fn handle(&self, request: &Request) -> Option<Response>;
+}
+
+// Plain functions and closures make perfectly good middleware.
+impl<F> Middleware for F
+where
+    F: Fn(&Request) -> Option<Response> + Send + Sync,
+{
+    fn handle(&self, request: &Request) -> Option<Response> {
+        self(request)
+    }
+}
@@ -0,0 +1,17 @@
+pub mod auth;
+pub mod cache;
+pub mod connection;
+pub mod cors;
+pub mod handler;
+pub mod listener;
+pub mod log;
+pub mod metrics;
+pub mod rate_limit;
+pub mod middleware;
+pub mod reader;
+pub mod request_id;
+pub mod router;
+pub mod static_files;
+pub mod tls;
+pub mod transport;
+pub mod virtual_hosts;
@@ -0,0 +1,137 @@
+use std::sync::Mutex;
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+use std::collections::HashMap;
+
+/// How often idle buckets are swept out, so clients that have gone away don't pile up.
+const CLEANUP_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Limits how often each client can make requests, going by their IP address.
+///
+/// Every client gets a bucket of `burst` tokens that refills at `rate` tokens a second,
+/// and each request takes one. A client that has used up its bucket is turned away
+/// until it refills. Buckets that have filled back up are forgotten every so often,
+/// since a full bucket is no different to one we've never seen.
+pub struct RateLimiter {
+    rate: f64,
+    burst: f64,
+    state: Mutex<Buckets>,
+}
+impl RateLimiter {
+    /// Allow each client `rate` requests a second, with bursts of up to `burst`. Both
+    /// are at least one.
+    pub fn new(rate: u32, burst: u32) -> RateLimiter {
+        RateLimiter {
+            rate: f64::from(rate.max(1)),
+            burst: f64::from(burst.max(1)),
+            state: Mutex::new(Buckets { clients: HashMap::new(), last_cleanup: Instant::now() }),
+        }
+    }
+
+    /// Take a token from `ip`'s bucket, returning whether it had one to take. A request
+    /// that gets `false` should be turned away.
+    pub fn check(&self, ip: IpAddr) -> bool {
+        self.check_at(ip, Instant::now())
+    }
+
+    /// How many whole seconds a client that was turned away should wait before trying
+    /// again, for the `Retry-After` header.
+    pub fn retry_after(&self) -> u64 {
+        (1.0 / self.rate).ceil() as u64
+    }
+
+    /// How many clients we're keeping a bucket for.
+    pub fn tracked_clients(&self) -> usize {
+        self.state.lock().unwrap().clients.len()
+    }
+
+    // `check`, as of `now`, so tests don't have to wait for buckets to refill.
+    fn check_at(&self, ip: IpAddr, now: Instant) -> bool {
+        let mut state = self.state.lock().unwrap();
+        if now.saturating_duration_since(state.last_cleanup) >= CLEANUP_INTERVAL {
+            state.clean_up(now, self.rate, self.burst);
+        }
+
+        let bucket = state.clients.entry(ip).or_insert(Bucket { tokens: self.burst, updated: now });
+        bucket.refill(now, self.rate, self.burst);
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+struct Buckets {
+    clients: HashMap<IpAddr, Bucket>,
+    last_cleanup: Instant,
+}
+impl Buckets {
+    // Forget every client whose bucket would be full by now.
+    fn clean_up(&mut self, now: Instant, rate: f64, burst: f64) {
+        self.clients.retain(|_, bucket| {
+            bucket.refill(now, rate, burst);
+            bucket.tokens < burst
+        });
+        self.last_cleanup = now;
+    }
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+impl Bucket {
+    // Add the tokens earned since the bucket was last looked at, up to `burst`.
+    fn refill(&mut self, now: Instant, rate: f64, burst: f64) {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * rate).min(burst);
+        self.updated = now;
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exhausted_bucket_is_limited() {
+        let limiter = RateLimiter::new(1, 3);
+        let ip: IpAddr = "10.0.0.1".parse().unwrap();
+        let now = Instant::now();
+        for _ in 0..3 {
+            assert!(limiter.check_at(ip, now));
+        }
+        assert!(!limiter.check_at(ip, now));
+
+        // Another client has a bucket of its own.
+        assert!(limiter.check_at("10.0.0.2".parse().unwrap(), now));
+        assert!(!limiter.check_at(ip, now));
+    }
+
+    #[test]
+    fn test_bucket_refills() {
+        let limiter = RateLimiter::new(2, 1);
+        let ip: IpAddr = "::1".parse().unwrap();
+        let now = Instant::now();
+        assert!(limiter.check_at(ip, now));
+        assert!(!limiter.check_at(ip, now));
+        assert!(limiter.check_at(ip, now + Duration::from_millis(500)));
+        assert_eq!(limiter.retry_after(), 1);
+    }
+
+    #[test]
+    fn test_full_buckets_are_cleaned_up() {
+        let limiter = RateLimiter::new(1, 2);
+        let now = Instant::now();
+        limiter.check_at("10.0.0.1".parse().unwrap(), now);
+        limiter.check_at("10.0.0.2".parse().unwrap(), now);
+        assert_eq!(limiter.tracked_clients(), 2);
+
+        // Both buckets have long since refilled, so only the client asking now is kept.
+        limiter.check_at("10.0.0.3".parse().unwrap(), now + CLEANUP_INTERVAL * 2);
+        assert_eq!(limiter.tracked_clients(), 1);
+    }
+}
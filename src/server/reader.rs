@@ -0,0 +1,602 @@
+use std::io;
+use std::fmt;
+use std::mem;
+use std::error;
+use std::io::prelude::*;
+use crate::http::chunked::{ChunkedDecoder, ChunkedError};
+use crate::http::request::{header_end, HTTP_1_1};
+
+/// How many bytes we try to pull off the stream per read.
+const CHUNK_SIZE: usize = 1024;
+
+/// The interim response telling a client that sent `Expect: 100-continue` to go ahead and
+/// send its body.
+const CONTINUE: &[u8] = b"HTTP/1.1 100 Continue\r\n\r\n";
+
+/// Reads requests off a stream one at a time.
+///
+/// A single `read` may return only part of a request, or (when requests are pipelined)
+/// the end of one request and the start of the next. The reader keeps hold of anything
+/// past the end of the current request so it's there for the next call.
+pub struct RequestReader {
+    buffer: Vec<u8>,
+    max_body_size: usize,
+    max_header_size: usize,
+    max_request_line: usize,
+}
+impl RequestReader {
+    /// Create a reader with nothing buffered and no limit on the size of a body, its
+    /// headers or the request line.
+    pub fn new() -> RequestReader {
+        RequestReader::with_max_body_size(usize::MAX)
+    }
+
+    /// Create a reader that won't read bodies longer than `max_body_size` bytes.
+    ///
+    /// A request whose `Content-Length` is over the limit is an `InvalidData` error
+    /// wrapping `BodyTooLarge`. Whatever follows on the stream is the unread body, so the
+    /// connection can't be used for any more requests.
+    pub fn with_max_body_size(max_body_size: usize) -> RequestReader {
+        RequestReader {
+            buffer: Vec::new(),
+            max_body_size,
+            max_header_size: usize::MAX,
+            max_request_line: usize::MAX,
+        }
+    }
+
+    /// Stop reading a request whose request line and headers go on for more than
+    /// `max_header_size` bytes, so a client can't make us buffer as much as it likes.
+    pub fn with_max_header_size(mut self, max_header_size: usize) -> RequestReader {
+        self.max_header_size = max_header_size;
+        self
+    }
+
+    /// Stop reading a request whose request line is longer than `max_request_line` bytes,
+    /// which mostly means one with a ridiculously long URI.
+    pub fn with_max_request_line(mut self, max_request_line: usize) -> RequestReader {
+        self.max_request_line = max_request_line;
+        self
+    }
+
+    /// Read a whole request off the stream.
+    ///
+    /// Reads in chunks until the end of the headers (a blank line) has been seen, then keeps
+    /// reading until `Content-Length` bytes of body have arrived. If the client closes the
+    /// connection early whatever was received so far is returned, so an empty request
+    /// means the client went away without sending anything.
+    ///
+    /// A body sent with `Transfer-Encoding: chunked` is decoded as it's read, and the
+    /// request is returned with the decoded body after its headers. One that can't be
+    /// decoded (or is over the limit) is an `InvalidData` error wrapping a `ChunkedError`.
+    /// A `Content-Length` that isn't a number is an `InvalidData` error too, as is a
+    /// request with several `Content-Length`s that disagree, with both a `Content-Length`
+    /// and a chunked body, or with a `Transfer-Encoding` that doesn't end in chunked.
+    /// Different servers could disagree about where those end, which is how requests get
+    /// smuggled past a proxy. Headers over the limit are an `InvalidData` error wrapping
+    /// `HeadersTooLarge`, a request line over its limit one wrapping `RequestLineTooLong`,
+    /// and a `Content-Length` over the body limit one wrapping `BodyTooLarge`.
+    ///
+    /// A client that sent `Expect: 100-continue` is waiting for the go-ahead before it
+    /// sends the body, so it's sent a `100 Continue` first. Unless the body is over the
+    /// limit, in which case it's never asked for. HTTP/1.0 clients don't know about interim
+    /// responses, so they're never sent one.
+    pub fn read_request<S: Read + Write>(&mut self, stream: &mut S) -> io::Result<Vec<u8>> {
+        let mut chunk = [0; CHUNK_SIZE];
+
+        // Keep reading until we find the end of the headers.
+        let header_end = loop {
+            // The request line is checked on its own first, so a long URI is reported as
+            // that rather than as the headers being too large.
+            let line_len = self.buffer.iter().position(|&byte| byte == b'\n').unwrap_or(self.buffer.len());
+            if line_len > self.max_request_line {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    RequestLineTooLong { limit: self.max_request_line },
+                ));
+            }
+            match header_end(&self.buffer) {
+                Some(end) if end > self.max_header_size => return Err(self.headers_too_large()),
+                Some(end) => break end,
+                // Give up as soon as there's too much without having seen the end.
+                None if self.buffer.len() > self.max_header_size => return Err(self.headers_too_large()),
+                None => {}
+            }
+            let bytes_read = stream.read(&mut chunk)?;
+            if bytes_read == 0 {
+                return Ok(mem::take(&mut self.buffer));
+            }
+            self.buffer.extend_from_slice(&chunk[..bytes_read]);
+        };
+
+        // A chunked body has no Content-Length, it carries on until the zero-length chunk.
+        // Any other transfer coding leaves no way to tell where the body ends.
+        let chunked = is_chunked(&self.buffer[..header_end]);
+        if !chunked && !raw_headers(&self.buffer[..header_end], "transfer-encoding").is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "A Transfer-Encoding has to end with chunked",
+            ));
+        }
+        if chunked && !raw_headers(&self.buffer[..header_end], "content-length").is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "A chunked body can't have a Content-Length as well",
+            ));
+        }
+        let body_len = if chunked { None } else { Some(content_length(&self.buffer[..header_end])?) };
+
+        // Don't bother reading a body that's too big.
+        if body_len.is_some_and(|body_len| body_len > self.max_body_size) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                BodyTooLarge {
+                    limit: self.max_body_size,
+                    expectation: !raw_headers(&self.buffer[..header_end], "expect").is_empty(),
+                },
+            ));
+        }
+
+        // Only ask for the body if the client hasn't started sending it anyway.
+        if expects_continue(&self.buffer[..header_end]) && self.buffer.len() == header_end && body_len != Some(0) {
+            stream.write_all(CONTINUE)?;
+            stream.flush()?;
+        }
+
+        let Some(body_len) = body_len else {
+            return self.read_chunked_body(stream, header_end);
+        };
+
+        // Then read the rest of the body, some of which may have come in with the headers.
+        let expected_len = header_end + body_len;
+        while self.buffer.len() < expected_len {
+            let bytes_read = stream.read(&mut chunk)?;
+            if bytes_read == 0 {
+                break;
+            }
+            self.buffer.extend_from_slice(&chunk[..bytes_read]);
+        }
+
+        // Anything past the end of this request belongs to the next one.
+        let next = self.buffer.split_off(expected_len.min(self.buffer.len()));
+        Ok(mem::replace(&mut self.buffer, next))
+    }
+
+    fn headers_too_large(&self) -> io::Error {
+        io::Error::new(io::ErrorKind::InvalidData, HeadersTooLarge { limit: self.max_header_size })
+    }
+
+    // Keep reading until the chunked body starting at `header_end` is complete, decoding
+    // chunks as they arrive so only the one still coming in is held raw. Trailers count
+    // against the header limit, and the raw body (framing and all) can't be much bigger
+    // than the decoded one is allowed to be.
+    fn read_chunked_body<R: Read>(&mut self, stream: &mut R, header_end: usize) -> io::Result<Vec<u8>> {
+        let mut chunk = [0; CHUNK_SIZE];
+        let mut decoder = ChunkedDecoder::new(self.max_body_size).with_max_trailer_size(self.max_header_size);
+        let max_raw_size = self.max_body_size.saturating_add(self.max_header_size);
+        let mut raw = self.buffer.split_off(header_end);
+        let mut raw_size = 0;
+        loop {
+            let used = decoder.decode(&raw).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+            raw.drain(..used);
+            raw_size += used;
+            if raw_size > max_raw_size {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, ChunkedError::TooLarge));
+            }
+            if decoder.is_done() {
+                self.buffer.extend_from_slice(&decoder.into_body());
+                return Ok(mem::replace(&mut self.buffer, raw));
+            }
+
+            let bytes_read = stream.read(&mut chunk)?;
+            if bytes_read == 0 {
+                return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "The connection closed partway through a chunked body"));
+            }
+            raw.extend_from_slice(&chunk[..bytes_read]);
+        }
+    }
+}
+
+impl Default for RequestReader {
+    fn default() -> RequestReader {
+        RequestReader::new()
+    }
+}
+
+// Pull the Content-Length out of the raw header bytes, defaulting to zero when it's
+// missing. One that isn't a number means we can't tell where the body ends, and neither
+// do several that aren't all the same (whether as separate headers or a list).
+fn content_length(headers: &[u8]) -> io::Result<usize> {
+    let mut length = None;
+    for value in raw_headers(headers, "content-length").iter().flat_map(|value| value.split(',')) {
+        let value = value.trim();
+        let parsed: usize = value
+            .parse()
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, format!("Invalid Content-Length: {:?}", value)))?;
+        if length.is_some_and(|length| length != parsed) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "Conflicting Content-Length headers"));
+        }
+        length = Some(parsed);
+    }
+    Ok(length.unwrap_or(0))
+}
+
+// Whether the client is waiting for a `100 Continue` before sending its body. The
+// expectation is ignored from anything older than HTTP/1.1, which mustn't be sent a 1xx
+// response at all.
+fn expects_continue(headers: &[u8]) -> bool {
+    let request_line = headers.split(|&byte| byte == b'\n').next().unwrap_or(&[]);
+    request_line.trim_ascii_end().ends_with(HTTP_1_1.as_bytes())
+        && raw_headers(headers, "expect").iter().any(|value| value.trim().eq_ignore_ascii_case("100-continue"))
+}
+
+// Whether the body is chunked, which it is when chunked is the last transfer coding.
+fn is_chunked(headers: &[u8]) -> bool {
+    raw_headers(headers, "transfer-encoding")
+        .last()
+        .is_some_and(|value| value.rsplit(',').next().is_some_and(|coding| coding.trim().eq_ignore_ascii_case("chunked")))
+}
+
+// Find every value of a header in the raw header bytes, in the order they were sent.
+fn raw_headers(headers: &[u8], name: &str) -> Vec<String> {
+    String::from_utf8_lossy(headers)
+        .lines()
+        .filter_map(|line| line.split_once(':'))
+        .filter(|(key, _)| key.trim().eq_ignore_ascii_case(name))
+        .map(|(_, value)| String::from(value))
+        .collect()
+}
+
+// Errors:
+// The request line and headers were longer than the reader allows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HeadersTooLarge {
+    // The most header bytes that were allowed.
+    pub limit: usize,
+}
+impl fmt::Display for HeadersTooLarge {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "The request headers were over the {} byte limit!", self.limit)
+    }
+}
+impl error::Error for HeadersTooLarge {}
+
+// Errors:
+// The request line was longer than the reader allows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RequestLineTooLong {
+    // The longest request line that was allowed.
+    pub limit: usize,
+}
+impl fmt::Display for RequestLineTooLong {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "The request line was over the {} byte limit!", self.limit)
+    }
+}
+impl error::Error for RequestLineTooLong {}
+
+// Errors:
+// The request said it had a body longer than the reader allows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BodyTooLarge {
+    // The longest body that was allowed.
+    pub limit: usize,
+    // Whether the request came with an `Expect` header, which it's too big to meet.
+    pub expectation: bool,
+}
+impl fmt::Display for BodyTooLarge {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "The request body was over the {} byte limit!", self.limit)
+    }
+}
+impl error::Error for BodyTooLarge {}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+    use std::time::Duration;
+    use std::net::{Shutdown, TcpListener, TcpStream};
+
+    // Send each part with a pause in between so they arrive as separate reads,
+    // and return what read_request assembled on the other end.
+    fn read_parts(parts: Vec<Vec<u8>>) -> Vec<u8> {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("Failed to bind test listener");
+        let address = listener.local_addr().unwrap();
+        let client = thread::spawn(move || {
+            let mut client = TcpStream::connect(address).expect("Failed to connect to test listener");
+            for part in parts {
+                client.write_all(&part).unwrap();
+                client.flush().unwrap();
+                thread::sleep(Duration::from_millis(50));
+            }
+            // Keep the connection open until the server is done reading.
+            client
+        });
+
+        let (mut stream, _) = listener.accept().expect("Failed to accept test connection");
+        let request = RequestReader::new().read_request(&mut stream).expect("Failed to read request");
+        client.join().unwrap().shutdown(Shutdown::Both).unwrap();
+        request
+    }
+
+    #[test]
+    fn test_read_headers_split_across_reads() {
+        let request = read_parts(vec![
+            b"GET / HTTP/1.1\r\nHo".to_vec(),
+            b"st: localhost\r\n".to_vec(),
+            b"\r\n".to_vec(),
+        ]);
+        assert_eq!(request, b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n");
+    }
+
+    #[test]
+    fn test_read_bare_lf_headers() {
+        let request = read_parts(vec![
+            b"POST / HTTP/1.1\nContent-Length: 5\n".to_vec(),
+            b"\nhello".to_vec(),
+        ]);
+        assert_eq!(request, b"POST / HTTP/1.1\nContent-Length: 5\n\nhello");
+    }
+
+    #[test]
+    fn test_read_body_split_across_reads() {
+        let request = read_parts(vec![
+            b"POST /form HTTP/1.1\r\nContent-Length: 11\r\n\r\nhello".to_vec(),
+            b" wor".to_vec(),
+            b"ld".to_vec(),
+        ]);
+        assert_eq!(request, b"POST /form HTTP/1.1\r\nContent-Length: 11\r\n\r\nhello world");
+    }
+
+    #[test]
+    fn test_read_larger_than_one_chunk() {
+        let request = read_parts(vec![
+            b"POST / HTTP/1.1\r\ncontent-length: 4000\r\n\r\n".to_vec(),
+            vec![b'a'; 4000],
+        ]);
+        assert_eq!(request.len(), "POST / HTTP/1.1\r\ncontent-length: 4000\r\n\r\n".len() + 4000);
+    }
+
+    #[test]
+    fn test_read_pipelined_requests() {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("Failed to bind test listener");
+        let address = listener.local_addr().unwrap();
+        let mut client = TcpStream::connect(address).expect("Failed to connect to test listener");
+        client
+            .write_all(b"POST /a HTTP/1.1\r\nContent-Length: 3\r\n\r\nabcGET /b HTTP/1.1\r\n\r\n")
+            .unwrap();
+        client.shutdown(Shutdown::Write).unwrap();
+
+        let (mut stream, _) = listener.accept().expect("Failed to accept test connection");
+        let mut reader = RequestReader::new();
+        assert_eq!(
+            reader.read_request(&mut stream).unwrap(),
+            b"POST /a HTTP/1.1\r\nContent-Length: 3\r\n\r\nabc"
+        );
+        assert_eq!(reader.read_request(&mut stream).unwrap(), b"GET /b HTTP/1.1\r\n\r\n");
+        assert!(reader.read_request(&mut stream).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_read_rejects_body_over_limit() {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("Failed to bind test listener");
+        let address = listener.local_addr().unwrap();
+        let mut client = TcpStream::connect(address).expect("Failed to connect to test listener");
+        client.write_all(b"POST / HTTP/1.1\r\nContent-Length: 20\r\nContent-Length: 20\r\n\r\n").unwrap();
+        client.write_all(&[b'a'; 20]).unwrap();
+        client.shutdown(Shutdown::Write).unwrap();
+
+        let (mut stream, _) = listener.accept().expect("Failed to accept test connection");
+        let err = RequestReader::with_max_body_size(10).read_request(&mut stream).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        let err = err.get_ref().unwrap().downcast_ref::<BodyTooLarge>().unwrap();
+        assert_eq!(*err, BodyTooLarge { limit: 10, expectation: false });
+    }
+
+    #[test]
+    fn test_read_sends_continue_before_body() {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("Failed to bind test listener");
+        let address = listener.local_addr().unwrap();
+        let client = thread::spawn(move || {
+            let mut client = TcpStream::connect(address).expect("Failed to connect to test listener");
+            client.write_all(b"POST / HTTP/1.1\r\nExpect: 100-continue\r\nContent-Length: 5\r\n\r\n").unwrap();
+            // Hold the body back until we've been told to go ahead.
+            let mut interim = [0; CONTINUE.len()];
+            client.read_exact(&mut interim).unwrap();
+            client.write_all(b"hello").unwrap();
+            (client, interim)
+        });
+
+        let (mut stream, _) = listener.accept().expect("Failed to accept test connection");
+        let request = RequestReader::new().read_request(&mut stream).expect("Failed to read request");
+        let (_client, interim) = client.join().unwrap();
+        assert_eq!(interim, CONTINUE);
+        assert_eq!(request, b"POST / HTTP/1.1\r\nExpect: 100-continue\r\nContent-Length: 5\r\n\r\nhello");
+    }
+
+    #[test]
+    fn test_read_skips_continue_for_http_1_0() {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("Failed to bind test listener");
+        let address = listener.local_addr().unwrap();
+        let mut client = TcpStream::connect(address).expect("Failed to connect to test listener");
+        client.write_all(b"POST / HTTP/1.0\r\nExpect: 100-continue\r\nContent-Length: 5\r\n\r\n").unwrap();
+        let mut sender = client.try_clone().unwrap();
+        let sender = thread::spawn(move || {
+            // A 1.0 client sends its body anyway once it's waited a bit.
+            thread::sleep(Duration::from_millis(50));
+            sender.write_all(b"hello").unwrap();
+        });
+
+        let (mut stream, _) = listener.accept().expect("Failed to accept test connection");
+        let request = RequestReader::new().read_request(&mut stream).unwrap();
+        sender.join().unwrap();
+        assert!(request.ends_with(b"\r\n\r\nhello"));
+        drop(stream);
+        let mut response = Vec::new();
+        client.read_to_end(&mut response).unwrap();
+        assert!(response.is_empty());
+    }
+
+    #[test]
+    fn test_read_skips_continue_for_body_over_limit() {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("Failed to bind test listener");
+        let address = listener.local_addr().unwrap();
+        let mut client = TcpStream::connect(address).expect("Failed to connect to test listener");
+        client.write_all(b"POST / HTTP/1.1\r\nExpect: 100-continue\r\nContent-Length: 20\r\n\r\n").unwrap();
+
+        let (mut stream, _) = listener.accept().expect("Failed to accept test connection");
+        let err = RequestReader::with_max_body_size(10).read_request(&mut stream).unwrap_err();
+        let err = err.get_ref().unwrap().downcast_ref::<BodyTooLarge>().unwrap();
+        assert_eq!(*err, BodyTooLarge { limit: 10, expectation: true });
+        drop(stream);
+        // Nothing was written back, it's left to the caller to turn the request down.
+        let mut response = Vec::new();
+        client.read_to_end(&mut response).unwrap();
+        assert!(response.is_empty());
+    }
+
+    #[test]
+    fn test_read_chunked_body() {
+        let request = read_parts(vec![
+            b"POST /upload HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhel".to_vec(),
+            b"lo\r\n6\r\n world\r\n".to_vec(),
+            b"0\r\n\r\n".to_vec(),
+        ]);
+        assert_eq!(request, b"POST /upload HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\nhello world");
+    }
+
+    #[test]
+    fn test_read_rejects_bad_chunk_size() {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("Failed to bind test listener");
+        let address = listener.local_addr().unwrap();
+        let mut client = TcpStream::connect(address).expect("Failed to connect to test listener");
+        client.write_all(b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\nnope\r\nhello\r\n0\r\n\r\n").unwrap();
+
+        let (mut stream, _) = listener.accept().expect("Failed to accept test connection");
+        let err = RequestReader::new().read_request(&mut stream).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_read_rejects_chunked_overhead_over_limit() {
+        let padding = format!(";ext={}", "a".repeat(100));
+        let requests = [
+            // Lots of tiny chunks padded out with extensions.
+            format!("POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n{}0\r\n\r\n", format!("1{}\r\na\r\n", padding).repeat(10)),
+            // Trailers longer than the headers are allowed to be.
+            format!("POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n1\r\na\r\n0\r\nX-Padding: {}\r\n\r\n", padding),
+        ];
+        for request in requests {
+            let listener = TcpListener::bind("127.0.0.1:0").expect("Failed to bind test listener");
+            let address = listener.local_addr().unwrap();
+            let mut client = TcpStream::connect(address).expect("Failed to connect to test listener");
+            client.write_all(request.as_bytes()).unwrap();
+
+            let (mut stream, _) = listener.accept().expect("Failed to accept test connection");
+            let err = RequestReader::with_max_body_size(100).with_max_header_size(100).read_request(&mut stream).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+            assert_eq!(err.get_ref().unwrap().downcast_ref::<ChunkedError>(), Some(&ChunkedError::TooLarge));
+        }
+    }
+
+    #[test]
+    fn test_read_rejects_unparseable_content_length() {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("Failed to bind test listener");
+        let address = listener.local_addr().unwrap();
+        let mut client = TcpStream::connect(address).expect("Failed to connect to test listener");
+        client.write_all(b"POST / HTTP/1.1\r\nContent-Length: lots\r\n\r\nhello").unwrap();
+
+        let (mut stream, _) = listener.accept().expect("Failed to accept test connection");
+        let err = RequestReader::new().read_request(&mut stream).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_content_length() {
+        assert_eq!(content_length(b"POST / HTTP/1.1\r\nContent-Length: 12\r\n\r\n").unwrap(), 12);
+        assert_eq!(content_length(b"GET / HTTP/1.1\r\n\r\n").unwrap(), 0);
+        assert!(content_length(b"POST / HTTP/1.1\r\nContent-Length: -1\r\n\r\n").is_err());
+        assert!(content_length(b"POST / HTTP/1.1\r\nContent-Length: 99999999999999999999999\r\n\r\n").is_err());
+    }
+
+    #[test]
+    fn test_duplicate_content_length() {
+        // Repeating the same length is harmless.
+        assert_eq!(content_length(b"POST / HTTP/1.1\r\nContent-Length: 5\r\ncontent-length: 5\r\n\r\n").unwrap(), 5);
+        assert_eq!(content_length(b"POST / HTTP/1.1\r\nContent-Length: 5, 5\r\n\r\n").unwrap(), 5);
+        // Disagreeing about it isn't.
+        let err = content_length(b"POST / HTTP/1.1\r\nContent-Length: 5\r\nContent-Length: 50\r\n\r\n").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(content_length(b"POST / HTTP/1.1\r\nContent-Length: 5, 50\r\n\r\n").is_err());
+    }
+
+    #[test]
+    fn test_read_rejects_content_length_with_chunked_body() {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("Failed to bind test listener");
+        let address = listener.local_addr().unwrap();
+        let mut client = TcpStream::connect(address).expect("Failed to connect to test listener");
+        client
+            .write_all(b"POST / HTTP/1.1\r\nContent-Length: 3\r\nTransfer-Encoding: chunked\r\n\r\n3\r\nabc\r\n0\r\n\r\n")
+            .unwrap();
+
+        let (mut stream, _) = listener.accept().expect("Failed to accept test connection");
+        let err = RequestReader::new().read_request(&mut stream).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_read_rejects_transfer_encoding_not_ending_in_chunked() {
+        let requests: [&[u8]; 2] = [
+            b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked, gzip\r\n\r\n3\r\nabc\r\n0\r\n\r\n",
+            b"POST / HTTP/1.1\r\nTransfer-Encoding: gzip\r\nContent-Length: 3\r\n\r\nabc",
+        ];
+        for request in requests {
+            let listener = TcpListener::bind("127.0.0.1:0").expect("Failed to bind test listener");
+            let address = listener.local_addr().unwrap();
+            let mut client = TcpStream::connect(address).expect("Failed to connect to test listener");
+            client.write_all(request).unwrap();
+
+            let (mut stream, _) = listener.accept().expect("Failed to accept test connection");
+            let err = RequestReader::new().read_request(&mut stream).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        }
+    }
+
+    #[test]
+    fn test_read_rejects_headers_over_limit() {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("Failed to bind test listener");
+        let address = listener.local_addr().unwrap();
+        let mut client = TcpStream::connect(address).expect("Failed to connect to test listener");
+        client.write_all(b"GET / HTTP/1.1\r\nShort: header\r\n\r\n").unwrap();
+        let flood = format!("GET / HTTP/1.1\r\nX-Flood: {}\r\n", "a".repeat(100_000));
+        client.write_all(flood.as_bytes()).unwrap();
+
+        let (mut stream, _) = listener.accept().expect("Failed to accept test connection");
+        let mut reader = RequestReader::new().with_max_header_size(64);
+        assert_eq!(reader.read_request(&mut stream).unwrap(), b"GET / HTTP/1.1\r\nShort: header\r\n\r\n");
+        let err = reader.read_request(&mut stream).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        let err = err.get_ref().and_then(|err| err.downcast_ref::<HeadersTooLarge>());
+        assert_eq!(err, Some(&HeadersTooLarge { limit: 64 }));
+    }
+
+    #[test]
+    fn test_read_rejects_request_line_over_limit() {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("Failed to bind test listener");
+        let address = listener.local_addr().unwrap();
+        let mut client = TcpStream::connect(address).expect("Failed to connect to test listener");
+        client.write_all(b"GET /short HTTP/1.1\r\n\r\n").unwrap();
+        let long = format!("GET /{} HTTP/1.1\r\n\r\n", "a".repeat(100));
+        client.write_all(long.as_bytes()).unwrap();
+
+        let (mut stream, _) = listener.accept().expect("Failed to accept test connection");
+        // Even with the headers allowed to be bigger, the request line gets its own limit.
+        let mut reader = RequestReader::new().with_max_header_size(1024).with_max_request_line(64);
+        assert_eq!(reader.read_request(&mut stream).unwrap(), b"GET /short HTTP/1.1\r\n\r\n");
+        let err = reader.read_request(&mut stream).unwrap_err();
+        let err = err.get_ref().and_then(|err| err.downcast_ref::<RequestLineTooLong>());
+        assert_eq!(err, Some(&RequestLineTooLong { limit: 64 }));
+    }
+}
@@ -0,0 +1,71 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// The header a request's ID is read from and echoed back in.
+pub const REQUEST_ID_HEADER: &str = "X-Request-Id";
+
+/// The longest client-supplied ID we'll use, anything longer gets one of ours instead.
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+/// Hands out an ID for each request so its log lines can be tied together.
+///
+/// IDs look like `65f1c2a0-42`: when the generator was created (in seconds, as hex) and
+/// then a counter, so they're unique even across restarts of the server.
+#[derive(Debug)]
+pub struct RequestIds {
+    prefix: String,
+    next: AtomicU64,
+}
+impl RequestIds {
+    /// Start counting from one.
+    pub fn new() -> RequestIds {
+        let started = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |since| since.as_secs());
+        RequestIds {
+            prefix: format!("{:x}", started),
+            next: AtomicU64::new(1),
+        }
+    }
+
+    /// A new ID, different from every other one this generator has handed out.
+    pub fn next_id(&self) -> String {
+        format!("{}-{}", self.prefix, self.next.fetch_add(1, Ordering::Relaxed))
+    }
+}
+impl Default for RequestIds {
+    fn default() -> RequestIds {
+        RequestIds::new()
+    }
+}
+
+/// Whether an ID sent by the client is safe to use as our own: not too long, and only
+/// printable ASCII without spaces so it can't mess up the access log.
+pub fn is_valid_request_id(id: &str) -> bool {
+    !id.is_empty() && id.len() <= MAX_REQUEST_ID_LEN && id.bytes().all(|byte| byte.is_ascii_graphic())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_request_ids_are_unique() {
+        let ids = RequestIds::new();
+        let first = ids.next_id();
+        let second = ids.next_id();
+        assert_ne!(first, second);
+        assert!(first.ends_with("-1"));
+        assert!(second.ends_with("-2"));
+        assert!(is_valid_request_id(&first));
+    }
+
+    #[test]
+    fn test_is_valid_request_id() {
+        assert!(is_valid_request_id("abc-123"));
+        assert!(is_valid_request_id("6f9619ff-8b86-d011-b42d-00cf4fc964ff"));
+        assert!(!is_valid_request_id(""));
+        assert!(!is_valid_request_id("two words"));
+        assert!(!is_valid_request_id("new\nline"));
+        assert!(!is_valid_request_id(&"a".repeat(MAX_REQUEST_ID_LEN + 1)));
+    }
+}
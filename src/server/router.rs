@@ -0,0 +1,384 @@
+use std::collections::HashMap;
+use crate::http::method::Method;
+use crate::http::request::Request;
+use crate::http::response::{error_response, Response};
+use crate::server::handler::Handler;
+use crate::server::middleware::Middleware;
+
+/// Maps a method and path onto the handler registered for it, after running the request
+/// past any middleware.
+pub struct Router {
+    routes: HashMap<(Method, String), Box<dyn Handler>>,
+    middleware: Vec<Box<dyn Middleware>>,
+    fallback: Option<Box<dyn Handler>>,
+}
+impl Router {
+    /// Create a Router with no routes or middleware registered.
+    pub fn new() -> Router {
+        Router {
+            routes: HashMap::new(),
+            middleware: Vec::new(),
+            fallback: None,
+        }
+    }
+
+    /// Add middleware to run before every request is dispatched. Middleware runs in the
+    /// order it was added.
+    pub fn middleware<M>(&mut self, middleware: M)
+    where
+        M: Middleware + 'static,
+    {
+        self.middleware.push(Box::new(middleware));
+    }
+
+    /// Register `handler` to be called for requests with the given method and path.
+    ///
+    /// The handler can be a closure or function taking the `&Request` (a closure needs
+    /// its argument's type written out), or anything else implementing `Handler`.
+    ///
+    /// Segments of the path starting with a `:` are parameters that match any (non-empty)
+    /// segment, so `/users/:id` matches `/users/42` and the handler finds `42` with
+    /// `request.param("id")`. A request that matches a route exactly always goes to that
+    /// route over one with parameters. Registering the same method and path twice
+    /// replaces the earlier handler.
+    pub fn route<H>(&mut self, method: Method, path: &str, handler: H)
+    where
+        H: Handler + 'static,
+    {
+        self.routes.insert((method, String::from(path)), Box::new(handler));
+    }
+
+    /// Register `handler` to be called for requests that nothing else could answer, in
+    /// place of the `404 Not Found` page.
+    ///
+    /// It's only called once there's no matching route and no static file either, so
+    /// it's handy for single page apps that serve their `index.html` for any unknown
+    /// path. Registering a second fallback replaces the first.
+    pub fn fallback<H>(&mut self, handler: H)
+    where
+        H: Handler + 'static,
+    {
+        self.fallback = Some(Box::new(handler));
+    }
+
+    /// Call the fallback handler for the request, or `None` if there isn't one.
+    pub fn dispatch_fallback(&self, request: &Request) -> Option<Response> {
+        self.fallback.as_ref().map(|handler| handler.handle(request))
+    }
+
+    /// Find and call the handler for the request.
+    ///
+    /// The middleware runs first, and if any of it responds to the request that response
+    /// is returned without looking for a handler. This applies to every request, not just
+    /// ones for registered paths.
+    ///
+    /// `OPTIONS` requests for a registered path are answered automatically with a `204 No
+    /// Content` and an `Allow` header, unless an `OPTIONS` handler is registered for it.
+    ///
+    /// Returns `None` if nothing is registered for the request path at all, leaving the
+    /// caller free to handle it some other way (e.g. by serving a static file). If the
+    /// path is registered but not for the request method a `405 Method Not Allowed`
+    /// response is returned instead, with an `Allow` header listing the methods that are.
+    pub fn dispatch(&self, request: &Request) -> Option<Response> {
+        if let Some(response) = self.middleware.iter().find_map(|middleware| middleware.handle(request)) {
+            return Some(response);
+        }
+
+        if let Ok(method) = request.method.parse::<Method>() {
+            if let Some(handler) = self.routes.get(&(method, request.path.clone())) {
+                return Some(handler.handle(request));
+            }
+            if let Some((handler, params)) = self.match_params(method, &request.path) {
+                let request = Request { params, ..request.clone() };
+                return Some(handler.handle(&request));
+            }
+        }
+
+        // The method didn't match, but some other method might be registered for this path.
+        let mut allowed = self.allowed_methods(&request.path);
+        if allowed.is_empty() {
+            return None;
+        }
+
+        // Answer OPTIONS ourselves since there's no handler for it.
+        if request.method == "OPTIONS" {
+            allowed.push(Method::Options);
+            return Some(Response::new(204).with_header("Allow", &allow_header(&allowed)));
+        }
+
+        Some(error_response(405).with_header("Allow", &allow_header(&allowed)))
+    }
+
+    /// The methods with a handler registered for `path`, in a consistent order. Empty if
+    /// nothing is registered for the path at all.
+    pub fn allowed_methods(&self, path: &str) -> Vec<Method> {
+        let mut methods: Vec<Method> = self
+            .routes
+            .keys()
+            .filter(|(_, route)| route == path || match_path(route, path).is_some())
+            .map(|(method, _)| *method)
+            .collect();
+        methods.sort();
+        // A path can match more than one route with the same method.
+        methods.dedup();
+        methods
+    }
+
+    // Find the route with parameters (if any) that matches the method and path, along with
+    // what it captured. If several match, the one with the fewest parameters wins.
+    fn match_params(&self, method: Method, path: &str) -> Option<(&dyn Handler, HashMap<String, String>)> {
+        self.routes
+            .iter()
+            .filter(|((route_method, route), _)| *route_method == method && route.contains(':'))
+            .filter_map(|((_, route), handler)| match_path(route, path).map(|params| (route, handler, params)))
+            .min_by_key(|(route, _, params)| (params.len(), route.as_str()))
+            .map(|(_, handler, params)| (handler.as_ref(), params))
+    }
+}
+
+/// Match a request path against a route pattern like `/users/:id/posts/:post`.
+///
+/// The path must have the same number of segments as the pattern. Each `:name` segment
+/// captures the corresponding segment of the path, and every other segment has to match
+/// exactly. Returns the captured parameters, or `None` if the path doesn't match.
+pub fn match_path(pattern: &str, path: &str) -> Option<HashMap<String, String>> {
+    let pattern: Vec<&str> = pattern.split('/').collect();
+    let path: Vec<&str> = path.split('/').collect();
+    if pattern.len() != path.len() {
+        return None;
+    }
+
+    let mut params = HashMap::new();
+    for (expected, actual) in pattern.iter().zip(path) {
+        match expected.strip_prefix(':') {
+            Some(name) if !actual.is_empty() => {
+                params.insert(String::from(name), String::from(actual));
+            }
+            Some(_) => return None,
+            None if *expected == actual => {}
+            None => return None,
+        }
+    }
+    Some(params)
+}
+
+// Format methods for an `Allow` header, e.g. `GET, POST`.
+fn allow_header(methods: &[Method]) -> String {
+    methods.iter().map(Method::as_str).collect::<Vec<_>>().join(", ")
+}
+
+impl Default for Router {
+    fn default() -> Router {
+        Router::new()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn request(method: &str, path: &str) -> Request {
+        Request {
+            method: String::from(method),
+            path: String::from(path),
+            version: String::from("HTTP/1.1"),
+            ..Request::default()
+        }
+    }
+
+    fn test_router() -> Router {
+        let mut router = Router::new();
+        router.route(Method::Get, "/items", |_: &Request| Response::text(200, "list"));
+        router.route(Method::Post, "/items", |_: &Request| Response::text(201, "created"));
+        router
+    }
+
+    #[test]
+    fn test_dispatch_get_and_post_on_same_path() {
+        let router = test_router();
+        assert_eq!(
+            router.dispatch(&request("GET", "/items")),
+            Some(Response::text(200, "list"))
+        );
+        assert_eq!(
+            router.dispatch(&request("POST", "/items")),
+            Some(Response::text(201, "created"))
+        );
+    }
+
+    #[test]
+    fn test_dispatch_unregistered_method_is_405() {
+        let router = test_router();
+        let response = router.dispatch(&request("DELETE", "/items")).expect("The path is registered");
+        assert_eq!(response.status, 405);
+
+        // Methods we don't even know about are still not allowed.
+        let response = router.dispatch(&request("BREW", "/items")).expect("The path is registered");
+        assert_eq!(response.status, 405);
+    }
+
+    #[test]
+    fn test_dispatch_unknown_path() {
+        let router = test_router();
+        assert_eq!(router.dispatch(&request("GET", "/missing")), None);
+    }
+
+    // Turns away anything without an Authorization header.
+    struct RequireAuthorization;
+    impl Middleware for RequireAuthorization {
+        fn handle(&self, request: &Request) -> Option<Response> {
+            match request.header("authorization") {
+                Some(_) => None,
+                None => Some(error_response(401)),
+            }
+        }
+    }
+
+    #[test]
+    fn test_middleware_short_circuits() {
+        let mut router = test_router();
+        router.middleware(RequireAuthorization);
+
+        let response = router.dispatch(&request("GET", "/items")).expect("Middleware should respond");
+        assert_eq!(response.status, 401);
+        // Paths without a route are covered too.
+        let response = router.dispatch(&request("GET", "/missing")).expect("Middleware should respond");
+        assert_eq!(response.status, 401);
+
+        let mut authorized = request("GET", "/items");
+        authorized.headers.insert(String::from("authorization"), String::from("Bearer token"));
+        assert_eq!(router.dispatch(&authorized), Some(Response::text(200, "list")));
+    }
+
+    #[test]
+    fn test_middleware_runs_in_order() {
+        let mut router = test_router();
+        router.middleware(|_: &Request| None);
+        router.middleware(|_: &Request| Some(Response::text(503, "first")));
+        router.middleware(|_: &Request| Some(Response::text(500, "second")));
+        assert_eq!(router.dispatch(&request("GET", "/items")), Some(Response::text(503, "first")));
+    }
+
+    #[test]
+    fn test_allowed_methods() {
+        let router = test_router();
+        assert_eq!(router.allowed_methods("/items"), vec![Method::Get, Method::Post]);
+        assert!(router.allowed_methods("/missing").is_empty());
+    }
+
+    #[test]
+    fn test_405_lists_allowed_methods() {
+        let mut router = Router::new();
+        router.route(Method::Get, "/report", |_: &Request| Response::text(200, "report"));
+        let response = router.dispatch(&request("POST", "/report")).expect("The path is registered");
+        assert_eq!(response.status, 405);
+        assert_eq!(response.header("Allow"), Some("GET"));
+
+        let response = test_router().dispatch(&request("DELETE", "/items")).expect("The path is registered");
+        assert_eq!(response.header("Allow"), Some("GET, POST"));
+    }
+
+    #[test]
+    fn test_automatic_options() {
+        let response = test_router().dispatch(&request("OPTIONS", "/items")).expect("The path is registered");
+        assert_eq!(response.status, 204);
+        assert_eq!(response.header("Allow"), Some("GET, POST, OPTIONS"));
+
+        assert_eq!(test_router().dispatch(&request("OPTIONS", "/missing")), None);
+    }
+
+    #[test]
+    fn test_explicit_options_handler_wins() {
+        let mut router = test_router();
+        router.route(Method::Options, "/items", |_: &Request| Response::text(200, "custom"));
+        assert_eq!(router.dispatch(&request("OPTIONS", "/items")), Some(Response::text(200, "custom")));
+        assert_eq!(router.allowed_methods("/items"), vec![Method::Get, Method::Post, Method::Options]);
+    }
+
+    #[test]
+    fn test_fallback() {
+        let mut router = Router::new();
+        assert!(router.dispatch_fallback(&request("GET", "/anything")).is_none());
+
+        router.route(Method::Get, "/known", |_: &Request| Response::text(200, "known"));
+        router.fallback(|request: &Request| Response::text(200, &format!("fallback for {}", request.path)));
+        // Dispatching doesn't use the fallback, it's up to the caller to try it last.
+        assert!(router.dispatch(&request("GET", "/unknown")).is_none());
+        let response = router.dispatch_fallback(&request("GET", "/unknown")).unwrap();
+        assert_eq!(response.body, b"fallback for /unknown");
+    }
+
+    // Counts the requests it's handled, the kind of state a closure can't easily hold.
+    struct HitCounter {
+        hits: AtomicUsize,
+    }
+    impl Handler for HitCounter {
+        fn handle(&self, _: &Request) -> Response {
+            let hits = self.hits.fetch_add(1, Ordering::SeqCst) + 1;
+            Response::text(200, &hits.to_string())
+        }
+    }
+
+    #[test]
+    fn test_struct_handler_keeps_state() {
+        let mut router = test_router();
+        router.route(Method::Get, "/hits", HitCounter { hits: AtomicUsize::new(0) });
+        for expected in ["1", "2", "3"] {
+            assert_eq!(router.dispatch(&request("GET", "/hits")), Some(Response::text(200, expected)));
+        }
+        // Closures still work alongside it.
+        assert_eq!(router.dispatch(&request("GET", "/items")), Some(Response::text(200, "list")));
+    }
+
+    #[test]
+    fn test_function_handler() {
+        fn hello(request: &Request) -> Response {
+            Response::text(200, &format!("hello from {}", request.path))
+        }
+        let mut router = Router::new();
+        router.route(Method::Get, "/hello", hello);
+        router.fallback(hello);
+        assert_eq!(router.dispatch(&request("GET", "/hello")), Some(Response::text(200, "hello from /hello")));
+        assert_eq!(router.dispatch_fallback(&request("GET", "/nope")), Some(Response::text(200, "hello from /nope")));
+    }
+
+    #[test]
+    fn test_match_path_single_param() {
+        let params = match_path("/users/:id", "/users/42").expect("The path should match");
+        assert_eq!(params.len(), 1);
+        assert_eq!(params["id"], "42");
+        assert!(match_path("/users/:id", "/posts/42").is_none());
+        assert!(match_path("/users/:id", "/users/").is_none());
+    }
+
+    #[test]
+    fn test_match_path_multiple_params() {
+        let params = match_path("/users/:user/posts/:post", "/users/ferris/posts/7").expect("The path should match");
+        assert_eq!(params["user"], "ferris");
+        assert_eq!(params["post"], "7");
+        assert!(match_path("/users/:user/posts/:post", "/users/ferris/comments/7").is_none());
+    }
+
+    #[test]
+    fn test_match_path_length_must_match() {
+        assert!(match_path("/users/:id", "/users").is_none());
+        assert!(match_path("/users/:id", "/users/42/posts").is_none());
+        assert_eq!(match_path("/users", "/users"), Some(HashMap::new()));
+    }
+
+    #[test]
+    fn test_dispatch_with_params() {
+        let mut router = Router::new();
+        router.route(Method::Get, "/users/:id", |request: &Request| Response::text(200, request.param("id").unwrap_or("none")));
+        router.route(Method::Get, "/users/me", |_: &Request| Response::text(200, "me"));
+        router.route(Method::Get, "/users/:id/:tab", |_: &Request| Response::text(200, "tab"));
+        assert_eq!(router.dispatch(&request("GET", "/users/42")), Some(Response::text(200, "42")));
+        assert_eq!(router.dispatch(&request("GET", "/users/me")), Some(Response::text(200, "me")));
+        assert_eq!(router.dispatch(&request("DELETE", "/users/42")).map(|response| response.status), Some(405));
+        assert_eq!(router.allowed_methods("/users/42"), vec![Method::Get]);
+        assert_eq!(router.allowed_methods("/users/me"), vec![Method::Get]);
+        assert!(router.dispatch(&request("GET", "/users")).is_none());
+    }
+}
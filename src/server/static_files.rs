@@ -0,0 +1,368 @@
+use std::fs;
+use std::fmt;
+use std::fs::Metadata;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+use crate::http::url::percent_decode;
+
+/// The page served for a directory, including the root path `/`.
+pub const INDEX_PAGE: &str = "index.html";
+
+/// Check that the document root exists and is a directory, before anything is served from it.
+///
+/// Returns the root canonicalized, so it no longer depends on the working directory.
+pub fn check_document_root(root: &Path) -> Result<PathBuf, DocumentRootError> {
+    let canonical = root.canonicalize().map_err(|_| DocumentRootError::Missing(root.to_path_buf()))?;
+    if !canonical.is_dir() {
+        return Err(DocumentRootError::NotADirectory(root.to_path_buf()));
+    }
+    Ok(canonical)
+}
+
+/// Map a request path (like `/css/style.css`) onto a file under `root`.
+///
+/// The path is percent-decoded (so `/my%20file.html` finds `my file.html`) and the
+/// leading `/` is stripped so the path is joined relative to the root, then the result
+/// is canonicalized. A path that points at a directory (like `/` or `/docs/`) maps onto
+/// the `index.html` inside it. Returns `None` if the file doesn't exist or is not a
+/// regular file.
+pub fn resolve_path(request_path: &str, root: &Path) -> Option<PathBuf> {
+    let decoded = decode_request_path(request_path)?;
+    let candidate = root.join(decoded.trim_start_matches('/'));
+
+    // canonicalize fails if the path doesn't exist, which we treat as a miss.
+    let candidate = candidate.canonicalize().ok()?;
+    let candidate = if candidate.is_dir() {
+        candidate.join(INDEX_PAGE)
+    } else {
+        candidate
+    };
+    if candidate.is_file() {
+        Some(candidate)
+    } else {
+        None
+    }
+}
+
+/// Map a request path onto a directory under `root`, for when there's no index page to
+/// serve in its place. Returns `None` if the path isn't an existing directory.
+pub fn resolve_directory(request_path: &str, root: &Path) -> Option<PathBuf> {
+    let decoded = decode_request_path(request_path)?;
+    let candidate = root.join(decoded.trim_start_matches('/')).canonicalize().ok()?;
+    if candidate.is_dir() {
+        Some(candidate)
+    } else {
+        None
+    }
+}
+
+// Percent-decode a request path for looking up on disk. No real file name needs an
+// encoded `..` (like `%2e%2e`), so one that only shows up after decoding is refused
+// rather than being given the chance to climb out of the root.
+fn decode_request_path(request_path: &str) -> Option<String> {
+    let decoded = percent_decode(request_path);
+    let has_dot_dot = |path: &str| path.split('/').any(|segment| segment == "..");
+    if has_dot_dot(&decoded) && !has_dot_dot(request_path) {
+        return None;
+    }
+    Some(decoded)
+}
+
+/// Build an HTML page listing the entries of `dir` as links.
+///
+/// Each link is the entry's name appended to `request_path`, so they work whether or not
+/// the request path ended in a `/`. Subdirectories get a trailing `/`. Hidden files (whose
+/// names start with a `.`) are left out, and anything that can't be read is skipped.
+pub fn render_directory_listing(dir: &Path, request_path: &str) -> String {
+    let prefix = if request_path.ends_with('/') {
+        String::from(request_path)
+    } else {
+        format!("{}/", request_path)
+    };
+
+    let mut entries: Vec<(String, bool)> = fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .filter_map(Result::ok)
+        .filter_map(|entry| {
+            let name = entry.file_name().into_string().ok()?;
+            let is_dir = entry.file_type().ok()?.is_dir();
+            Some((name, is_dir))
+        })
+        .filter(|(name, _)| !name.starts_with('.'))
+        .collect();
+    entries.sort();
+
+    let title = escape_html(&prefix);
+    let mut page = format!("<!DOCTYPE html>\n<html lang=\"en\">\n  <head>\n    <meta charset=\"utf-8\">\n    <title>Index of {}</title>\n  </head>\n  <body>\n    <h1>Index of {}</h1>\n    <ul>\n", title, title);
+    for (name, is_dir) in entries {
+        let slash = if is_dir { "/" } else { "" };
+        page.push_str(&format!(
+            "      <li><a href=\"{}{}{}\">{}{}</a></li>\n",
+            escape_html(&prefix),
+            escape_html(&encode_href(&name)),
+            slash,
+            escape_html(&name),
+            slash
+        ));
+    }
+    page.push_str("    </ul>\n  </body>\n</html>\n");
+    page
+}
+
+// Escape the characters that mean something in HTML.
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+// Percent-encode a file name for use in a link, so names with spaces or characters like
+// `#` and `?` still point at the right file.
+fn encode_href(name: &str) -> String {
+    name.bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => char::from(byte).to_string(),
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+/// Check that `candidate` lives somewhere under `root` once both are canonicalized.
+///
+/// Canonicalizing resolves any `..` segments and symlinks, so a request like
+/// `/../../etc/passwd` that climbs out of the document root fails this check.
+/// Paths that can't be canonicalized (e.g. they don't exist) are never within the root.
+pub fn is_within_root(candidate: &Path, root: &Path) -> bool {
+    match (candidate.canonicalize(), root.canonicalize()) {
+        (Ok(candidate), Ok(root)) => candidate.starts_with(root),
+        _ => false,
+    }
+}
+
+/// Compute an `ETag` for a file from its size and modification time.
+///
+/// This is much cheaper than hashing the contents and still changes whenever the file
+/// is rewritten. The tag comes wrapped in quotes, ready to go in the header.
+pub fn compute_etag(metadata: &Metadata) -> String {
+    let modified = metadata
+        .modified()
+        .ok()
+        .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |modified| modified.as_nanos());
+    format!("\"{:x}-{:x}\"", metadata.len(), modified)
+}
+
+/// Check whether an `If-None-Match` header matches the `etag` of the current file.
+///
+/// The header may list several tags separated by commas, or be `*` to match anything.
+/// Weak tags (`W/"..."`) are compared as if they were strong.
+pub fn etag_matches(if_none_match: &str, etag: &str) -> bool {
+    if_none_match
+        .split(',')
+        .map(str::trim)
+        .any(|tag| tag == "*" || tag.trim_start_matches("W/") == etag)
+}
+
+// Errors:
+// Everything that can be wrong with the configured document root.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DocumentRootError {
+    // Nothing exists at the path.
+    Missing(PathBuf),
+    // Something exists at the path, but it's a file rather than a directory.
+    NotADirectory(PathBuf),
+}
+impl fmt::Display for DocumentRootError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DocumentRootError::Missing(root) => write!(f, "The document root {} does not exist!", root.display()),
+            DocumentRootError::NotADirectory(root) => write!(f, "The document root {} is not a directory!", root.display()),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_resolve_nested_path() {
+        let root = tempfile::tempdir().expect("Failed to create temp dir");
+        fs::create_dir(root.path().join("css")).unwrap();
+        fs::write(root.path().join("css/style.css"), "body {}").unwrap();
+
+        let resolved = resolve_path("/css/style.css", root.path()).expect("Nested file should resolve");
+        assert_eq!(resolved, root.path().join("css/style.css").canonicalize().unwrap());
+    }
+
+    #[test]
+    fn test_resolve_missing_file() {
+        let root = tempfile::tempdir().expect("Failed to create temp dir");
+        assert!(resolve_path("/missing.html", root.path()).is_none());
+    }
+
+    // Build a document root at <tmp>/public with a secret file just outside of it.
+    fn root_with_secret() -> (tempfile::TempDir, PathBuf) {
+        let dir = tempfile::tempdir().expect("Failed to create temp dir");
+        fs::create_dir(dir.path().join("public")).unwrap();
+        fs::write(dir.path().join("public/index.html"), "public").unwrap();
+        fs::write(dir.path().join("secret.txt"), "secret").unwrap();
+        let root = dir.path().join("public");
+        (dir, root)
+    }
+
+    #[test]
+    fn test_file_inside_root_is_within_root() {
+        let (_dir, root) = root_with_secret();
+        let resolved = resolve_path("/index.html", &root).unwrap();
+        assert!(is_within_root(&resolved, &root));
+    }
+
+    #[test]
+    fn test_dot_dot_segments_escape_root() {
+        let (_dir, root) = root_with_secret();
+        let resolved = resolve_path("/../secret.txt", &root).expect("The secret file exists");
+        assert!(!is_within_root(&resolved, &root));
+
+        let resolved = resolve_path("/index.html/../../secret.txt", &root);
+        assert!(resolved.is_none_or(|resolved| !is_within_root(&resolved, &root)));
+    }
+
+    #[test]
+    fn test_absolute_path_stays_under_root() {
+        let (dir, root) = root_with_secret();
+        // The leading slashes are stripped so the absolute path is joined onto the root
+        // rather than replacing it.
+        let absolute = format!("/{}", dir.path().join("secret.txt").display());
+        let resolved = resolve_path(&absolute, &root);
+        assert!(resolved.is_none_or(|resolved| is_within_root(&resolved, &root)));
+    }
+
+    #[test]
+    fn test_url_encoded_dot_dot_does_not_escape() {
+        let (_dir, root) = root_with_secret();
+        let resolved = resolve_path("/%2e%2e/secret.txt", &root);
+        assert!(resolved.is_none_or(|resolved| is_within_root(&resolved, &root)));
+    }
+
+    #[test]
+    fn test_resolve_root_to_index() {
+        let root = tempfile::tempdir().expect("Failed to create temp dir");
+        fs::write(root.path().join(INDEX_PAGE), "home").unwrap();
+
+        let resolved = resolve_path("/", root.path()).expect("The root should resolve to its index");
+        assert_eq!(resolved, root.path().join(INDEX_PAGE).canonicalize().unwrap());
+    }
+
+    #[test]
+    fn test_resolve_directory_to_index() {
+        let root = tempfile::tempdir().expect("Failed to create temp dir");
+        fs::create_dir(root.path().join("docs")).unwrap();
+        fs::write(root.path().join("docs").join(INDEX_PAGE), "docs").unwrap();
+
+        let expected = root.path().join("docs").join(INDEX_PAGE).canonicalize().unwrap();
+        assert_eq!(resolve_path("/docs/", root.path()), Some(expected.clone()));
+        assert_eq!(resolve_path("/docs", root.path()), Some(expected));
+    }
+
+    #[test]
+    fn test_resolve_directory_is_not_a_file() {
+        let root = tempfile::tempdir().expect("Failed to create temp dir");
+        fs::create_dir(root.path().join("docs")).unwrap();
+        assert!(resolve_path("/docs", root.path()).is_none());
+    }
+
+    #[test]
+    fn test_etag_changes_with_file() {
+        let root = tempfile::tempdir().expect("Failed to create temp dir");
+        let path = root.path().join("index.html");
+        fs::write(&path, "first").unwrap();
+        let first = compute_etag(&fs::metadata(&path).unwrap());
+        assert_eq!(first, compute_etag(&fs::metadata(&path).unwrap()));
+        assert!(first.starts_with('"') && first.ends_with('"'));
+
+        fs::write(&path, "second version").unwrap();
+        assert_ne!(first, compute_etag(&fs::metadata(&path).unwrap()));
+    }
+
+    #[test]
+    fn test_etag_matches() {
+        assert!(etag_matches("\"abc\"", "\"abc\""));
+        assert!(etag_matches("\"old\", W/\"abc\"", "\"abc\""));
+        assert!(etag_matches("*", "\"abc\""));
+        assert!(!etag_matches("\"old\"", "\"abc\""));
+    }
+
+    #[test]
+    fn test_render_directory_listing() {
+        let root = tempfile::tempdir().expect("Failed to create temp dir");
+        fs::write(root.path().join("a.txt"), "a").unwrap();
+        fs::write(root.path().join("my notes.md"), "notes").unwrap();
+        fs::write(root.path().join(".secret"), "hidden").unwrap();
+        fs::create_dir(root.path().join("sub")).unwrap();
+
+        let page = render_directory_listing(root.path(), "/files");
+        assert!(page.contains("<a href=\"/files/a.txt\">a.txt</a>"));
+        assert!(page.contains("<a href=\"/files/my%20notes.md\">my notes.md</a>"));
+        assert!(page.contains("<a href=\"/files/sub/\">sub/</a>"));
+        assert!(!page.contains(".secret"));
+
+        // A trailing slash on the request path doesn't double up.
+        let page = render_directory_listing(root.path(), "/files/");
+        assert!(page.contains("<a href=\"/files/a.txt\">"));
+    }
+
+    #[test]
+    fn test_directory_listing_escapes_names() {
+        let root = tempfile::tempdir().expect("Failed to create temp dir");
+        fs::write(root.path().join("<b>.txt"), "bold").unwrap();
+        let page = render_directory_listing(root.path(), "/");
+        assert!(page.contains("<a href=\"/%3Cb%3E.txt\">&lt;b&gt;.txt</a>"));
+    }
+
+    #[test]
+    fn test_resolve_directory() {
+        let root = tempfile::tempdir().expect("Failed to create temp dir");
+        fs::create_dir(root.path().join("docs")).unwrap();
+        fs::write(root.path().join("file.txt"), "file").unwrap();
+        assert!(resolve_directory("/docs/", root.path()).is_some());
+        assert!(resolve_directory("/file.txt", root.path()).is_none());
+        assert!(resolve_directory("/missing/", root.path()).is_none());
+    }
+
+    #[test]
+    fn test_check_document_root() {
+        let root = tempfile::tempdir().expect("Failed to create temp dir");
+        fs::write(root.path().join("file.txt"), "file").unwrap();
+        assert_eq!(check_document_root(root.path()), Ok(root.path().canonicalize().unwrap()));
+
+        let missing = root.path().join("missing");
+        assert_eq!(check_document_root(&missing), Err(DocumentRootError::Missing(missing.clone())));
+        let file = root.path().join("file.txt");
+        assert_eq!(check_document_root(&file), Err(DocumentRootError::NotADirectory(file.clone())));
+    }
+
+    #[test]
+    fn test_resolve_under_custom_root() {
+        let root = tempfile::tempdir().expect("Failed to create temp dir");
+        fs::create_dir(root.path().join("site")).unwrap();
+        fs::write(root.path().join("site").join(INDEX_PAGE), "custom").unwrap();
+        let root = check_document_root(&root.path().join("site")).expect("The root should be valid");
+        assert_eq!(resolve_path("/", &root), Some(root.join(INDEX_PAGE)));
+        assert_eq!(fs::read_to_string(resolve_path("/", &root).unwrap()).unwrap(), "custom");
+    }
+
+    #[test]
+    fn test_resolve_percent_encoded_path() {
+        let root = tempfile::tempdir().expect("Failed to create temp dir");
+        fs::write(root.path().join("my file.html"), "spaced").unwrap();
+        let resolved = resolve_path("/my%20file.html", root.path()).expect("The encoded name should resolve");
+        assert_eq!(resolved, root.path().canonicalize().unwrap().join("my file.html"));
+        assert!(resolve_path("/my file%2", root.path()).is_none());
+    }
+}
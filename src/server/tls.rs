@@ -0,0 +1,179 @@
+use std::fmt;
+use std::io;
+use std::error;
+use std::sync::Arc;
+use std::io::prelude::*;
+use std::time::Duration;
+use std::path::{Path, PathBuf};
+use std::net::{SocketAddr, TcpStream};
+use rustls::{ServerConfig, ServerConnection, StreamOwned};
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::pki_types::pem::{self, PemObject};
+use crate::server::transport::Transport;
+
+/// A TLS connection to a client, encrypting everything over the underlying socket.
+pub type TlsStream = StreamOwned<ServerConnection, TcpStream>;
+
+/// Load a certificate chain and private key from PEM files into a TLS configuration.
+///
+/// The certificate file can hold the whole chain, starting with the server's own
+/// certificate. The key file should hold a single PKCS#8, PKCS#1 or SEC1 private key.
+pub fn load_tls_config(cert_path: &Path, key_path: &Path) -> Result<Arc<ServerConfig>, TlsError> {
+    let certs = CertificateDer::pem_file_iter(cert_path)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .map_err(TlsError::Certificate)?;
+    if certs.is_empty() {
+        return Err(TlsError::NoCertificates(cert_path.to_path_buf()));
+    }
+    let key = PrivateKeyDer::from_pem_file(key_path).map_err(TlsError::Key)?;
+
+    let config = ServerConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .and_then(|builder| builder.with_no_client_auth().with_single_cert(certs, key))
+        .map_err(TlsError::Config)?;
+    Ok(Arc::new(config))
+}
+
+/// Start a TLS session on an accepted connection.
+///
+/// The handshake itself happens on the first read, so it's covered by the read timeout
+/// like everything else.
+pub fn accept_tls(config: &Arc<ServerConfig>, stream: TcpStream) -> io::Result<TlsStream> {
+    let connection = ServerConnection::new(Arc::clone(config)).map_err(io::Error::other)?;
+    Ok(StreamOwned::new(connection, stream))
+}
+
+impl Transport for TlsStream {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.sock.set_read_timeout(timeout)
+    }
+
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.sock.set_write_timeout(timeout)
+    }
+
+    fn set_nodelay(&self, nodelay: bool) -> io::Result<()> {
+        self.sock.set_nodelay(nodelay)
+    }
+
+    fn peer_addr(&self) -> Option<SocketAddr> {
+        self.sock.peer_addr().ok()
+    }
+
+    fn local_addr(&self) -> Option<SocketAddr> {
+        self.sock.local_addr().ok()
+    }
+
+    // Let the client know we're done on purpose, rather than it looking like the
+    // connection was cut off.
+    fn close(&mut self) -> io::Result<()> {
+        self.conn.send_close_notify();
+        self.flush()
+    }
+}
+
+// Errors:
+// Everything that can go wrong while loading the certificate and key.
+#[derive(Debug)]
+pub enum TlsError {
+    // The certificate file couldn't be read or parsed.
+    Certificate(pem::Error),
+    // The certificate file was read but didn't have any certificates in it.
+    NoCertificates(PathBuf),
+    // The private key file couldn't be read or parsed.
+    Key(pem::Error),
+    // The certificate and key were read but rustls wouldn't accept them.
+    Config(rustls::Error),
+}
+impl fmt::Display for TlsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TlsError::Certificate(err) => write!(f, "Could not load the TLS certificate: {}", err),
+            TlsError::NoCertificates(path) => {
+                write!(f, "Could not load the TLS certificate: no certificates found in {}", path.display())
+            }
+            TlsError::Key(err) => write!(f, "Could not load the TLS private key: {}", err),
+            TlsError::Config(err) => write!(f, "Invalid TLS certificate or key: {}", err),
+        }
+    }
+}
+impl error::Error for TlsError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            TlsError::Certificate(err) | TlsError::Key(err) => Some(err),
+            TlsError::NoCertificates(_) => None,
+            TlsError::Config(err) => Some(err),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::thread;
+    use std::net::TcpListener;
+    use rustls::{ClientConfig, ClientConnection, RootCertStore};
+    use rustls::pki_types::ServerName;
+    use crate::server::connection::Server;
+    use crate::server::router::Router;
+    use crate::server::static_files::INDEX_PAGE;
+
+    // Write a self-signed certificate for localhost and its key into `dir`, returning the
+    // certificate so a client can be told to trust it.
+    fn self_signed(dir: &Path) -> CertificateDer<'static> {
+        let certified = rcgen::generate_simple_self_signed(vec![String::from("localhost")]).unwrap();
+        fs::write(dir.join("cert.pem"), certified.cert.pem()).unwrap();
+        fs::write(dir.join("key.pem"), certified.key_pair.serialize_pem()).unwrap();
+        certified.cert.der().clone()
+    }
+
+    #[test]
+    fn test_tls_handshake_serves_index() {
+        let dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let cert = self_signed(dir.path());
+        fs::write(dir.path().join(INDEX_PAGE), "secure hello").unwrap();
+        let config = load_tls_config(&dir.path().join("cert.pem"), &dir.path().join("key.pem"))
+            .expect("The certificate and key should load");
+
+        let listener = TcpListener::bind("127.0.0.1:0").expect("Failed to bind test listener");
+        let address = listener.local_addr().unwrap();
+        let root = dir.path().to_path_buf();
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().expect("Failed to accept test connection");
+            Server::new(root, Router::new()).handle_connection(accept_tls(&config, stream).unwrap());
+        });
+
+        let mut roots = RootCertStore::empty();
+        roots.add(cert).unwrap();
+        let client_config = ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        let connection = ClientConnection::new(Arc::new(client_config), ServerName::try_from("localhost").unwrap()).unwrap();
+        let mut client = StreamOwned::new(connection, TcpStream::connect(address).unwrap());
+
+        client.write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n").unwrap();
+        let mut response = String::new();
+        client.read_to_string(&mut response).expect("The server should close the TLS session cleanly");
+        server.join().expect("Server thread panicked");
+
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.ends_with("\r\n\r\nsecure hello"));
+    }
+
+    #[test]
+    fn test_load_tls_config_missing_files() {
+        let dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let err = load_tls_config(&dir.path().join("cert.pem"), &dir.path().join("key.pem")).unwrap_err();
+        assert!(matches!(err, TlsError::Certificate(_)));
+        assert!(error::Error::source(&err).is_some());
+
+        self_signed(dir.path());
+        fs::write(dir.path().join("key.pem"), "not a key").unwrap();
+        let err = load_tls_config(&dir.path().join("cert.pem"), &dir.path().join("key.pem")).unwrap_err();
+        assert!(matches!(err, TlsError::Key(_)));
+    }
+}
@@ -0,0 +1,90 @@
+use std::io;
+use std::io::prelude::*;
+use std::time::Duration;
+use std::net::{SocketAddr, TcpStream};
+
+/// A connection to a client that requests are read from and responses written to.
+///
+/// This is `Read + Write` plus the handful of socket operations the server needs. They all
+/// default to doing nothing, so anything that can be read from and written to (like an
+/// in-memory buffer in a test) can be served with an empty `impl`.
+pub trait Transport: Read + Write {
+    /// Set how long a read may block before giving up with a timeout error.
+    fn set_read_timeout(&self, _timeout: Option<Duration>) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Set how long a write may block before giving up with a timeout error.
+    fn set_write_timeout(&self, _timeout: Option<Duration>) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Turn Nagle's algorithm off (or back on), so small writes go out straight away.
+    fn set_nodelay(&self, _nodelay: bool) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// The address of the client on the other end, if there is one.
+    fn peer_addr(&self) -> Option<SocketAddr> {
+        None
+    }
+
+    /// The address of our end of the connection, if there is one.
+    fn local_addr(&self) -> Option<SocketAddr> {
+        None
+    }
+
+    /// Cleanly finish the connection after the last response has been written.
+    fn close(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// So a caller can keep hold of the stream and just lend it out to be served.
+impl<T: Transport + ?Sized> Transport for &mut T {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        (**self).set_read_timeout(timeout)
+    }
+
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        (**self).set_write_timeout(timeout)
+    }
+
+    fn set_nodelay(&self, nodelay: bool) -> io::Result<()> {
+        (**self).set_nodelay(nodelay)
+    }
+
+    fn peer_addr(&self) -> Option<SocketAddr> {
+        (**self).peer_addr()
+    }
+
+    fn local_addr(&self) -> Option<SocketAddr> {
+        (**self).local_addr()
+    }
+
+    fn close(&mut self) -> io::Result<()> {
+        (**self).close()
+    }
+}
+
+impl Transport for TcpStream {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        TcpStream::set_read_timeout(self, timeout)
+    }
+
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        TcpStream::set_write_timeout(self, timeout)
+    }
+
+    fn set_nodelay(&self, nodelay: bool) -> io::Result<()> {
+        TcpStream::set_nodelay(self, nodelay)
+    }
+
+    fn peer_addr(&self) -> Option<SocketAddr> {
+        TcpStream::peer_addr(self).ok()
+    }
+
+    fn local_addr(&self) -> Option<SocketAddr> {
+        TcpStream::local_addr(self).ok()
+    }
+}
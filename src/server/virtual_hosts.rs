@@ -0,0 +1,84 @@
+use std::path::{Path, PathBuf};
+use std::collections::HashMap;
+use crate::http::request::Request;
+
+/// The document root to serve each site from, picked by the request's `Host` header.
+///
+/// Hostnames are matched ignoring case and any port, so `Example.com:8080` is served
+/// from the root added for `example.com`. Requests for any other host (or with no `Host`
+/// at all) are served from the default root.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VirtualHosts {
+    default_root: PathBuf,
+    roots: HashMap<String, PathBuf>,
+}
+impl VirtualHosts {
+    /// Serve every host from `default_root` until others are added.
+    pub fn new(default_root: impl Into<PathBuf>) -> VirtualHosts {
+        VirtualHosts {
+            default_root: default_root.into(),
+            roots: HashMap::new(),
+        }
+    }
+
+    /// Serve requests for `host` from `root`.
+    pub fn insert(&mut self, host: &str, root: impl Into<PathBuf>) {
+        self.roots.insert(normalize_host(host), root.into());
+    }
+
+    /// The root to serve the request from.
+    pub fn root_for(&self, request: &Request) -> &Path {
+        request
+            .header("host")
+            .and_then(|host| self.roots.get(&normalize_host(host)))
+            .unwrap_or(&self.default_root)
+    }
+}
+
+// Lowercase the hostname and drop the port and any trailing dot, e.g. `Example.com.:80`
+// becomes `example.com`. IPv6 addresses keep their brackets.
+fn normalize_host(host: &str) -> String {
+    let host = host.trim();
+    let host = match host.rfind(':') {
+        Some(colon) if !host[colon..].contains(']') && host[colon + 1..].bytes().all(|byte| byte.is_ascii_digit()) => {
+            &host[..colon]
+        }
+        _ => host,
+    };
+    host.trim_end_matches('.').to_ascii_lowercase()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request_for(host: Option<&str>) -> Request {
+        let mut request = Request::default();
+        if let Some(host) = host {
+            request.headers.insert(String::from("host"), String::from(host));
+        }
+        request
+    }
+
+    #[test]
+    fn test_root_for_host() {
+        let mut hosts = VirtualHosts::new("/srv/default");
+        hosts.insert("example.com", "/srv/example");
+        hosts.insert("Blog.Example.com", "/srv/blog");
+
+        assert_eq!(hosts.root_for(&request_for(Some("example.com"))), Path::new("/srv/example"));
+        assert_eq!(hosts.root_for(&request_for(Some("EXAMPLE.com:8080"))), Path::new("/srv/example"));
+        assert_eq!(hosts.root_for(&request_for(Some("blog.example.com."))), Path::new("/srv/blog"));
+        assert_eq!(hosts.root_for(&request_for(Some("other.example.com"))), Path::new("/srv/default"));
+        assert_eq!(hosts.root_for(&request_for(None)), Path::new("/srv/default"));
+    }
+
+    #[test]
+    fn test_normalize_host() {
+        assert_eq!(normalize_host("Example.com:80"), "example.com");
+        assert_eq!(normalize_host("[::1]:8080"), "[::1]");
+        assert_eq!(normalize_host("[::1]"), "[::1]");
+        assert_eq!(normalize_host("localhost"), "localhost");
+    }
+}
@@ -1,68 +1,610 @@
+use std::io;
+use std::any::Any;
 use std::fmt;
+use std::error;
+use std::mem;
+use std::panic;
 use std::thread;
 use std::sync::mpsc;
 use std::sync::Arc;
-use std::sync::Mutex;
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use std::sync::Condvar;
+use std::marker::PhantomData;
+use std::time::{Duration, Instant};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use crossbeam_channel::{Receiver, RecvTimeoutError, Sender};
 
 pub struct ThreadPool {
-    workers: Vec<Worker>,
-    sender: mpsc::Sender<Message>
+    // Behind a lock so a pool with an idle timeout can spawn workers as jobs come in.
+    workers: Mutex<Vec<Worker>>,
+    sender: Sender<Message>,
+    reciever: Receiver<Message>,
+    state: Arc<PoolState>,
+    // The id to give the next worker we spawn, so ids stay unique as the pool is resized.
+    next_id: AtomicUsize,
+    // Set once the workers have been shut down, so Drop doesn't try to do it again.
+    is_shut_down: bool,
+    // Where workers report going busy and idle, if anyone asked.
+    status: Option<mpsc::Sender<WorkerStatus>>,
 }
 impl ThreadPool {
     /// Create a new ThreadPool.
     ///
     /// The size is the number of threads in the pool.
     ///
-    /// # Panics
+    /// # Errors
     ///
-    /// The `new` function will panic if the size is less than or equal to zero.
+    /// Returns `PoolCreationError::ZeroSize` if the size is zero, or `SpawnFailed` if
+    /// one of the worker threads couldn't be started.
     pub fn new(size: usize) -> Result<ThreadPool, PoolCreationError> {
-        if size <= 0 {
-            return Err(PoolCreationError)
-        }
-
         // We are going to use channels to send a job from the threadpool
         // to the worker threads.
-        let (sender, reciever) = mpsc::channel();
+        let (sender, reciever) = crossbeam_channel::unbounded();
+
+        ThreadPool::with_channel(size, sender, reciever, None, None)
+    }
+
+    /// Create a new ThreadPool that grows and shrinks between `min` and `max` workers.
+    ///
+    /// The pool starts with `min` workers and spawns another whenever a job is queued
+    /// while they're all busy, up to `max`. A worker that goes `timeout` without picking
+    /// up a job terminates, unless the pool is already down to `min`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `PoolCreationError::ZeroSize` if `min` is zero, `MinAboveMax` if `min` is
+    /// bigger than `max`, or `SpawnFailed` if one of the workers couldn't be started.
+    pub fn with_idle_timeout(min: usize, max: usize, timeout: Duration) -> Result<ThreadPool, PoolCreationError> {
+        if min > max {
+            return Err(PoolCreationError::MinAboveMax { min, max });
+        }
+        let (sender, reciever) = crossbeam_channel::unbounded();
+
+        ThreadPool::with_channel(min, sender, reciever, None, Some(IdlePolicy { min, max, timeout }))
+    }
+
+    /// Create a new ThreadPool whose workers report on `status` whenever they pick up a
+    /// job and whenever they finish one.
+    ///
+    /// Each job produces a `WorkerStatus::Busy` just before it runs and a
+    /// `WorkerStatus::Idle` once it's done (even if it panicked), tagged with the
+    /// worker's id. Handy for watching how busy the pool is. If the receiver is dropped
+    /// the workers just stop reporting.
+    pub fn with_status_sender(size: usize, status: mpsc::Sender<WorkerStatus>) -> Result<ThreadPool, PoolCreationError> {
+        let (sender, reciever) = crossbeam_channel::unbounded();
+
+        ThreadPool::with_channel(size, sender, reciever, Some(status), None)
+    }
+
+    /// Create a new ThreadPool that queues at most `capacity` jobs.
+    ///
+    /// Once `capacity` jobs are waiting for a worker `execute` blocks until there is
+    /// room, and `try_execute` hands the job straight back. A capacity of zero means
+    /// jobs are only accepted when a worker is ready to pick them up immediately.
+    pub fn with_queue_capacity(size: usize, capacity: usize) -> Result<ThreadPool, PoolCreationError> {
+        let (sender, reciever) = crossbeam_channel::bounded(capacity);
+
+        ThreadPool::with_channel(size, sender, reciever, None, None)
+    }
+
+    fn with_channel(
+        size: usize,
+        sender: Sender<Message>,
+        reciever: Receiver<Message>,
+        status: Option<mpsc::Sender<WorkerStatus>>,
+        idle: Option<IdlePolicy>,
+    ) -> Result<ThreadPool, PoolCreationError> {
+        if size == 0 {
+            return Err(PoolCreationError::ZeroSize)
+        }
 
-        // We are going to share the reciever amongst multiple threads so
-        // wrap it in an Atomic Reference Counter and Mutex combo.
-        let reciever = Arc::new(Mutex::new(reciever));
+        // The counters every worker updates as it runs jobs.
+        let state = Arc::new(PoolState { idle, ..PoolState::default() });
 
-        let mut workers = Vec::with_capacity(size);
+        let pool = ThreadPool {
+            workers: Mutex::new(Vec::with_capacity(size)),
+            sender,
+            reciever,
+            state,
+            next_id: AtomicUsize::new(0),
+            is_shut_down: false,
+            status,
+        };
 
-        for id in 0..size {
-            workers.push(Worker::new(id, Arc::clone(&reciever)));
+        // If a worker fails to spawn, dropping the pool shuts down the ones that didn't.
+        for _ in 0..size {
+            pool.spawn_worker()?;
         }
 
-        return Ok(ThreadPool { workers, sender })
+        Ok(pool)
+    }
+
+    // Start another worker pulling jobs off the queue.
+    fn spawn_worker(&self) -> Result<(), PoolCreationError> {
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        let worker = Worker::new(id, self.reciever.clone(), Arc::clone(&self.state), self.status.clone())
+            .map_err(PoolCreationError::SpawnFailed)?;
+        self.state.live.fetch_add(1, Ordering::SeqCst);
+        self.workers.lock().unwrap().push(worker);
+        Ok(())
+    }
+
+    // Called after queueing a job. If the pool has an idle timeout and every worker is
+    // already busy, spawn another one (up to the maximum) to pick the job up.
+    fn scale_up(&self) {
+        let Some(policy) = &self.state.idle else {
+            return;
+        };
+
+        let mut workers = self.workers.lock().unwrap();
+        // Clear out the workers that timed out while we're at it.
+        let retired: Vec<usize> = lock_state(&self.state.retired).drain(..).collect();
+        join_workers(&mut workers, &retired);
+
+        let live = self.state.live.load(Ordering::SeqCst);
+        let outstanding = *lock_state(&self.state.outstanding);
+        if live < policy.max && outstanding > live {
+            drop(workers);
+            // Without another worker the job still gets run by one of the others.
+            if let Err(err) = self.spawn_worker() {
+                eprintln!("Failed to spawn another worker: {}", err);
+            }
+        }
     }
 
     pub fn execute<F>(&self, f: F) where F: FnOnce() + Send + 'static, {
         let job = Box::new(f);
 
+        self.state.job_queued();
         self.sender.send(Message::NewJob(job)).unwrap();
+        self.scale_up();
     }
-    
-}
 
-impl Drop for ThreadPool {
-    fn drop(&mut self) {
+    /// Execute every job in `jobs`, in order.
+    ///
+    /// On a pool created with `with_queue_capacity` this blocks whenever the queue is
+    /// full, so a long (or endless) iterator is only pulled from as fast as the workers
+    /// can keep up.
+    pub fn execute_all<I, F>(&self, jobs: I) where I: IntoIterator<Item = F>, F: FnOnce() + Send + 'static, {
+        for job in jobs {
+            self.execute(job);
+        }
+    }
+
+    /// Execute a job without blocking, handing it back as `Err(f)` if the queue is full.
+    ///
+    /// Only pools created with `with_queue_capacity` ever fill up, so on an unbounded pool
+    /// this behaves just like `execute`.
+    pub fn try_execute<F>(&self, f: F) -> Result<(), F> where F: FnOnce() + Send + 'static, {
+        // Once the job is boxed up we can't get the original closure back out, so keep it
+        // in a slot that we can take it back out of if it couldn't be queued.
+        let slot = Arc::new(Mutex::new(Some(f)));
+        let job = {
+            let slot = Arc::clone(&slot);
+            Box::new(move || {
+                let f = slot.lock().unwrap().take();
+                if let Some(f) = f {
+                    f();
+                }
+            })
+        };
+
+        self.state.job_queued();
+        match self.sender.try_send(Message::NewJob(job)) {
+            Ok(()) => {
+                self.scale_up();
+                Ok(())
+            }
+            Err(err) => {
+                self.state.job_finished();
+                // Drop the rejected message so ours is the only reference to the slot.
+                drop(err);
+                let f = slot.lock().unwrap().take();
+                Err(f.expect("A job that was never queued can't have run"))
+            }
+        }
+    }
+
+    /// Execute a job on the pool and get back a receiver for its result.
+    ///
+    /// The returned `Receiver` yields the value once the job has run, so calling `recv`
+    /// on it blocks until the result is ready.
+    ///
+    /// # Panics
+    ///
+    /// If the job panics the result is never sent. The sending half of the channel is
+    /// dropped as the job unwinds, so `recv` returns a `RecvError` (disconnect) rather
+    /// than blocking forever.
+    pub fn execute_with_result<F, T>(&self, f: F) -> mpsc::Receiver<T>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let (sender, reciever) = mpsc::channel();
+        self.execute(move || {
+            // The caller may have dropped the receiver if it no longer cares about
+            // the result, which is fine.
+            let _ = sender.send(f());
+        });
+        reciever
+    }
+
+    /// Run `f` with a `Scope` whose jobs can borrow from the caller's stack, returning
+    /// once every job executed through it has finished.
+    ///
+    /// This works like `std::thread::scope`, but on the pool's workers: jobs don't need to
+    /// be `'static`, so read-only data can be shared by reference instead of behind an
+    /// `Arc`. Only the scope's own jobs are waited on, not everything queued on the pool.
+    /// Calling this from one of the pool's own jobs can deadlock if there isn't a spare
+    /// worker to run the scoped jobs.
+    ///
+    /// # Panics
+    ///
+    /// If `f` or any of the scope's jobs panicked, this panics too once all of the jobs
+    /// have finished.
+    pub fn scope<'env, F, T>(&self, f: F) -> T
+    where
+        F: for<'scope> FnOnce(&'scope Scope<'scope, 'env>) -> T,
+    {
+        let scope = Scope {
+            pool: self,
+            state: Arc::new(ScopeState::default()),
+            scope: PhantomData,
+            env: PhantomData,
+        };
+        // Even if `f` panics the jobs it started are still borrowing from the stack, so they
+        // have to finish before we unwind any further.
+        let result = panic::catch_unwind(panic::AssertUnwindSafe(|| f(&scope)));
+        scope.wait();
+        match result {
+            Err(payload) => panic::resume_unwind(payload),
+            Ok(_) if scope.state.panicked.load(Ordering::SeqCst) => panic!("A scoped job panicked"),
+            Ok(result) => result,
+        }
+    }
+
+    /// Block until every job that has been queued so far has finished running.
+    ///
+    /// Unlike dropping the pool, the workers keep running afterwards so the pool can
+    /// carry on being used. Jobs queued from other threads while we're waiting are
+    /// waited on too.
+    pub fn join(&self) {
+        let outstanding = lock_state(&self.state.outstanding);
+        let _outstanding = self
+            .state
+            .all_done
+            .wait_while(outstanding, |outstanding| *outstanding > 0)
+            .unwrap_or_else(PoisonError::into_inner);
+    }
+
+    /// The number of jobs that have panicked since the pool was created.
+    pub fn panic_count(&self) -> usize {
+        self.state.panics.load(Ordering::SeqCst)
+    }
+
+    /// The number of workers currently executing a job.
+    pub fn active_workers(&self) -> usize {
+        self.state.active.load(Ordering::SeqCst)
+    }
+
+    /// The number of workers in the pool, busy or idle.
+    pub fn size(&self) -> usize {
+        self.state.live.load(Ordering::SeqCst)
+    }
+
+    /// A handle for reading the pool's counters from elsewhere, e.g. from inside a job.
+    pub fn stats(&self) -> PoolStats {
+        PoolStats { state: Arc::clone(&self.state) }
+    }
+
+    /// How many jobs each worker has run (or started running), in worker order.
+    ///
+    /// Handy for checking that work is actually being spread across the pool.
+    pub fn per_worker_counts(&self) -> Vec<usize> {
+        self.workers
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|worker| worker.jobs.load(Ordering::SeqCst))
+            .collect()
+    }
+
+    /// Grow or shrink the pool to `new_size` workers.
+    ///
+    /// Growing spawns new workers that pick jobs off the same queue as the rest. Shrinking
+    /// asks the excess workers to terminate and waits for them to finish. The termination
+    /// requests queue up behind any jobs already waiting, so this blocks until those have
+    /// been picked up. A `new_size` of zero is an error and leaves the pool alone.
+    pub fn resize(&mut self, new_size: usize) -> Result<(), PoolCreationError> {
+        if new_size == 0 {
+            return Err(PoolCreationError::ZeroSize);
+        }
+
+        while self.size() < new_size {
+            self.spawn_worker()?;
+        }
+
+        let excess = self.size() - new_size;
+        if excess > 0 {
+            for _ in 0..excess {
+                self.sender.send(Message::Terminate).unwrap();
+            }
+
+            // Whichever workers happen to pick up the requests are the ones that go.
+            let exited = lock_state(&self.state.exited);
+            let mut exited = self
+                .state
+                .worker_exited
+                .wait_while(exited, |exited| exited.len() < excess)
+                .unwrap_or_else(PoisonError::into_inner);
+            let exited: Vec<usize> = exited.drain(..).collect();
+            join_workers(self.workers.get_mut().unwrap(), &exited);
+        }
+
+        Ok(())
+    }
+
+    /// Shut the pool down, letting the workers finish every job that's already queued and
+    /// then waiting for them all to exit.
+    ///
+    /// Dropping the pool does the same thing, this just makes it explicit.
+    pub fn shutdown(mut self) {
+        self.terminate_workers();
+    }
+
+    /// Shut the pool down like `shutdown`, but only wait up to `timeout` for the workers
+    /// to exit.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `ShutdownTimeout` listing the ids of any workers still busy when the
+    /// time ran out, e.g. because they're stuck in a job that never finishes. Those
+    /// workers are left to exit in the background whenever their job does.
+    pub fn shutdown_timeout(mut self, timeout: Duration) -> Result<(), ShutdownTimeout> {
+        let deadline = Instant::now() + timeout;
+        self.request_termination();
+
+        let workers = self.workers.get_mut().unwrap();
+        let exited = lock_state(&self.state.exited);
+        let (mut exited, _) = self
+            .state
+            .worker_exited
+            .wait_timeout_while(exited, deadline.saturating_duration_since(Instant::now()), |exited| {
+                exited.len() < workers.len()
+            })
+            .unwrap_or_else(PoisonError::into_inner);
+        let exited: Vec<usize> = exited.drain(..).collect();
+
+        let mut unfinished = Vec::new();
+        for mut worker in workers.drain(..) {
+            if exited.contains(&worker.id) {
+                worker.join();
+            } else {
+                eprintln!("Worker {} did not terminate in time.", worker.id);
+                unfinished.push(worker.id);
+            }
+        }
+
+        if unfinished.is_empty() {
+            Ok(())
+        } else {
+            unfinished.sort();
+            Err(ShutdownTimeout { unfinished })
+        }
+    }
+
+    // Ask every worker to terminate once the queue is drained, and wait for them to do so.
+    fn terminate_workers(&mut self) {
+        self.request_termination();
+
+        println!("Shutting down all workers.");
+        for worker in self.workers.get_mut().unwrap() {
+            println!("Shutting down worker {}", worker.id);
+            worker.join();
+        }
+    }
+
+    // Queue up a request for every worker to terminate, behind any jobs already waiting.
+    fn request_termination(&mut self) {
+        self.is_shut_down = true;
+
+        // Stop workers timing out from here on, so the ones left are exactly the ones
+        // that need a request.
+        let retired: Vec<usize> = {
+            let mut retired = lock_state(&self.state.retired);
+            self.state.closing.store(true, Ordering::SeqCst);
+            retired.drain(..).collect()
+        };
+        let workers = self.workers.get_mut().unwrap();
+        join_workers(workers, &retired);
+
         println!("Asking workers to terminate.");
-        for _ in &self.workers {
+        for _ in workers.iter() {
             self.sender.send(Message::Terminate).unwrap();
         }
+    }
+}
 
-        println!("Shutting down all workers.");
-        for worker in &mut self.workers {
+// Remove the workers with the given ids, which have already exited, and join them.
+fn join_workers(workers: &mut Vec<Worker>, ids: &[usize]) {
+    for id in ids {
+        if let Some(index) = workers.iter().position(|worker| worker.id == *id) {
+            let mut worker = workers.swap_remove(index);
             println!("Shutting down worker {}", worker.id);
-            
-            if let Some(thread) = worker.thread.take() {
-                thread.join().unwrap();
-            }
+            worker.join();
+        }
+    }
+}
+
+impl Drop for ThreadPool {
+    fn drop(&mut self) {
+        if !self.is_shut_down {
+            self.terminate_workers();
+        }
+    }
+}
+
+/// A cheap, cloneable view of a pool's counters that can outlive a borrow of the pool.
+#[derive(Clone)]
+pub struct PoolStats {
+    state: Arc<PoolState>,
+}
+impl PoolStats {
+    /// The number of workers currently executing a job.
+    pub fn active_workers(&self) -> usize {
+        self.state.active.load(Ordering::SeqCst)
+    }
+
+    /// The number of jobs waiting for a worker to pick them up.
+    pub fn queued_jobs(&self) -> usize {
+        let outstanding = *lock_state(&self.state.outstanding);
+        outstanding.saturating_sub(self.active_workers())
+    }
+
+    /// The number of jobs that have panicked since the pool was created.
+    pub fn panic_count(&self) -> usize {
+        self.state.panics.load(Ordering::SeqCst)
+    }
+}
+
+/// Executes jobs that borrow from outside the scope, see `ThreadPool::scope`.
+pub struct Scope<'scope, 'env: 'scope> {
+    pool: &'scope ThreadPool,
+    state: Arc<ScopeState>,
+    // Invariant over both lifetimes, the same as std::thread::Scope.
+    scope: PhantomData<&'scope mut &'scope ()>,
+    env: PhantomData<&'env mut &'env ()>,
+}
+impl<'scope> Scope<'scope, '_> {
+    /// Execute a job on the pool that may borrow anything that outlives the scope.
+    pub fn execute<F>(&'scope self, f: F) where F: FnOnce() + Send + 'scope, {
+        *lock_state(&self.state.pending) += 1;
+        let done = ScopedJobDone { state: Arc::clone(&self.state) };
+        let job: Box<dyn FnOnce() + Send + 'scope> = Box::new(move || {
+            // Dropped after `f` has run (or unwound), which is what `wait` is waiting for.
+            let _done = done;
+            f();
+        });
+        // SAFETY: `ThreadPool::scope` doesn't return until `wait` has seen every job
+        // finish, so nothing the job borrows goes away while it can still be used. Jobs
+        // are only ever dropped after running, the pool can't be dropped out from under a
+        // scope that's borrowing it.
+        let job: Job = unsafe { mem::transmute::<Box<dyn FnOnce() + Send + 'scope>, Job>(job) };
+        self.pool.execute(job);
+    }
 
+    // Block until every job executed in the scope has finished.
+    fn wait(&self) {
+        let pending = lock_state(&self.state.pending);
+        let _pending = self.state.all_done.wait_while(pending, |pending| *pending > 0).unwrap_or_else(PoisonError::into_inner);
+    }
+}
+
+// How many of a scope's jobs are still running, and whether any have panicked.
+#[derive(Default)]
+struct ScopeState {
+    pending: Mutex<usize>,
+    all_done: Condvar,
+    panicked: AtomicBool,
+}
+
+// Marks one of a scope's jobs as finished when it's dropped, noting whether it panicked.
+struct ScopedJobDone {
+    state: Arc<ScopeState>,
+}
+impl Drop for ScopedJobDone {
+    fn drop(&mut self) {
+        if thread::panicking() {
+            self.state.panicked.store(true, Ordering::SeqCst);
+        }
+        let mut pending = lock_state(&self.state.pending);
+        *pending -= 1;
+        if *pending == 0 {
+            self.state.all_done.notify_all();
+        }
+    }
+}
+
+// Counters shared between the pool and all of its workers.
+#[derive(Default)]
+struct PoolState {
+    // How many jobs have panicked.
+    panics: AtomicUsize,
+    // How many workers are in the middle of running a job.
+    active: AtomicUsize,
+    // How many jobs have been queued but haven't finished yet, and a Condvar to
+    // signal when that drops to zero.
+    outstanding: Mutex<usize>,
+    all_done: Condvar,
+    // The ids of workers that have terminated but not been joined yet, and a Condvar to
+    // signal when another one is added.
+    exited: Mutex<Vec<usize>>,
+    worker_exited: Condvar,
+    // How many workers are running, which is the pool's size.
+    live: AtomicUsize,
+    // When set, idle workers terminate themselves after a while.
+    idle: Option<IdlePolicy>,
+    // The ids of workers that timed out but haven't been joined yet, and whether the
+    // pool is shutting down so they should stop doing that.
+    retired: Mutex<Vec<usize>>,
+    closing: AtomicBool,
+}
+impl PoolState {
+    // Called just before a job is sent to the workers.
+    fn job_queued(&self) {
+        *lock_state(&self.outstanding) += 1;
+    }
+
+    // Called once a job has run (or failed to be queued), waking anyone in `join`
+    // if it was the last one.
+    fn job_finished(&self) {
+        let mut outstanding = lock_state(&self.outstanding);
+        *outstanding -= 1;
+        if *outstanding == 0 {
+            self.all_done.notify_all();
         }
     }
+
+    // Called by a worker that's been idle for too long. Returns whether it should
+    // terminate, which it does as long as the pool has more workers than it needs.
+    fn retire(&self, id: usize, min: usize) -> bool {
+        let mut retired = lock_state(&self.retired);
+        if self.closing.load(Ordering::SeqCst) || self.live.load(Ordering::SeqCst) <= min {
+            return false;
+        }
+        self.live.fetch_sub(1, Ordering::SeqCst);
+        retired.push(id);
+        true
+    }
+}
+
+// Lock one of the pool's shared counters. A thread that panicked while holding the lock
+// poisons it, and unwrapping that would panic every worker that touches the lock after
+// it, so instead we log a warning, clear the poison and carry on. None of the counters
+// are left half-updated by a panic, so what's inside is still good.
+fn lock_state<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|err| {
+        eprintln!("Warning: a thread panicked while holding one of the pool's locks, recovering.");
+        mutex.clear_poison();
+        err.into_inner()
+    })
+}
+
+// The bounds and timeout given to `with_idle_timeout`.
+struct IdlePolicy {
+    min: usize,
+    max: usize,
+    timeout: Duration,
+}
+
+/// What a worker is up to, as reported by a pool created with `with_status_sender`.
+/// Each carries the id of the worker it's about.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WorkerStatus {
+    // The worker has picked up a job and is about to run it.
+    Busy(usize),
+    // The worker has finished its job and is waiting for another.
+    Idle(usize),
 }
 
 // Define Job to be a box of memory with the same trait bounds as the execute function.
@@ -75,56 +617,157 @@ enum Message {
 struct Worker {
     id: usize,
     thread: Option<thread::JoinHandle<()>>,
+    // How many jobs this worker has picked up.
+    jobs: Arc<AtomicUsize>,
 }
 impl Worker {
-    fn new(id: usize, reciever: Arc<Mutex<mpsc::Receiver<Message>>>) -> Worker {
-        let thread = thread::spawn(move || loop {
-            // Retrieve the message by locking the reciever (preventing other threads from accessing)
-            // unwrap it to panic on any errors (an example may be a posioned mutex which happens
-            // if a thread panics before releasing the lock).
-            let message = reciever.lock() // We are using a Mutex here to ensure that only a single thread is waiting to recieve a job.
-                                          // Any other threads that hit the lock will wait till the lock is released before trying to call
-                                          // recv().
-                                  .expect("Thread is poisioned. Likely a panic occurred and the lock was not released")
-                                  .recv() // If we get the lock call recv to recieve the job from the channel.
-                                          // recv will block the thread execution until a message is sent (job is available).
-                                  .unwrap();
+    // Spawn the worker's thread, which fails if the OS won't give us another thread. The
+    // thread is named after the worker, e.g. `worker-3`, so it's easy to spot in stack
+    // traces and profilers.
+    fn new(
+        id: usize,
+        reciever: Receiver<Message>,
+        state: Arc<PoolState>,
+        status: Option<mpsc::Sender<WorkerStatus>>,
+    ) -> io::Result<Worker> {
+        let jobs = Arc::new(AtomicUsize::new(0));
+        let worker_jobs = Arc::clone(&jobs);
+        let thread = thread::Builder::new().name(format!("worker-{}", id)).spawn(move || loop {
+            // Retrieve the next message. The channel hands each message to exactly one of
+            // the workers waiting on it without them all queueing up on a single lock.
+            // recv will block the thread execution until a message is sent (job is available),
+            // and only fails if the pool (and so the sender) is gone, in which case there's
+            // nothing left to do.
+            let message = match &state.idle {
+                // Give up waiting after a while if the pool can do without us.
+                Some(policy) => match reciever.recv_timeout(policy.timeout) {
+                    Ok(message) => message,
+                    Err(RecvTimeoutError::Timeout) if state.retire(id, policy.min) => {
+                        println!("Worker {} was idle for too long, terminating.", id);
+                        break;
+                    }
+                    Err(RecvTimeoutError::Timeout) => continue,
+                    Err(RecvTimeoutError::Disconnected) => break,
+                },
+                None => match reciever.recv() {
+                    Ok(message) => message,
+                    Err(_) => break,
+                },
+            };
             match message {
                 Message::NewJob(job) => {
                     println!("Worker {} got a job; executing...", id);
-                    job();
+                    worker_jobs.fetch_add(1, Ordering::SeqCst);
+                    state.active.fetch_add(1, Ordering::SeqCst);
+                    // Nobody listening for status updates isn't our problem.
+                    if let Some(status) = &status {
+                        let _ = status.send(WorkerStatus::Busy(id));
+                    }
+                    // Catch any panic so a bad job doesn't take the whole worker down with it.
+                    // The job is consumed either way so AssertUnwindSafe is fine here.
+                    if let Err(payload) = panic::catch_unwind(panic::AssertUnwindSafe(job)) {
+                        eprintln!("{}", panic_report(id, payload.as_ref()));
+                        state.panics.fetch_add(1, Ordering::SeqCst);
+                    }
+                    state.active.fetch_sub(1, Ordering::SeqCst);
+                    if let Some(status) = &status {
+                        let _ = status.send(WorkerStatus::Idle(id));
+                    }
+                    state.job_finished();
                 }
                 Message::Terminate => {
                     println!("Worker {} was asked to terminate.", id);
+                    state.live.fetch_sub(1, Ordering::SeqCst);
+                    lock_state(&state.exited).push(id);
+                    state.worker_exited.notify_all();
                     break;
                 }
             }
-        });
-        Worker {
+        })?;
+        Ok(Worker {
             id,
-            thread: Some(thread)
+            thread: Some(thread),
+            jobs,
+        })
+    }
+
+    // Wait for the worker's thread to exit. A thread that panicked is logged rather than
+    // passing the panic on, since we're usually shutting down at this point.
+    fn join(&mut self) {
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                eprintln!("Worker {} panicked while shutting down.", self.id);
+            }
         }
     }
 }
 
+// The log line for a job that panicked on worker `id`, with the panic's message if it
+// had one. `panic!` hands over a `&str` or a `String` depending on whether it was given
+// any arguments to format.
+fn panic_report(id: usize, payload: &(dyn Any + Send)) -> String {
+    let message = payload
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| payload.downcast_ref::<String>().map(String::as_str));
+    match message {
+        Some(message) => format!("Worker {} caught a panicking job ({}); continuing...", id, message),
+        None => format!("Worker {} caught a panicking job; continuing...", id),
+    }
+}
+
 
 // Errors:
 // Define our error types. These may be customized for our error handling cases.
 // Now we will be able to write our own errors, defer to an underlying error
 // implementation, or do something in between.
-#[derive(Debug, Clone)]
-pub struct PoolCreationError;
+#[derive(Debug)]
+pub enum PoolCreationError {
+    // The pool was asked to have no workers at all.
+    ZeroSize,
+    // The OS refused to spawn a worker thread.
+    SpawnFailed(io::Error),
+    // The pool was asked to keep more workers around than it's allowed to have.
+    MinAboveMax { min: usize, max: usize },
+}
 impl fmt::Display for PoolCreationError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "Could not create ThreadPool, the size must be greater than zero!")
+        match self {
+            PoolCreationError::ZeroSize => write!(f, "Could not create ThreadPool, the size must be greater than zero!"),
+            PoolCreationError::SpawnFailed(err) => write!(f, "Could not create ThreadPool, failed to spawn a worker: {}", err),
+            PoolCreationError::MinAboveMax { min, max } => {
+                write!(f, "Could not create ThreadPool, the minimum size {} is more than the maximum {}!", min, max)
+            }
+        }
+    }
+}
+impl error::Error for PoolCreationError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            PoolCreationError::ZeroSize | PoolCreationError::MinAboveMax { .. } => None,
+            PoolCreationError::SpawnFailed(err) => Some(err),
+        }
+    }
+}
+
+// Returned by `shutdown_timeout` when some of the workers didn't exit in time.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ShutdownTimeout {
+    // The ids of the workers that were still running.
+    pub unfinished: Vec<usize>,
+}
+impl fmt::Display for ShutdownTimeout {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let ids: Vec<String> = self.unfinished.iter().map(usize::to_string).collect();
+        write!(f, "Timed out waiting for workers to terminate: {}", ids.join(", "))
     }
 }
+impl error::Error for ShutdownTimeout {}
 
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::time::{Duration};
 
     #[test]
     fn test_acceptable_threadpool_creation() {
@@ -136,6 +779,18 @@ mod tests {
     #[test]
     fn test_threadpool_creation_zero_thread_count() {
         assert!(ThreadPool::new(0).is_err());
+        assert!(matches!(ThreadPool::new(0), Err(PoolCreationError::ZeroSize)));
+        assert!(matches!(ThreadPool::with_queue_capacity(0, 4), Err(PoolCreationError::ZeroSize)));
+    }
+
+    #[test]
+    fn test_pool_creation_error_display() {
+        let zero = PoolCreationError::ZeroSize;
+        let spawn = PoolCreationError::SpawnFailed(io::Error::other("out of threads"));
+        assert_ne!(zero.to_string(), spawn.to_string());
+        assert!(spawn.to_string().contains("out of threads"));
+        assert!(error::Error::source(&zero).is_none());
+        assert!(error::Error::source(&spawn).is_some());
     }
 
 
@@ -192,4 +847,528 @@ mod tests {
             }
         }
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_threadpool_drop_finishes_queued_jobs() {
+        // Queue up more jobs than there are workers and drop the pool straight away.
+        // The Terminate messages are queued behind the jobs so every job should still run.
+        let tp = ThreadPool::new(2).expect("Failed to create threads.");
+        let (tx, rx) = mpsc::channel();
+        for i in 0..10 {
+            let tx = tx.clone();
+            tp.execute(move || {
+                thread::sleep(Duration::from_millis(10));
+                tx.send(i).expect("Channel transmitter should've sent a value.");
+            });
+        }
+        drop(tx);
+        drop(tp);
+        assert_eq!(rx.try_iter().count(), 10);
+    }
+
+    #[test]
+    fn test_threadpool_execute_with_result_parallel_sum() {
+        let tp = ThreadPool::new(4).expect("Failed to create threads.");
+        // Split 1..=1000 into 10 ranges and sum each on the pool.
+        let results: Vec<mpsc::Receiver<u64>> = (0..10)
+            .map(|chunk| tp.execute_with_result(move || (chunk * 100 + 1..=chunk * 100 + 100).sum()))
+            .collect();
+        let total: u64 = results.iter().map(|result| result.recv().expect("Job should send its result")).sum();
+        assert_eq!(total, (1..=1000).sum());
+    }
+
+    #[test]
+    fn test_threadpool_execute_with_result_panic_disconnects() {
+        let tp = ThreadPool::new(2).expect("Failed to create threads.");
+        let result = tp.execute_with_result(|| -> u64 { panic!("job failed") });
+        assert!(result.recv().is_err());
+    }
+
+    #[test]
+    fn test_threadpool_survives_panicking_job() {
+        // A single worker so the normal job has to run on the same worker that panicked.
+        let tp = ThreadPool::new(1).expect("Failed to create threads.");
+        tp.execute(|| panic!("job failed"));
+        let result = tp.execute_with_result(|| 42);
+        assert_eq!(result.recv(), Ok(42));
+        assert_eq!(tp.panic_count(), 1);
+    }
+
+    #[test]
+    fn test_panic_report() {
+        let formatted = panic::catch_unwind(|| panic!("job {} failed", 7)).unwrap_err();
+        assert_eq!(panic_report(3, formatted.as_ref()), "Worker 3 caught a panicking job (job 7 failed); continuing...");
+        let literal = panic::catch_unwind(|| panic!("job failed")).unwrap_err();
+        assert_eq!(panic_report(0, literal.as_ref()), "Worker 0 caught a panicking job (job failed); continuing...");
+        let other = panic::catch_unwind(|| panic::panic_any(42)).unwrap_err();
+        assert_eq!(panic_report(0, other.as_ref()), "Worker 0 caught a panicking job; continuing...");
+    }
+
+    #[test]
+    fn test_threadpool_active_workers() {
+        let tp = ThreadPool::new(3).expect("Failed to create threads.");
+        assert_eq!(tp.size(), 3);
+        assert_eq!(tp.active_workers(), 0);
+
+        let (started_tx, started_rx) = mpsc::channel();
+        let (finish_tx, finish_rx) = mpsc::channel::<()>();
+        let done = tp.execute_with_result(move || {
+            started_tx.send(()).unwrap();
+            // Stay busy until the test lets us go.
+            finish_rx.recv().unwrap();
+        });
+
+        started_rx.recv().unwrap();
+        assert_eq!(tp.active_workers(), 1);
+
+        finish_tx.send(()).unwrap();
+        done.recv().unwrap();
+        // The result is sent just before the job returns, so give the worker a moment.
+        thread::sleep(Duration::from_millis(50));
+        assert_eq!(tp.active_workers(), 0);
+    }
+
+    #[test]
+    fn test_threadpool_try_execute_rejects_when_full() {
+        // One worker and room for one queued job.
+        let tp = ThreadPool::with_queue_capacity(1, 1).expect("Failed to create threads.");
+
+        // Keep the only worker busy.
+        let (started_tx, started_rx) = mpsc::channel();
+        let (finish_tx, finish_rx) = mpsc::channel::<()>();
+        tp.execute(move || {
+            started_tx.send(()).unwrap();
+            finish_rx.recv().unwrap();
+        });
+        started_rx.recv().unwrap();
+
+        // The first job fills the queue, the second gets handed back.
+        let (tx, rx) = mpsc::channel();
+        let queued_tx = tx.clone();
+        assert!(tp.try_execute(move || queued_tx.send("queued").unwrap()).is_ok());
+        let rejected = tp.try_execute(move || tx.send("rejected").unwrap()).expect_err("The queue should be full");
+
+        // We still own the rejected job so we can run it ourselves.
+        rejected();
+        assert_eq!(rx.recv(), Ok("rejected"));
+
+        finish_tx.send(()).unwrap();
+        assert_eq!(rx.recv(), Ok("queued"));
+    }
+
+    #[test]
+    fn test_threadpool_try_execute_unbounded() {
+        let tp = ThreadPool::new(1).expect("Failed to create threads.");
+        let (tx, rx) = mpsc::channel();
+        for i in 0..10 {
+            let tx = tx.clone();
+            assert!(tp.try_execute(move || tx.send(i).unwrap()).is_ok());
+        }
+        drop(tx);
+        assert_eq!(rx.iter().count(), 10);
+    }
+
+    #[test]
+    fn test_threadpool_execute_all() {
+        let tp = ThreadPool::new(4).expect("Failed to create threads.");
+        let counter = Arc::new(AtomicUsize::new(0));
+        tp.execute_all((0..50).map(|_| {
+            let counter = Arc::clone(&counter);
+            move || {
+                counter.fetch_add(1, Ordering::SeqCst);
+            }
+        }));
+        tp.join();
+        assert_eq!(counter.load(Ordering::SeqCst), 50);
+    }
+
+    #[test]
+    fn test_threadpool_execute_all_bounded() {
+        // Only one job can wait at a time, so submitting the batch has to keep pace with
+        // the worker rather than queueing everything up front.
+        let tp = ThreadPool::with_queue_capacity(1, 1).expect("Failed to create threads.");
+        let (tx, rx) = mpsc::channel();
+        tp.execute_all((0..50).map(|i| {
+            let tx = tx.clone();
+            move || tx.send(i).unwrap()
+        }));
+        drop(tx);
+        let mut results: Vec<i32> = rx.iter().collect();
+        results.sort();
+        assert_eq!(results, (0..50).collect::<Vec<i32>>());
+    }
+
+    #[test]
+    fn test_threadpool_scope_borrows_stack_data() {
+        let tp = ThreadPool::new(4).expect("Failed to create threads.");
+        let numbers: Vec<u64> = (1..=1000).collect();
+        let total = AtomicUsize::new(0);
+        tp.scope(|scope| {
+            for chunk in numbers.chunks(100) {
+                let total = &total;
+                scope.execute(move || {
+                    total.fetch_add(chunk.iter().sum::<u64>() as usize, Ordering::SeqCst);
+                });
+            }
+        });
+        // Every job has finished by the time the scope returns.
+        assert_eq!(total.into_inner(), 500500);
+    }
+
+    #[test]
+    fn test_threadpool_scope_panics_after_jobs_finish() {
+        let tp = ThreadPool::new(2).expect("Failed to create threads.");
+        let finished = AtomicUsize::new(0);
+        let result = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+            tp.scope(|scope| {
+                scope.execute(|| panic!("Oh no!"));
+                scope.execute(|| {
+                    thread::sleep(Duration::from_millis(50));
+                    finished.fetch_add(1, Ordering::SeqCst);
+                });
+            })
+        }));
+        assert!(result.is_err());
+        assert_eq!(finished.load(Ordering::SeqCst), 1);
+        // The worker counts the panic just after the scope sees the job finish.
+        tp.join();
+        assert_eq!(tp.panic_count(), 1);
+    }
+
+    #[test]
+    fn test_threadpool_join_waits_for_all_jobs() {
+        let tp = ThreadPool::new(4).expect("Failed to create threads.");
+        let counter = Arc::new(AtomicUsize::new(0));
+        for _ in 0..100 {
+            let counter = Arc::clone(&counter);
+            tp.execute(move || {
+                thread::sleep(Duration::from_millis(1));
+                counter.fetch_add(1, Ordering::SeqCst);
+            });
+        }
+        tp.join();
+        assert_eq!(counter.load(Ordering::SeqCst), 100);
+
+        // The pool is still usable after joining.
+        let counter_clone = Arc::clone(&counter);
+        tp.execute(move || {
+            counter_clone.fetch_add(1, Ordering::SeqCst);
+        });
+        tp.join();
+        assert_eq!(counter.load(Ordering::SeqCst), 101);
+    }
+
+    #[test]
+    fn test_threadpool_recovers_from_poisoned_lock() {
+        let tp = ThreadPool::new(2).expect("Failed to create threads.");
+        let state = Arc::clone(&tp.state);
+        let poisoner = thread::spawn(move || {
+            let _outstanding = state.outstanding.lock().unwrap();
+            panic!("Poisoning the lock");
+        });
+        assert!(poisoner.join().is_err());
+        assert!(tp.state.outstanding.is_poisoned());
+
+        // Every worker still takes jobs and counts them off as they finish.
+        let counter = Arc::new(AtomicUsize::new(0));
+        for _ in 0..10 {
+            let counter = Arc::clone(&counter);
+            tp.execute(move || {
+                counter.fetch_add(1, Ordering::SeqCst);
+            });
+        }
+        tp.join();
+        assert_eq!(counter.load(Ordering::SeqCst), 10);
+        assert!(!tp.state.outstanding.is_poisoned());
+    }
+
+    #[test]
+    fn test_threadpool_join_with_nothing_queued() {
+        let tp = ThreadPool::new(1).expect("Failed to create threads.");
+        tp.join();
+    }
+
+    // Run `jobs` jobs on the pool and check they all get done.
+    fn assert_runs_jobs(tp: &ThreadPool, jobs: usize) {
+        let (tx, rx) = mpsc::channel();
+        for i in 0..jobs {
+            let tx = tx.clone();
+            tp.execute(move || tx.send(i).unwrap());
+        }
+        drop(tx);
+        assert_eq!(rx.iter().count(), jobs);
+    }
+
+    #[test]
+    fn test_threadpool_resize_grow_and_shrink() {
+        let mut tp = ThreadPool::new(2).expect("Failed to create threads");
+        tp.resize(5).expect("Should be able to grow the pool");
+        assert_eq!(tp.size(), 5);
+        assert_runs_jobs(&tp, 20);
+
+        // Every new worker should be able to run a job at the same time.
+        let (tx, rx) = mpsc::channel();
+        for _ in 0..5 {
+            let tx = tx.clone();
+            tp.execute(move || {
+                thread::sleep(Duration::from_millis(200));
+                tx.send(()).unwrap();
+            });
+        }
+        drop(tx);
+        thread::sleep(Duration::from_millis(100));
+        assert_eq!(tp.active_workers(), 5);
+        assert_eq!(rx.iter().count(), 5);
+
+        tp.resize(2).expect("Should be able to shrink the pool");
+        assert_eq!(tp.size(), 2);
+        assert_runs_jobs(&tp, 20);
+    }
+
+    #[test]
+    fn test_threadpool_resize_to_zero() {
+        let mut tp = ThreadPool::new(2).expect("Failed to create threads");
+        assert!(tp.resize(0).is_err());
+        assert_eq!(tp.size(), 2);
+    }
+
+    #[test]
+    fn test_threadpool_per_worker_counts() {
+        let tp = ThreadPool::new(4).expect("Failed to create threads");
+        assert_eq!(tp.per_worker_counts(), vec![0; 4]);
+
+        for _ in 0..200 {
+            tp.execute(|| thread::sleep(Duration::from_micros(100)));
+        }
+        tp.join();
+
+        let counts = tp.per_worker_counts();
+        assert_eq!(counts.len(), 4);
+        assert_eq!(counts.iter().sum::<usize>(), 200);
+    }
+
+    #[test]
+    fn test_threadpool_stats() {
+        let tp = ThreadPool::new(1).expect("Failed to create threads");
+        let stats = tp.stats();
+        let (tx, rx) = mpsc::channel::<()>();
+        let rx = Arc::new(Mutex::new(rx));
+        for _ in 0..3 {
+            let rx = Arc::clone(&rx);
+            tp.execute(move || rx.lock().unwrap().recv().unwrap());
+        }
+        thread::sleep(Duration::from_millis(100));
+        // One job is running and blocked, the other two are waiting behind it.
+        assert_eq!(stats.active_workers(), 1);
+        assert_eq!(stats.queued_jobs(), 2);
+
+        for _ in 0..3 {
+            tx.send(()).unwrap();
+        }
+        tp.join();
+        assert_eq!(stats.active_workers(), 0);
+        assert_eq!(stats.queued_jobs(), 0);
+    }
+
+    #[test]
+    fn test_threadpool_explicit_shutdown() {
+        let tp = ThreadPool::new(3).expect("Failed to create threads");
+        let (tx, rx) = mpsc::channel();
+        for i in 0..10 {
+            let tx = tx.clone();
+            tp.execute(move || {
+                thread::sleep(Duration::from_millis(10));
+                tx.send(i).unwrap();
+            });
+        }
+        drop(tx);
+
+        // Consumes the pool, and the drop afterwards mustn't try to terminate the workers again.
+        tp.shutdown();
+        assert_eq!(rx.try_iter().count(), 10);
+    }
+
+    #[test]
+    fn test_threadpool_runs_every_job_exactly_once() {
+        const SENDERS: usize = 4;
+        const JOBS: usize = 2_000;
+
+        let tp = Arc::new(ThreadPool::new(8).expect("Failed to create threads"));
+        let runs: Arc<Vec<AtomicUsize>> = Arc::new((0..SENDERS * JOBS).map(|_| AtomicUsize::new(0)).collect());
+
+        // Queue the jobs from several threads at once.
+        let senders: Vec<_> = (0..SENDERS)
+            .map(|sender| {
+                let tp = Arc::clone(&tp);
+                let runs = Arc::clone(&runs);
+                thread::spawn(move || {
+                    for job in 0..JOBS {
+                        let runs = Arc::clone(&runs);
+                        tp.execute(move || {
+                            runs[sender * JOBS + job].fetch_add(1, Ordering::SeqCst);
+                        });
+                    }
+                })
+            })
+            .collect();
+        for sender in senders {
+            sender.join().unwrap();
+        }
+        tp.join();
+
+        assert!(runs.iter().all(|runs| runs.load(Ordering::SeqCst) == 1));
+        assert_eq!(tp.per_worker_counts().iter().sum::<usize>(), SENDERS * JOBS);
+    }
+
+    #[test]
+    fn test_threadpool_shutdown_timeout() {
+        let tp = ThreadPool::new(2).expect("Failed to create threads");
+        let (tx, rx) = mpsc::channel();
+        tp.execute(move || {
+            tx.send(()).unwrap();
+            thread::sleep(Duration::from_secs(2));
+        });
+        // Make sure the slow job has been picked up before shutting down.
+        rx.recv().unwrap();
+
+        let start = Instant::now();
+        let err = tp.shutdown_timeout(Duration::from_millis(200)).expect_err("The slow job should time out");
+        assert!(start.elapsed() < Duration::from_secs(1));
+        assert_eq!(err.unfinished.len(), 1);
+        assert!(err.to_string().starts_with("Timed out waiting for workers to terminate: "));
+    }
+
+    #[test]
+    fn test_threadpool_shutdown_timeout_in_time() {
+        let tp = ThreadPool::new(3).expect("Failed to create threads");
+        for _ in 0..3 {
+            tp.execute(|| thread::sleep(Duration::from_millis(10)));
+        }
+        assert_eq!(tp.shutdown_timeout(Duration::from_secs(5)), Ok(()));
+    }
+
+    #[test]
+    fn test_threadpool_reports_worker_status() {
+        let (tx, rx) = mpsc::channel();
+        let tp = ThreadPool::with_status_sender(1, tx).expect("Failed to create threads");
+        tp.execute(|| {});
+        tp.execute(|| panic!("Status is still reported for jobs that panic"));
+        tp.join();
+        tp.shutdown();
+
+        let statuses: Vec<WorkerStatus> = rx.try_iter().collect();
+        assert_eq!(
+            statuses,
+            [WorkerStatus::Busy(0), WorkerStatus::Idle(0), WorkerStatus::Busy(0), WorkerStatus::Idle(0)]
+        );
+    }
+
+    #[test]
+    fn test_threadpool_status_per_worker() {
+        let (tx, rx) = mpsc::channel();
+        let tp = ThreadPool::with_status_sender(3, tx).expect("Failed to create threads");
+        for _ in 0..6 {
+            tp.execute(|| thread::sleep(Duration::from_millis(10)));
+        }
+        tp.join();
+
+        // Every worker goes busy and then idle again, one job at a time.
+        let statuses: Vec<WorkerStatus> = rx.try_iter().collect();
+        assert_eq!(statuses.len(), 12);
+        for id in 0..3 {
+            let own: Vec<&WorkerStatus> = statuses
+                .iter()
+                .filter(|status| matches!(status, WorkerStatus::Busy(worker) | WorkerStatus::Idle(worker) if *worker == id))
+                .collect();
+            for pair in own.chunks(2) {
+                assert_eq!(pair, [&WorkerStatus::Busy(id), &WorkerStatus::Idle(id)]);
+            }
+        }
+    }
+
+    #[test]
+    fn test_threadpool_idle_workers_scale_down() {
+        const TIMEOUT: Duration = Duration::from_millis(100);
+        let tp = ThreadPool::with_idle_timeout(1, 4, TIMEOUT).expect("Failed to create threads");
+        assert_eq!(tp.size(), 1);
+
+        // Keep more workers busy than the pool starts with so it has to grow.
+        let (finish_tx, finish_rx) = crossbeam_channel::unbounded::<()>();
+        for _ in 0..6 {
+            let finish_rx = finish_rx.clone();
+            tp.execute(move || finish_rx.recv().unwrap());
+        }
+        assert_eq!(tp.size(), 4);
+        for _ in 0..6 {
+            finish_tx.send(()).unwrap();
+        }
+        tp.join();
+
+        // Once they've been idle for long enough the extra workers go away.
+        let deadline = Instant::now() + TIMEOUT * 20;
+        while tp.size() > 1 && Instant::now() < deadline {
+            thread::sleep(TIMEOUT / 2);
+        }
+        assert_eq!(tp.size(), 1);
+
+        // And come back when there's work again.
+        let (started_tx, started_rx) = mpsc::channel();
+        for _ in 0..2 {
+            let started_tx = started_tx.clone();
+            let finish_rx = finish_rx.clone();
+            tp.execute(move || {
+                started_tx.send(()).unwrap();
+                finish_rx.recv().unwrap();
+            });
+        }
+        started_rx.recv().unwrap();
+        started_rx.recv().unwrap();
+        assert_eq!(tp.size(), 2);
+        finish_tx.send(()).unwrap();
+        finish_tx.send(()).unwrap();
+        tp.shutdown();
+    }
+
+    #[test]
+    fn test_threadpool_idle_timeout_bounds() {
+        assert!(matches!(ThreadPool::with_idle_timeout(0, 4, Duration::from_secs(1)), Err(PoolCreationError::ZeroSize)));
+        assert!(matches!(
+            ThreadPool::with_idle_timeout(4, 2, Duration::from_secs(1)),
+            Err(PoolCreationError::MinAboveMax { min: 4, max: 2 })
+        ));
+    }
+
+    #[test]
+    fn test_worker_threads_are_named() {
+        let mut tp = ThreadPool::new(2).expect("Failed to create threads");
+        let name = || String::from(thread::current().name().unwrap_or("unnamed"));
+
+        // Keep both workers busy at once so each one reports its own name.
+        let (started_tx, started_rx) = mpsc::channel();
+        let (finish_tx, finish_rx) = crossbeam_channel::unbounded::<()>();
+        let names: Vec<mpsc::Receiver<String>> = (0..2)
+            .map(|_| {
+                let started_tx = started_tx.clone();
+                let finish_rx = finish_rx.clone();
+                tp.execute_with_result(move || {
+                    started_tx.send(()).unwrap();
+                    finish_rx.recv().unwrap();
+                    name()
+                })
+            })
+            .collect();
+        started_rx.recv().unwrap();
+        started_rx.recv().unwrap();
+        finish_tx.send(()).unwrap();
+        finish_tx.send(()).unwrap();
+        let mut names: Vec<String> = names.iter().map(|name| name.recv().unwrap()).collect();
+        names.sort();
+        assert_eq!(names, ["worker-0", "worker-1"]);
+
+        // Workers added later carry on the numbering.
+        tp.resize(3).unwrap();
+        assert!(tp.workers.get_mut().unwrap().iter().any(|worker| {
+            worker.thread.as_ref().and_then(|thread| thread.thread().name()) == Some("worker-2")
+        }));
+    }
+}